@@ -0,0 +1,174 @@
+//! Backend process management for the serve system.
+
+use std::io;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use async_process::{Child, Command};
+use async_std::task::{sleep, spawn, JoinHandle};
+use futures::channel::oneshot;
+use futures::future::FutureExt;
+use indicatif::ProgressBar;
+
+use crate::common::SERVER;
+use crate::config::ConfigOptsBackendCommand;
+
+/// The initial delay before restarting a backend process which has exited.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// The maximum delay before restarting a backend process which has exited.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// A backend process which runs for at least this long is considered healthy, resetting the backoff.
+const HEALTHY_RUNTIME: Duration = Duration::from_secs(30);
+/// The number of consecutive restarts of a backend process which keeps exiting before it is
+/// considered healthy, after which it is no longer restarted.
+const MAX_RESTARTS: u32 = 5;
+
+/// A supervisor for a backend process which is run alongside the dev server.
+///
+/// The process is restarted with backoff whenever it exits, until it fails to spawn or keeps
+/// exiting early. It is killed when the serve system shuts down via its `BackendHandle`, and as it
+/// is spawned with `kill_on_drop`, it is also killed if the supervisor is dropped.
+pub struct BackendProcess {
+    /// The config of the backend command to run.
+    cfg: ConfigOptsBackendCommand,
+    /// The progress bar used for reporting on the backend process.
+    progress: ProgressBar,
+}
+
+/// A handle to a running backend process supervisor.
+pub struct BackendHandle {
+    /// A channel used to signal the supervisor to kill the backend process & stop.
+    shutdown: oneshot::Sender<()>,
+    /// The supervisor task.
+    task: JoinHandle<()>,
+}
+
+impl BackendHandle {
+    /// Kill the backend process, if it is running, and wait for it to exit.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        self.task.await;
+    }
+}
+
+/// The outcome of waiting on a backend process.
+enum Outcome {
+    /// The process exited by itself.
+    Exited(io::Result<ExitStatus>),
+    /// The serve system is shutting down.
+    Shutdown,
+}
+
+impl BackendProcess {
+    /// Create a new instance.
+    pub fn new(cfg: ConfigOptsBackendCommand, progress: ProgressBar) -> Self {
+        Self { cfg, progress }
+    }
+
+    /// Spawn the backend process, restarting it with backoff whenever it exits.
+    pub fn spawn(self) -> BackendHandle {
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        BackendHandle {
+            shutdown,
+            task: spawn(self.run(shutdown_rx)),
+        }
+    }
+
+    async fn run(self, mut shutdown: oneshot::Receiver<()>) {
+        let mut backoff = MIN_BACKOFF;
+        let mut restarts = 0;
+        loop {
+            self.progress.println(format!("{} starting backend command `{}`", SERVER, &self.cfg.command));
+            let mut child = match self.spawn_child() {
+                Ok(child) => child,
+                Err(err) => {
+                    // NOTE WELL: we use debug formatting here to ensure the error chain is displayed.
+                    self.progress.println(format!("{:?}", err));
+                    return;
+                }
+            };
+            let started = Instant::now();
+            let outcome = futures::select! {
+                status = child.status().fuse() => Outcome::Exited(status),
+                _ = shutdown => Outcome::Shutdown,
+            };
+            match outcome {
+                Outcome::Exited(Ok(status)) => self.progress.println(format!("backend command exited with {}", status)),
+                Outcome::Exited(Err(err)) => {
+                    self.progress.println(format!("error waiting on backend command: {}", err));
+                    return;
+                }
+                Outcome::Shutdown => {
+                    let _ = child.kill();
+                    let _ = child.status().await;
+                    return;
+                }
+            }
+
+            // Restart with backoff, giving up on a process which keeps exiting early.
+            if started.elapsed() >= HEALTHY_RUNTIME {
+                backoff = MIN_BACKOFF;
+                restarts = 0;
+            }
+            if restarts >= MAX_RESTARTS {
+                self.progress.println(format!(
+                    "backend command exited {} times in quick succession; no longer restarting it",
+                    restarts + 1
+                ));
+                return;
+            }
+            restarts += 1;
+            self.progress
+                .println(format!("restarting backend command in {}s", backoff.as_secs()));
+            futures::select! {
+                _ = sleep(backoff).fuse() => (),
+                _ = shutdown => return,
+            }
+            backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+        }
+    }
+
+    fn spawn_child(&self) -> Result<Child> {
+        let mut cmd = Command::new(&self.cfg.command);
+        cmd.args(&self.cfg.args).envs(&self.cfg.env).kill_on_drop(true);
+        if let Some(cwd) = &self.cfg.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.spawn()
+            .with_context(|| format!("error spawning backend command `{}`", &self.cfg.command))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(command: &str, args: &[&str]) -> BackendProcess {
+        let cfg = ConfigOptsBackendCommand {
+            command: command.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            cwd: None,
+            env: Default::default(),
+        };
+        BackendProcess::new(cfg, ProgressBar::hidden())
+    }
+
+    #[async_std::test]
+    async fn spawn_error_stops_the_supervisor() {
+        let handle = backend("trunk-test-no-such-command", &[]).spawn();
+        async_std::future::timeout(Duration::from_secs(5), handle.task)
+            .await
+            .expect("the supervisor should stop once the command fails to spawn");
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn shutdown_kills_the_running_process() {
+        let handle = backend("sleep", &["60"]).spawn();
+        sleep(Duration::from_millis(100)).await;
+        async_std::future::timeout(Duration::from_secs(5), handle.shutdown())
+            .await
+            .expect("shutdown should kill the backend process & return");
+    }
+}
@@ -1,16 +1,24 @@
 //! Build system & asset pipelines.
 
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use async_std::fs;
-use futures::channel::mpsc::Sender;
+use async_std::task::sleep;
+use futures::channel::mpsc::{Sender, UnboundedSender};
 use indicatif::ProgressBar;
+use regex::Regex;
 
 use crate::common::{BUILDING, ERROR, SUCCESS};
-use crate::config::RtcBuild;
-use crate::pipelines::HtmlPipeline;
+use crate::config::{CargoMetadata, RtcBuild};
+use crate::pipelines::{scan_dist, AssetPlan, HtmlPipeline, HtmlPipelineOutput};
+
+/// The maximum time to wait for a concurrent build of the same dist dir to finish, when
+/// configured to wait at all.
+const LOCK_WAIT_TIMEOUT: Duration = Duration::from_secs(300);
 
 /// A system used for building a Rust WASM app & bundling its assets.
 ///
@@ -21,10 +29,15 @@ use crate::pipelines::HtmlPipeline;
 pub struct BuildSystem {
     /// Runtime config.
     cfg: Arc<RtcBuild>,
-    /// HTML build pipeline.
-    html_pipeline: Arc<HtmlPipeline>,
+    /// HTML build pipelines, the first of which builds the target into `index.html`, followed by
+    /// one for each additional target.
+    html_pipelines: Vec<Arc<HtmlPipeline>>,
     /// The build system progress bar for displaying the state of the build system overall.
     progress: ProgressBar,
+    /// All observers of build events, the first of which is always the progress bar.
+    observers: Vec<Arc<dyn BuildObserver>>,
+    /// The dir holding Trunk's own state for this build, outside of the dist dir.
+    state_dir: PathBuf,
 }
 
 impl BuildSystem {
@@ -33,46 +46,645 @@ impl BuildSystem {
     /// Reducing the number of assumptions here should help us to stay flexible when adding new
     /// commands, rafctoring and the like.
     pub async fn new(cfg: Arc<RtcBuild>, progress: ProgressBar, ignore_chan: Option<Sender<PathBuf>>) -> Result<Self> {
-        let html_pipeline = Arc::new(HtmlPipeline::new(cfg.clone(), progress.clone(), ignore_chan)?);
+        for warning in cfg.warnings.iter() {
+            progress.println(format!("warning: {}", warning));
+        }
+        let mut html_pipelines = vec![Arc::new(HtmlPipeline::new(
+            cfg.clone(),
+            &cfg.target,
+            String::from("index.html"),
+            progress.clone(),
+            ignore_chan.clone(),
+        )?)];
+        for target in cfg.targets.iter() {
+            let output_name = target
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .ok_or_else(|| anyhow!("could not determine the file name of target HTML file {:?}", target))?;
+            html_pipelines.push(Arc::new(HtmlPipeline::new(
+                cfg.clone(),
+                target,
+                output_name,
+                progress.clone(),
+                ignore_chan.clone(),
+            )?));
+        }
+        let observers: Vec<Arc<dyn BuildObserver>> = vec![Arc::new(progress.clone())];
+        let state_dir = state_dir(&cfg).await;
         Ok(Self {
             cfg,
-            html_pipeline,
+            html_pipelines,
             progress,
+            observers,
+            state_dir,
         })
     }
 
+    /// The dir holding Trunk's own state for this build, outside of the dist dir.
+    pub(crate) fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
+    /// Register an additional observer of build events.
+    pub fn add_observer(&mut self, observer: Arc<dyn BuildObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Emit the given event to all observers.
+    fn emit(&self, event: BuildEvent) {
+        self.observers.iter().for_each(|observer| observer.on_event(&event));
+    }
+
+    /// Describe the assets declared in the source HTML & their intended outputs, without building.
+    pub async fn plan(&self) -> Result<Vec<AssetPlan>> {
+        let mut plans = vec![];
+        for pipeline in self.html_pipelines.iter() {
+            plans.extend(pipeline.plan().await?);
+        }
+        Ok(plans)
+    }
+
     /// Build the application described in the given build data.
+    ///
+    /// With `dry_run` enabled, this only prints what the build would emit.
     pub async fn build(&mut self) -> Result<()> {
-        self.progress.reset();
-        self.progress.enable_steady_tick(100);
-        self.progress.set_prefix(&format!("{}", BUILDING));
-        self.progress.set_message("starting build");
+        if self.cfg.dry_run {
+            return self.dry_run().await;
+        }
+        self.emit(BuildEvent::BuildStarted);
         let res = self.do_build().await;
-        self.progress.disable_steady_tick();
-        self.progress.set_position(0);
-        match res {
-            Ok(_) => {
-                self.progress.set_prefix(&format!("{}", SUCCESS));
-                self.progress.finish_with_message("success");
-                Ok(())
-            }
-            Err(err) => {
-                self.progress.set_prefix(&format!("{}", ERROR));
-                self.progress.finish_with_message("error");
-                Err(err)
-            }
+        match &res {
+            Ok(_) => self.emit(BuildEvent::BuildFinished),
+            // NOTE WELL: we use debug formatting here to ensure the error chain is included.
+            Err(err) => self.emit(BuildEvent::BuildFailed(format!("{:?}", err))),
         }
+        res
+    }
+
+    /// Print the resolved dist dir & public URL along with the planned outputs of all assets,
+    /// without running cargo or writing to the dist dir.
+    async fn dry_run(&self) -> Result<()> {
+        let plans = self.plan().await?;
+        self.progress.println(format!("dry run: dist dir {:?}, public URL {:?}", &self.cfg.dist, &self.cfg.public_url));
+        for plan in plans {
+            self.progress.println(format!("  {}", plan));
+        }
+        self.progress.finish_and_clear();
+        Ok(())
     }
 
     async fn do_build(&mut self) -> Result<()> {
         // TODO: delete the contents of the `dist/.current` dir (currently in flight elsewhere).
 
-        // Ensure the output dist directory is in place.
+        // Ensure the output dist directory is in place, and that no other build is writing to it.
         fs::create_dir_all(self.cfg.dist.as_path()).await?;
+        fs::create_dir_all(self.cfg.assets_out_dir()).await?;
+        let _lock = BuildLock::acquire(&self.cfg.dist, &self.state_dir, self.cfg.lock_wait, &self.progress).await?;
+
+        // Spawn the source HTML pipelines. Each will spawn all other pipelines derived from its
+        // source HTML, and will ultimately generate and write its final HTML.
+        //
+        // Multiple targets are built one after another rather than concurrently. As all outputs
+        // are content hashed, assets shared by targets (such as the Rust app, for which the later
+        // cargo builds are no-ops) are emitted under the same names, and are only listed once.
+        self.emit(BuildEvent::StageStarted(BuildStage::Pipelines));
+        let mut output = HtmlPipelineOutput {
+            input_paths: vec![],
+            output_paths: vec![],
+            hashed_patterns: vec![],
+        };
+        for pipeline in self.html_pipelines.iter() {
+            let pipeline_output = pipeline.clone().spawn().await?;
+            for path in pipeline_output.input_paths {
+                if !output.input_paths.contains(&path) {
+                    output.input_paths.push(path);
+                }
+            }
+            for path in pipeline_output.output_paths {
+                if !output.output_paths.contains(&path) {
+                    output.output_paths.push(path);
+                }
+            }
+            output.hashed_patterns.extend(pipeline_output.hashed_patterns);
+        }
+        if !self.cfg.source_maps {
+            self.remove_source_maps().await?;
+            output
+                .output_paths
+                .retain(|path| path.extension().map(|ext| ext != "map").unwrap_or(true));
+        }
+        if self.cfg.prune_stale {
+            self.prune_stale(&output).await?;
+        }
+        if self.cfg.github_pages {
+            output.output_paths.extend(self.write_github_pages_files().await?);
+        }
+        output
+            .output_paths
+            .iter()
+            .for_each(|path| self.emit(BuildEvent::AssetEmitted(path.clone())));
+        self.emit(BuildEvent::StageFinished(BuildStage::Pipelines));
+
+        if let Some(deps_path) = &self.cfg.emit_deps {
+            self.emit(BuildEvent::StageStarted(BuildStage::EmitDeps));
+            write_deps_file(deps_path, &output).await?;
+            self.emit(BuildEvent::StageFinished(BuildStage::EmitDeps));
+        }
+
+        if self.cfg.release_size_report {
+            self.emit(BuildEvent::StageStarted(BuildStage::SizeReport));
+            self.size_report().await?;
+            self.emit(BuildEvent::StageFinished(BuildStage::SizeReport));
+        }
+        Ok(())
+    }
+
+    /// Write the files needed for hosting the dist dir on GitHub Pages, returning their paths.
+    ///
+    /// The `.nojekyll` file disables Jekyll processing, which would otherwise drop files &
+    /// dirs starting with `_`. The `404.html` copy of the output `index.html` is served by GitHub
+    /// Pages for unknown paths, which lets client-side routing handle them. As all asset URLs are
+    /// absolute paths under the public URL, the copy works at any depth; for project pages, the
+    /// public URL should be set to the repo's subpath (e.g. `/my-repo/`).
+    async fn write_github_pages_files(&self) -> Result<Vec<PathBuf>> {
+        let nojekyll = self.cfg.dist.join(".nojekyll");
+        fs::write(&nojekyll, "")
+            .await
+            .with_context(|| format!("error writing {:?}", &nojekyll))?;
+        let not_found = self.cfg.dist.join("404.html");
+        fs::copy(self.cfg.dist.join("index.html"), &not_found)
+            .await
+            .with_context(|| format!("error copying output index.html to {:?}", &not_found))?;
+        Ok(vec![nojekyll, not_found])
+    }
+
+    /// Remove all `.map` files from the dist dir.
+    ///
+    /// This covers maps copied in by asset pipelines, as well as any left behind by a previous
+    /// build which had source maps enabled.
+    async fn remove_source_maps(&self) -> Result<()> {
+        for asset in scan_dist(&self.cfg.dist).await?.into_iter().filter(|asset| asset.ext == "map") {
+            fs::remove_file(&asset.path)
+                .await
+                .with_context(|| format!("error removing source map {:?}", &asset.path))?;
+        }
+        Ok(())
+    }
+
+    /// Remove hashed files left in the dist dir by previous builds.
+    ///
+    /// Only files named like one of the hashed outputs of this build, but with a different hash,
+    /// are removed, such as an `app-<old hash>.css` next to the emitted `app-<new hash>.css`. All
+    /// files which are not named like Trunk's hashed outputs, including static copies, are kept.
+    async fn prune_stale(&self, output: &HtmlPipelineOutput) -> Result<()> {
+        if output.hashed_patterns.is_empty() {
+            return Ok(());
+        }
+        let patterns = output
+            .hashed_patterns
+            .iter()
+            .map(|pattern| pattern.split("<hash>").map(regex::escape).collect::<Vec<_>>().join("[0-9a-f]+"))
+            .collect::<Vec<_>>();
+        let stale_pattern = Regex::new(&format!("^(?:{})$", patterns.join("|"))).context("error building stale asset pattern")?;
+
+        let dist: PathBuf = fs::canonicalize(&self.cfg.dist).await?.into();
+        let current = output
+            .output_paths
+            .iter()
+            .filter_map(|path| path.strip_prefix(&self.cfg.dist).ok())
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<_>>();
+        for asset in scan_dist(&dist).await? {
+            let rel_path = match asset.path.strip_prefix(&dist) {
+                Ok(rel_path) => rel_path.to_string_lossy().replace('\\', "/"),
+                Err(_) => continue,
+            };
+            if stale_pattern.is_match(&rel_path) && !current.contains(&rel_path) {
+                fs::remove_file(&asset.path)
+                    .await
+                    .with_context(|| format!("error removing stale asset {:?}", &asset.path))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print a report of per-asset size changes compared to the previous build, then persist the
+    /// current asset sizes for the next build to compare against.
+    ///
+    /// The sizes are persisted within the state dir, so that they are never deployed along with
+    /// the dist dir.
+    async fn size_report(&self) -> Result<()> {
+        fs::create_dir_all(&self.state_dir)
+            .await
+            .with_context(|| format!("error creating Trunk state dir {:?}", &self.state_dir))?;
+        let snapshot_path = self.state_dir.join(dist_state_file(&self.cfg.dist, "sizes", "toml").await?);
+        let previous: BTreeMap<String, u64> = match fs::read(&snapshot_path).await {
+            Ok(bytes) => toml::from_slice(&bytes).context("error reading previous asset sizes")?,
+            Err(_) => BTreeMap::new(),
+        };
+        let dist: PathBuf = fs::canonicalize(&self.cfg.dist).await?.into();
+        let current = scan_dist(&dist)
+            .await?
+            .into_iter()
+            .filter_map(|asset| {
+                let rel_path = asset.path.strip_prefix(&dist).ok()?.to_string_lossy().replace('\\', "/");
+                Some((rel_path, asset.size))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        // Diff the current sizes against the previous sizes.
+        let mut lines = vec![];
+        for (name, size) in current.iter() {
+            match previous.get(name) {
+                None => lines.push(format!("  added    {} ({} bytes)", name, size)),
+                Some(prev) if prev != size => lines.push(format!(
+                    "  changed  {} ({} -> {} bytes, {:+})",
+                    name,
+                    prev,
+                    size,
+                    *size as i64 - *prev as i64
+                )),
+                Some(_) => (),
+            }
+        }
+        for (name, size) in previous.iter().filter(|(name, _)| !current.contains_key(*name)) {
+            lines.push(format!("  removed  {} ({} bytes)", name, size));
+        }
+        if lines.is_empty() {
+            self.progress.println("size report: no asset size changes since the previous build");
+        } else {
+            self.progress.println(format!("size report (vs previous build):\n{}", lines.join("\n")));
+        }
 
-        // Spawn the source HTML pipeline. This will spawn all other pipelines derived from
-        // the source HTML, and will ultimately generate and write the final HTML.
-        self.html_pipeline.clone().spawn().await?;
+        let snapshot = toml::to_string(&current).context("error serializing asset sizes")?;
+        fs::write(&snapshot_path, snapshot)
+            .await
+            .with_context(|| format!("error writing asset sizes to {:?}", &snapshot_path))?;
         Ok(())
     }
 }
+
+/// Write a Makefile-style deps file to the given path, declaring the build's inputs as
+/// prerequisites of its outputs, so that build systems such as make or ninja know when to rerun
+/// Trunk.
+async fn write_deps_file(deps_path: &Path, output: &HtmlPipelineOutput) -> Result<()> {
+    let escape = |path: &PathBuf| path.to_string_lossy().replace(' ', "\\ ");
+    let targets = output.output_paths.iter().map(escape).collect::<Vec<_>>();
+    let prereqs = output.input_paths.iter().map(escape).collect::<Vec<_>>();
+    let deps = format!("{}: {}\n", targets.join(" "), prereqs.join(" \\\n  "));
+    fs::write(deps_path, deps)
+        .await
+        .with_context(|| format!("error writing deps file {:?}", deps_path))
+}
+
+/// A structured event emitted by the build system over the course of a build.
+#[derive(Clone, Debug)]
+pub enum BuildEvent {
+    /// A build has started.
+    BuildStarted,
+    /// A stage of the build has started.
+    StageStarted(BuildStage),
+    /// A stage of the build has finished.
+    StageFinished(BuildStage),
+    /// A file or dir was emitted to the dist dir.
+    AssetEmitted(PathBuf),
+    /// The build finished successfully.
+    BuildFinished,
+    /// The build failed with the given error.
+    BuildFailed(String),
+}
+
+/// A stage of the build.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BuildStage {
+    /// The source HTML pipeline & all asset pipelines spawned from it.
+    Pipelines,
+    /// Writing the deps file, when enabled.
+    EmitDeps,
+    /// The size report, when enabled.
+    SizeReport,
+}
+
+impl BuildStage {
+    /// A human readable description of this stage.
+    pub fn description(self) -> &'static str {
+        match self {
+            BuildStage::Pipelines => "spawning asset pipelines",
+            BuildStage::EmitDeps => "writing deps file",
+            BuildStage::SizeReport => "generating size report",
+        }
+    }
+}
+
+/// An observer of build events.
+///
+/// Trunk's own UI is driven by the `ProgressBar` implementation of this trait. The
+/// `UnboundedSender` implementation forwards events over a channel, so that they may be consumed
+/// & rendered elsewhere.
+pub trait BuildObserver: Send + Sync {
+    /// Handle the given build event.
+    fn on_event(&self, event: &BuildEvent);
+}
+
+impl BuildObserver for ProgressBar {
+    fn on_event(&self, event: &BuildEvent) {
+        match event {
+            BuildEvent::BuildStarted => {
+                self.reset();
+                self.enable_steady_tick(100);
+                self.set_prefix(&format!("{}", BUILDING));
+                self.set_message("starting build");
+            }
+            BuildEvent::StageStarted(stage) => self.set_message(stage.description()),
+            BuildEvent::StageFinished(_) | BuildEvent::AssetEmitted(_) => (),
+            BuildEvent::BuildFinished => {
+                self.disable_steady_tick();
+                self.set_position(0);
+                self.set_prefix(&format!("{}", SUCCESS));
+                self.finish_with_message("success");
+            }
+            BuildEvent::BuildFailed(_) => {
+                self.disable_steady_tick();
+                self.set_position(0);
+                self.set_prefix(&format!("{}", ERROR));
+                self.finish_with_message("error");
+            }
+        }
+    }
+}
+
+impl BuildObserver for UnboundedSender<BuildEvent> {
+    fn on_event(&self, event: &BuildEvent) {
+        // A closed channel only means that the receiver is no longer interested in events.
+        let _ = self.unbounded_send(event.clone());
+    }
+}
+
+/// The state dirs already resolved by this process, keyed by the manifest they were resolved from.
+static STATE_DIRS: Mutex<BTreeMap<PathBuf, PathBuf>> = Mutex::new(BTreeMap::new());
+
+/// Resolve the dir holding Trunk's own state for the build of the given config, such as build
+/// locks & the asset sizes of the previous build.
+///
+/// This is the `trunk` dir within the cargo target dir of the crate alongside the source HTML,
+/// falling back to the system temp dir. Keeping this state out of the dist dir ensures that it is
+/// never deployed. As resolving the target dir runs `cargo metadata`, it is only resolved once
+/// per manifest.
+async fn state_dir(cfg: &RtcBuild) -> PathBuf {
+    let manifest = match cfg.target.parent().map(|dir| dir.join("Cargo.toml")) {
+        Some(manifest) if manifest.is_file() => manifest,
+        _ => return std::env::temp_dir().join("trunk"),
+    };
+    if let Some(dir) = STATE_DIRS.lock().unwrap().get(&manifest) {
+        return dir.clone();
+    }
+    let dir = match CargoMetadata::new(&manifest).await {
+        Ok(metadata) => metadata.metadata.target_directory.join("trunk"),
+        Err(_) => return std::env::temp_dir().join("trunk"),
+    };
+    STATE_DIRS.lock().unwrap().insert(manifest, dir.clone());
+    dir
+}
+
+/// The name of a state file belonging to the given dist dir, which is keyed by a hash of the dist
+/// dir's canonical path, as any number of dist dirs may share a state dir.
+pub(crate) async fn dist_state_file(dist: &Path, name: &str, ext: &str) -> Result<String> {
+    let dist = fs::canonicalize(dist)
+        .await
+        .with_context(|| format!("error getting canonical path to dist dir {:?}", dist))?;
+    Ok(format!("{}-{:x}.{}", name, seahash::hash(dist.to_string_lossy().as_bytes()), ext))
+}
+
+/// An advisory filesystem lock held on the dist dir for the duration of a build.
+///
+/// This ensures that concurrent Trunk processes targeting the same dist dir (for example, a
+/// `trunk build` while `trunk serve` is rebuilding) do not clobber each other's output. The lock
+/// file lives in the state dir, and the lock is released by the OS when this value is dropped or
+/// when the holding process exits, so a crashed build never leaves a stale lock behind.
+struct BuildLock {
+    /// The locked file, which is unlocked once closed.
+    _file: std::fs::File,
+}
+
+impl BuildLock {
+    /// Acquire the build lock for the given dist dir within the given state dir.
+    ///
+    /// If the lock is already held, this will either wait for it to be released, failing after
+    /// `LOCK_WAIT_TIMEOUT`, or fail immediately, depending on `wait`.
+    async fn acquire(dist: &Path, state_dir: &Path, wait: bool, progress: &ProgressBar) -> Result<Self> {
+        use fs2::FileExt;
+
+        fs::create_dir_all(state_dir)
+            .await
+            .with_context(|| format!("error creating Trunk state dir {:?}", state_dir))?;
+        let path = state_dir.join(dist_state_file(dist, "build", "lock").await?);
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("error opening build lock file {:?}", &path))?;
+        let started = Instant::now();
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => return Ok(Self { _file: file }),
+                Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                    if !wait {
+                        bail!("build already in progress for dist dir {:?}; set `build.lock_wait` to wait for it to finish", dist);
+                    }
+                    if started.elapsed() >= LOCK_WAIT_TIMEOUT {
+                        bail!(
+                            "timed out after {}s waiting for a concurrent build of dist dir {:?} to finish",
+                            LOCK_WAIT_TIMEOUT.as_secs(),
+                            dist
+                        );
+                    }
+                    progress.set_message("waiting for concurrent build to finish");
+                    sleep(Duration::from_millis(100)).await;
+                }
+                Err(err) => return Err(err).with_context(|| format!("error locking build lock file {:?}", &path)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigOpts, ConfigOptsBuild};
+    use crate::test_util::TestDir;
+
+    /// Build a build system for a fresh project dir holding a source HTML file & the given files
+    /// in its dist dir, with the given build options.
+    async fn test_build_system(name: &str, dist_files: &[(&str, &str)], opts: ConfigOptsBuild) -> (TestDir, BuildSystem) {
+        let dir = TestDir::new(name, &[("index.html", "<html><head></head><body></body></html>")]);
+        dir.create_dir("dist");
+        for (path, content) in dist_files {
+            dir.write(&format!("dist/{}", path), content);
+        }
+        let opts = ConfigOptsBuild {
+            target: Some(dir.join("index.html")),
+            ..opts
+        };
+        let cfg = ConfigOpts::rtc_build(opts, Some(dir.join("Trunk.toml")), None, false).await.unwrap();
+        let mut build = BuildSystem::new(cfg, ProgressBar::hidden(), None).await.unwrap();
+        build.state_dir = dir.join("state");
+        (dir, build)
+    }
+
+    /// An observer recording all events it receives.
+    #[derive(Default)]
+    struct RecordingObserver(std::sync::Mutex<Vec<BuildEvent>>);
+
+    impl BuildObserver for RecordingObserver {
+        fn on_event(&self, event: &BuildEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[async_std::test]
+    async fn observers_receive_the_events_of_a_failed_build() {
+        let opts = ConfigOptsBuild {
+            require_rust_link: true,
+            ..Default::default()
+        };
+        let (_dir, mut build) = test_build_system("observer", &[], opts).await;
+        let observer = Arc::new(RecordingObserver::default());
+        build.add_observer(observer.clone());
+        assert!(build.build().await.is_err());
+
+        let events = observer.0.lock().unwrap();
+        assert!(matches!(events[0], BuildEvent::BuildStarted), "{:?}", events);
+        assert!(matches!(events[1], BuildEvent::StageStarted(BuildStage::Pipelines)), "{:?}", events);
+        assert!(
+            matches!(&events[2], BuildEvent::BuildFailed(err) if err.contains("require_rust_link")),
+            "{:?}",
+            events
+        );
+        assert_eq!(events.len(), 3, "{:?}", events);
+    }
+
+    #[async_std::test]
+    async fn size_report_snapshot_is_kept_out_of_dist() {
+        let (_dir, build) = test_build_system("sizes", &[("app-abc.css", "body {}")], Default::default()).await;
+        build.size_report().await.unwrap();
+        let dist_files = std::fs::read_dir(&build.cfg.dist).unwrap().count();
+        assert_eq!(dist_files, 1, "the size snapshot must not be written to the dist dir");
+        let snapshot = std::fs::read_dir(&build.state_dir).unwrap().next().unwrap().unwrap().path();
+        assert!(std::fs::read_to_string(snapshot).unwrap().contains("app-abc.css"));
+    }
+
+    #[async_std::test]
+    async fn prune_stale_only_removes_old_hashed_outputs() {
+        let files = &[
+            ("app-abc123.css", "old"),
+            ("app-def456.css", "new"),
+            ("app-notahash.css", "kept"),
+            ("app.css", "kept"),
+            ("robots.txt", "kept"),
+        ];
+        let (_dir, build) = test_build_system("prune", files, Default::default()).await;
+        let output = HtmlPipelineOutput {
+            input_paths: vec![],
+            output_paths: vec![build.cfg.dist.join("app-def456.css")],
+            hashed_patterns: vec!["app-<hash>.css".into()],
+        };
+        build.prune_stale(&output).await.unwrap();
+
+        let mut remaining = std::fs::read_dir(&build.cfg.dist)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(remaining, vec!["app-def456.css", "app-notahash.css", "app.css", "robots.txt"]);
+    }
+
+    #[async_std::test]
+    async fn github_pages_files_are_written_next_to_the_index() {
+        let (_dir, build) = test_build_system("github-pages", &[("index.html", "<html>app</html>")], Default::default()).await;
+        let paths = build.write_github_pages_files().await.unwrap();
+        assert_eq!(paths, vec![build.cfg.dist.join(".nojekyll"), build.cfg.dist.join("404.html")]);
+        assert_eq!(std::fs::read_to_string(build.cfg.dist.join(".nojekyll")).unwrap(), "");
+        assert_eq!(std::fs::read_to_string(build.cfg.dist.join("404.html")).unwrap(), "<html>app</html>");
+    }
+
+    #[async_std::test]
+    async fn source_maps_default_to_debug_builds_only() {
+        let files = &[("index.html", ""), ("app-abc.css", ""), ("app-abc.css.map", ""), ("assets/lib.js.map", "")];
+        for release in &[false, true] {
+            let opts = ConfigOptsBuild {
+                release: *release,
+                ..Default::default()
+            };
+            let (_dir, build) = test_build_system("source-maps", files, opts).await;
+            assert_eq!(build.cfg.source_maps, !*release);
+            if !build.cfg.source_maps {
+                build.remove_source_maps().await.unwrap();
+            }
+            let maps = scan_dist(&build.cfg.dist).await.unwrap().into_iter().filter(|asset| asset.ext == "map").count();
+            assert_eq!(maps, if *release { 0 } else { 2 });
+            assert!(build.cfg.dist.join("app-abc.css").is_file());
+        }
+    }
+
+    #[async_std::test]
+    async fn each_target_is_emitted_under_its_own_name() {
+        let dir = TestDir::new(
+            "targets",
+            &[
+                ("Cargo.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2018\"\n"),
+                ("src/main.rs", "fn main() {}\n"),
+                ("index.html", "<html></html>"),
+                ("admin.html", "<html></html>"),
+            ],
+        );
+        let opts = ConfigOptsBuild {
+            target: Some(dir.join("index.html")),
+            targets: Some(vec![dir.join("admin.html")]),
+            ..Default::default()
+        };
+        let cfg = ConfigOpts::rtc_build(opts, Some(dir.join("Trunk.toml")), None, false).await.unwrap();
+        let build = BuildSystem::new(cfg, ProgressBar::hidden(), None).await.unwrap();
+        let html_outputs = build
+            .plan()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|plan| plan.asset_type == "html")
+            .map(|plan| plan.outputs)
+            .collect::<Vec<_>>();
+        assert_eq!(html_outputs, vec![vec![String::from("index.html")], vec![String::from("admin.html")]]);
+    }
+
+    #[async_std::test]
+    async fn deps_file_lists_outputs_depending_on_inputs() {
+        let dir = TestDir::new("emit-deps", &[]);
+        let output = HtmlPipelineOutput {
+            input_paths: vec![PathBuf::from("/app/index.html"), PathBuf::from("/app/my styles.css")],
+            output_paths: vec![PathBuf::from("/app/dist/index.html"), PathBuf::from("/app/dist/my styles-abc.css")],
+            hashed_patterns: vec![],
+        };
+        let deps_path = dir.join("deps.d");
+        write_deps_file(&deps_path, &output).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&deps_path).unwrap(),
+            "/app/dist/index.html /app/dist/my\\ styles-abc.css: /app/index.html \\\n  /app/my\\ styles.css\n"
+        );
+    }
+
+    #[async_std::test]
+    async fn build_lock_is_exclusive_until_dropped() {
+        let dir = TestDir::new("lock", &[]);
+        let (dist, state_dir) = (dir.join("dist"), dir.join("state"));
+        std::fs::create_dir_all(&dist).unwrap();
+        let progress = ProgressBar::hidden();
+
+        let lock = BuildLock::acquire(&dist, &state_dir, false, &progress).await.unwrap();
+        let err = BuildLock::acquire(&dist, &state_dir, false, &progress).await.err().unwrap();
+        assert!(err.to_string().contains("build already in progress"), "unexpected error: {}", err);
+        assert!(std::fs::read_dir(&dist).unwrap().next().is_none(), "the lock must not be placed in the dist dir");
+
+        drop(lock);
+        BuildLock::acquire(&dist, &state_dir, false, &progress).await.unwrap();
+    }
+}
@@ -1,6 +1,10 @@
-use std::path::PathBuf;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use async_std::task::spawn_blocking;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use structopt::StructOpt;
 
 use crate::build::BuildSystem;
@@ -13,13 +17,85 @@ use crate::config::{ConfigOpts, ConfigOptsBuild};
 pub struct Build {
     #[structopt(flatten)]
     pub build: ConfigOptsBuild,
+    /// List the assets declared in the source HTML & their intended output paths, without building
+    #[structopt(long = "list-assets")]
+    pub list_assets: bool,
+    /// Print the asset list as JSON
+    #[structopt(long, requires = "list-assets")]
+    pub json: bool,
+    /// After building, write a `.tar.gz` archive of the dist dir to the given path
+    #[structopt(long, parse(from_os_str), conflicts_with = "list-assets")]
+    pub archive: Option<PathBuf>,
 }
 
 impl Build {
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
-        let cfg = ConfigOpts::rtc_build(self.build, config).await?;
+    pub async fn run(self, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<()> {
+        let cfg = ConfigOpts::rtc_build(self.build, config, config_env, strict_config).await?;
+        let dist = cfg.dist.clone();
         let mut system = BuildSystem::new(cfg, spinner(), None).await?;
+        if self.list_assets {
+            let plans = system.plan().await?;
+            if self.json {
+                println!("{}", serde_json::to_string_pretty(&plans).context("error serializing asset list")?);
+            } else {
+                for plan in plans {
+                    println!("{}", plan);
+                }
+            }
+            return Ok(());
+        }
         system.build().await?;
+        if let Some(archive) = self.archive {
+            spawn_blocking(move || write_archive(&dist, &archive)).await?;
+        }
         Ok(())
     }
 }
+
+/// Write a gzipped tarball of the given dist dir to the given path, with all entries relative to
+/// the dist dir.
+fn write_archive(dist: &Path, archive: &Path) -> Result<()> {
+    let file = File::create(archive).with_context(|| format!("error creating archive {:?}", archive))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    builder
+        .append_dir_all(".", dist)
+        .with_context(|| format!("error archiving dist dir {:?}", dist))?;
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .with_context(|| format!("error finishing archive {:?}", archive))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn archive_holds_the_dist_dir_contents() {
+        let dir = TestDir::new("archive", &[("dist/index.html", "index"), ("dist/assets/app-abc.css", "body {}")]);
+        let archive = dir.join("dist.tar.gz");
+        write_archive(&dir.join("dist"), &archive).unwrap();
+
+        let mut tar = tar::Archive::new(GzDecoder::new(File::open(&archive).unwrap()));
+        let mut files = vec![];
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.header().entry_type().is_file() {
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                files.push((entry.path().unwrap().to_string_lossy().replace('\\', "/"), content));
+            }
+        }
+        files.sort();
+        assert_eq!(files, vec![
+            (String::from("assets/app-abc.css"), String::from("body {}")),
+            (String::from("index.html"), String::from("index")),
+        ]);
+    }
+}
@@ -16,8 +16,11 @@ pub struct Clean {
 }
 
 impl Clean {
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
-        let cfg = ConfigOpts::rtc_clean(self.clean, config).await?;
+    pub async fn run(self, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<()> {
+        let cfg = ConfigOpts::rtc_clean(self.clean, config, config_env, strict_config).await?;
+        for warning in cfg.warnings.iter() {
+            eprintln!("warning: {}", warning);
+        }
         let _ = fs::remove_dir_all(&cfg.dist).await;
         if cfg.cargo {
             let output = Command::new("cargo")
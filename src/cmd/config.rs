@@ -14,13 +14,13 @@ pub struct Config {
 }
 
 impl Config {
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
+    pub async fn run(self, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<()> {
         // NOTE WELL: if we ever add additional subcommands, refactor this to match the pattern
         // used in main, which is much more scalable. This is faster to code, and will not force
         // incompatibility when new commands are added.
         match self.action {
             ConfigSubcommands::Show => {
-                let cfg = ConfigOpts::full(config).await?;
+                let cfg = ConfigOpts::full(config, config_env, strict_config).await?;
                 println!("{:#?}", cfg);
             }
         }
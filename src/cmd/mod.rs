@@ -1,5 +1,6 @@
 pub mod build;
 pub mod clean;
 pub mod config;
+pub mod new;
 pub mod serve;
 pub mod watch;
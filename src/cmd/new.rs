@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use async_std::fs;
+use structopt::StructOpt;
+
+/// Create a new Trunk project.
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(name = "new")]
+pub struct New {
+    /// The directory in which to create the project, whose name is used as the crate name
+    #[structopt(parse(from_os_str))]
+    pub path: PathBuf,
+    /// The project template to use: minimal or web-sys [default: minimal]
+    #[structopt(long)]
+    pub template: Option<Template>,
+}
+
+impl New {
+    pub async fn run(self) -> Result<()> {
+        let name = scaffold(&self.path, self.template.unwrap_or_default()).await?;
+        println!("created new Trunk project `{}` in {:?}; run `trunk serve` from there to get started", name, &self.path);
+        Ok(())
+    }
+}
+
+/// Write a new project from the given template into the given dir, which must be empty or absent,
+/// returning the project's crate name, which is the name of the dir.
+async fn scaffold(dir: &Path, template: Template) -> Result<String> {
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!("could not determine a crate name from path {:?}", dir))?;
+    ensure!(
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "invalid crate name `{}`; crate names may only contain alphanumeric characters, `-` & `_`",
+        name
+    );
+    if let Ok(mut entries) = std::fs::read_dir(dir) {
+        ensure!(entries.next().is_none(), "destination {:?} already exists and is not empty", dir);
+    }
+
+    let files = vec![
+        ("Cargo.toml", template.cargo_toml(&name)),
+        ("src/main.rs", template.main_rs().to_string()),
+        ("index.html", INDEX_HTML.replace("{name}", &name)),
+        ("Trunk.toml", TRUNK_TOML.to_string()),
+        (".gitignore", GITIGNORE.to_string()),
+    ];
+    fs::create_dir_all(dir.join("src"))
+        .await
+        .with_context(|| format!("error creating project dir {:?}", dir))?;
+    for (file, content) in files {
+        let path = dir.join(file);
+        fs::write(&path, content)
+            .await
+            .with_context(|| format!("error writing project file {:?}", &path))?;
+    }
+    Ok(name)
+}
+
+/// The templates available for new projects.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Template {
+    /// An app depending only on `wasm-bindgen`, which logs to the browser console.
+    #[default]
+    Minimal,
+    /// An app using `web-sys` to render content into the page.
+    WebSys,
+}
+
+impl Template {
+    /// The `Cargo.toml` of a new project named `name`.
+    fn cargo_toml(self, name: &str) -> String {
+        let deps = match self {
+            Self::Minimal => MINIMAL_DEPS,
+            Self::WebSys => WEB_SYS_DEPS,
+        };
+        format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n[dependencies]\n{}",
+            name, deps
+        )
+    }
+
+    /// The `src/main.rs` of a new project.
+    fn main_rs(self) -> &'static str {
+        match self {
+            Self::Minimal => MINIMAL_MAIN_RS,
+            Self::WebSys => WEB_SYS_MAIN_RS,
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "minimal" => Self::Minimal,
+            "web-sys" => Self::WebSys,
+            _ => bail!("unknown template `{}`; expected one of `minimal` or `web-sys`", s),
+        })
+    }
+}
+
+const MINIMAL_DEPS: &str = r#"wasm-bindgen = "0.2"
+"#;
+
+const WEB_SYS_DEPS: &str = r#"wasm-bindgen = "0.2"
+web-sys = { version = "0.3", features = ["Document", "Element", "HtmlElement", "Node", "Window"] }
+"#;
+
+const MINIMAL_MAIN_RS: &str = r#"use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = console)]
+    fn log(msg: &str);
+}
+
+fn main() {
+    log("Hello from Rust!");
+}
+"#;
+
+const WEB_SYS_MAIN_RS: &str = r#"fn main() {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .expect("no document available");
+    let body = document.body().expect("document has no body");
+    body.set_text_content(Some("Hello from Rust!"));
+}
+"#;
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8"/>
+    <title>{name}</title>
+    <link data-trunk rel="rust" href="Cargo.toml"/>
+  </head>
+  <body></body>
+</html>
+"#;
+
+const TRUNK_TOML: &str = r#"[build]
+# The index HTML file to drive the bundling process.
+target = "index.html"
+# The output dir for all final assets.
+dist = "dist"
+
+[serve]
+# The port to serve on.
+port = 8080
+"#;
+
+const GITIGNORE: &str = "/dist\n/target\n";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigOpts;
+    use crate::test_util::TestDir;
+
+    #[async_std::test]
+    async fn scaffolded_projects_are_valid() {
+        let parent = TestDir::new("new", &[]);
+        for (template, dep) in &[(Template::Minimal, "wasm-bindgen"), (Template::WebSys, "web-sys")] {
+            let dir = parent.join(format!("app-{:?}", template).to_lowercase());
+            assert_eq!(scaffold(&dir, *template).await.unwrap(), format!("app-{:?}", template).to_lowercase());
+            for file in &["Cargo.toml", "src/main.rs", "index.html", "Trunk.toml", ".gitignore"] {
+                assert!(dir.join(file).is_file(), "{} is missing", file);
+            }
+
+            let manifest: toml::Value = toml::from_str(&std::fs::read_to_string(dir.join("Cargo.toml")).unwrap()).unwrap();
+            assert_eq!(manifest["package"]["name"].as_str(), dir.file_name().unwrap().to_str());
+            assert!(manifest["dependencies"].get(dep).is_some(), "{:?}", manifest);
+
+            // The Trunk.toml must pass the strict config checks & point at the generated index.
+            let cfg = ConfigOpts::rtc_build(Default::default(), Some(dir.join("Trunk.toml")), None, true)
+                .await
+                .unwrap();
+            assert_eq!(cfg.target, dir.join("index.html"));
+            assert_eq!(cfg.dist, dir.join("dist"));
+        }
+
+        let err = scaffold(&parent.join("app-minimal"), Template::Minimal).await.unwrap_err();
+        assert!(err.to_string().contains("not empty"), "{}", err);
+    }
+}
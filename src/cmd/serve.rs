@@ -20,8 +20,8 @@ pub struct Serve {
 }
 
 impl Serve {
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
-        let cfg = ConfigOpts::rtc_serve(self.build, self.watch, self.serve, config).await?;
+    pub async fn run(self, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<()> {
+        let cfg = ConfigOpts::rtc_serve(self.build, self.watch, self.serve, config, config_env, strict_config).await?;
         let system = ServeSystem::new(cfg, spinner()).await?;
         system.run().await?;
         Ok(())
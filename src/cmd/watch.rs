@@ -18,10 +18,10 @@ pub struct Watch {
 }
 
 impl Watch {
-    pub async fn run(self, config: Option<PathBuf>) -> Result<()> {
-        let cfg = ConfigOpts::rtc_watch(self.build, self.watch, config).await?;
+    pub async fn run(self, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<()> {
+        let cfg = ConfigOpts::rtc_watch(self.build, self.watch, config, config_env, strict_config).await?;
         let mut system = WatchSystem::new(cfg, spinner()).await?;
-        system.build().await;
+        let _ = system.build().await;
         system.run().await;
         Ok(())
     }
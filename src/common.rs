@@ -1,13 +1,17 @@
 //! Common functionality and types.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use async_std::path::PathBuf as AsyncPathBuf;
 use async_std::task::spawn_blocking;
 
 use console::Emoji;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha384};
 
 pub static BUILDING: Emoji<'_, '_> = Emoji("📦", "");
 pub static SUCCESS: Emoji<'_, '_> = Emoji("✅", "");
@@ -21,21 +25,149 @@ pub fn parse_public_url(val: &str) -> String {
     format!("{}{}{}", prefix, val, suffix)
 }
 
+/// The hashing algorithms available for fingerprinting content.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgo {
+    /// A fast non-cryptographic hash, yielding 16 hex characters at most.
+    #[default]
+    Seahash,
+    /// SHA-256, yielding 64 hex characters.
+    Sha256,
+    /// SHA-384, yielding 96 hex characters.
+    Sha384,
+}
+
+impl HashAlgo {
+    /// Hash the given bytes, returning the lowercase hexadecimal representation of the hash.
+    pub fn hash(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Seahash => format!("{:x}", seahash::hash(bytes)),
+            Self::Sha256 => format!("{:x}", Sha256::digest(bytes)),
+            Self::Sha384 => format!("{:x}", Sha384::digest(bytes)),
+        }
+    }
+}
+
+impl FromStr for HashAlgo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "seahash" => Self::Seahash,
+            "sha256" => Self::Sha256,
+            "sha384" => Self::Sha384,
+            _ => bail!("unknown hash algorithm `{}`; expected one of `seahash`, `sha256` or `sha384`", s),
+        })
+    }
+}
+
 /// A utility function to recursively copy a directory.
-pub async fn copy_dir_recursive(from_dir: PathBuf, to_dir: PathBuf) -> Result<()> {
+///
+/// When `follow_symlinks` is `true`, symlinked files & directories are copied as regular files &
+/// directories, and any symlink loops are skipped. When `false`, symlinks are skipped entirely.
+pub async fn copy_dir_recursive(from_dir: PathBuf, to_dir: PathBuf, follow_symlinks: bool) -> Result<()> {
     if !AsyncPathBuf::from(&from_dir).exists().await {
         return Err(anyhow!("directory can not be copied as it does not exist {:?}", &from_dir));
     }
-    spawn_blocking(move || {
-        let opts = fs_extra::dir::CopyOptions {
-            overwrite: true,
-            content_only: true,
-            ..Default::default()
-        };
-        let _ = fs_extra::dir::copy(from_dir, to_dir, &opts).context("error copying directory")?;
-        Ok(())
-    })
-    .await
+    spawn_blocking(move || copy_dir_recursive_blocking(&from_dir, &to_dir, follow_symlinks, &mut vec![]).context("error copying directory")).await
+}
+
+/// The blocking implementation of `copy_dir_recursive`.
+///
+/// The `ancestors` vector holds the canonical paths of the directories currently being copied,
+/// which is used to detect symlinks pointing back up the tree.
+fn copy_dir_recursive_blocking(from_dir: &Path, to_dir: &Path, follow_symlinks: bool, ancestors: &mut Vec<PathBuf>) -> Result<()> {
+    let canonical_dir = from_dir
+        .canonicalize()
+        .with_context(|| format!("error getting canonical path of directory {:?}", from_dir))?;
+    if ancestors.contains(&canonical_dir) {
+        return Ok(()); // Symlink loop, nothing more to copy.
+    }
+    ancestors.push(canonical_dir);
+    std::fs::create_dir_all(to_dir).with_context(|| format!("error creating directory {:?}", to_dir))?;
+    for entry in std::fs::read_dir(from_dir).with_context(|| format!("error reading directory {:?}", from_dir))? {
+        let entry = entry.with_context(|| format!("error reading entry of directory {:?}", from_dir))?;
+        if !follow_symlinks && entry.file_type()?.is_symlink() {
+            continue;
+        }
+        let (from_path, to_path) = (entry.path(), to_dir.join(entry.file_name()));
+        if from_path.is_dir() {
+            copy_dir_recursive_blocking(&from_path, &to_path, follow_symlinks, ancestors)?;
+        } else {
+            std::fs::copy(&from_path, &to_path).with_context(|| format!("error copying file {:?} to {:?}", &from_path, &to_path))?;
+        }
+    }
+    ancestors.pop();
+    Ok(())
+}
+
+/// Write the given contents to the given path, retrying transient failures.
+///
+/// See `retry_transient_io` for which failures are retried.
+pub async fn write_with_retry(path: &Path, contents: &[u8], retries: u32) -> std::io::Result<()> {
+    retry_transient_io(retries, || async_std::fs::write(path, contents)).await
+}
+
+/// Copy the file at the given path to the given destination, retrying transient failures.
+///
+/// See `retry_transient_io` for which failures are retried.
+pub async fn copy_with_retry(from: &Path, to: &Path, retries: u32) -> std::io::Result<u64> {
+    retry_transient_io(retries, || async_std::fs::copy(from, to)).await
+}
+
+/// The delay before the first retry of a transient IO failure, which doubles with each retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+/// The maximum delay between retries of a transient IO failure.
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Run the given IO operation, retrying it up to `retries` times with exponential backoff when it
+/// fails transiently.
+///
+/// Antivirus software & file indexers (especially on Windows) may briefly lock files which were
+/// just written, causing permission denied or sharing violation errors which resolve themselves
+/// shortly after. All other errors are returned immediately, as are transient errors once the
+/// retries are exhausted.
+async fn retry_transient_io<F, Fut, T>(retries: u32, mut op: F) -> std::io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = std::io::Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match op().await {
+            Err(err) if attempt < retries && is_transient_io_error(&err) => {
+                async_std::task::sleep(retry_backoff(attempt)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// The delay before the given zero based retry of a transient IO failure.
+fn retry_backoff(attempt: u32) -> Duration {
+    RETRY_BACKOFF.checked_mul(2u32.saturating_pow(attempt)).map_or(MAX_RETRY_BACKOFF, |backoff| backoff.min(MAX_RETRY_BACKOFF))
+}
+
+/// Check whether the given IO error is likely caused by another process briefly locking a file.
+fn is_transient_io_error(err: &std::io::Error) -> bool {
+    // The Windows ERROR_SHARING_VIOLATION & ERROR_LOCK_VIOLATION error codes.
+    const WINDOWS_LOCK_ERRORS: &[i32] = &[32, 33];
+    let is_lock_violation = cfg!(windows) && err.raw_os_error().map(|code| WINDOWS_LOCK_ERRORS.contains(&code)).unwrap_or(false);
+    err.kind() == std::io::ErrorKind::PermissionDenied || is_lock_violation
+}
+
+/// Check whether the given command resolves to an executable, either as a path or via `PATH`.
+pub fn is_executable_available(cmd: &str) -> bool {
+    let path = Path::new(cmd);
+    if path.components().count() > 1 {
+        return path.is_file();
+    }
+    let exts: &[&str] = if cfg!(windows) { &["", ".exe", ".cmd", ".bat"] } else { &[""] };
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| exts.iter().any(|ext| dir.join(format!("{}{}", cmd, ext)).is_file())))
+        .unwrap_or(false)
 }
 
 /// Build system spinner.
@@ -43,3 +175,92 @@ pub fn spinner() -> ProgressBar {
     let style = ProgressStyle::default_spinner().template("{spinner} {prefix} trunk | {wide_msg}");
     ProgressBar::new_spinner().with_style(style)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io::{Error, ErrorKind};
+
+    use super::*;
+    use crate::test_util::TestDir;
+
+    /// Run `retry_transient_io` with the given retries over an operation which fails with the given
+    /// error kind the given number of times, returning the result & the number of attempts made.
+    async fn retry_failing(retries: u32, failures: u32, kind: ErrorKind) -> (std::io::Result<()>, u32) {
+        let attempts = Cell::new(0);
+        let res = retry_transient_io(retries, || {
+            attempts.set(attempts.get() + 1);
+            let res = if attempts.get() <= failures { Err(Error::from(kind)) } else { Ok(()) };
+            async move { res }
+        })
+        .await;
+        (res, attempts.get())
+    }
+
+    #[async_std::test]
+    async fn transient_io_errors_succeed_on_retry() {
+        let (res, attempts) = retry_failing(3, 2, ErrorKind::PermissionDenied).await;
+        assert!(res.is_ok());
+        assert_eq!(attempts, 3);
+    }
+
+    #[async_std::test]
+    async fn persistent_io_errors_fail_once_retries_are_exhausted() {
+        let (res, attempts) = retry_failing(2, u32::MAX, ErrorKind::PermissionDenied).await;
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(attempts, 3);
+
+        // Other errors are not retried at all.
+        let (res, attempts) = retry_failing(2, u32::MAX, ErrorKind::NotFound).await;
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::NotFound);
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn retry_backoff_is_capped() {
+        assert_eq!(retry_backoff(0), Duration::from_millis(50));
+        assert_eq!(retry_backoff(1), Duration::from_millis(100));
+        assert_eq!(retry_backoff(5), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(40), MAX_RETRY_BACKOFF);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn copy_dir_recursive_guards_against_symlink_loops() {
+        use std::os::unix::fs::symlink;
+
+        let src = TestDir::new("copy-src", &[("a/file.txt", "file")]);
+        symlink(&*src, src.join("a/loop")).unwrap();
+        symlink(src.join("a/file.txt"), src.join("link.txt")).unwrap();
+
+        let out = TestDir::new("copy-out", &[]);
+        copy_dir_recursive(src.to_path_buf(), out.join("followed"), true).await.unwrap();
+        assert_eq!(std::fs::read_to_string(out.join("followed/a/file.txt")).unwrap(), "file");
+        assert_eq!(std::fs::read_to_string(out.join("followed/link.txt")).unwrap(), "file");
+        assert!(!out.join("followed/link.txt").symlink_metadata().unwrap().file_type().is_symlink());
+        assert!(!out.join("followed/a/loop").exists());
+
+        copy_dir_recursive(src.to_path_buf(), out.join("skipped"), false).await.unwrap();
+        assert!(out.join("skipped/a/file.txt").is_file());
+        assert!(!out.join("skipped/link.txt").exists());
+        assert!(!out.join("skipped/a/loop").exists());
+    }
+
+    #[test]
+    fn hash_algos_are_selected_by_name() {
+        assert_eq!("seahash".parse::<HashAlgo>().unwrap(), HashAlgo::Seahash);
+        assert_eq!("sha256".parse::<HashAlgo>().unwrap(), HashAlgo::Sha256);
+        assert_eq!("sha384".parse::<HashAlgo>().unwrap(), HashAlgo::Sha384);
+        assert!("md5".parse::<HashAlgo>().is_err());
+        assert_eq!(toml::from_str::<std::collections::BTreeMap<String, HashAlgo>>("algo = \"sha384\"").unwrap()["algo"], HashAlgo::Sha384);
+        assert_eq!(HashAlgo::default(), HashAlgo::Seahash);
+    }
+
+    #[test]
+    fn hash_algos_produce_hex_digests() {
+        assert_eq!(HashAlgo::Sha256.hash(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(HashAlgo::Sha384.hash(b"abc").len(), 96);
+        assert!(HashAlgo::Seahash.hash(b"abc").len() <= 16);
+        assert_ne!(HashAlgo::Seahash.hash(b"abc"), HashAlgo::Seahash.hash(b"abd"));
+    }
+}
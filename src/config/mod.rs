@@ -7,7 +7,10 @@
 mod manifest;
 mod models;
 mod rt;
+mod schema;
 
 pub use manifest::CargoMetadata;
-pub use models::{ConfigOpts, ConfigOptsBuild, ConfigOptsClean, ConfigOptsProxy, ConfigOptsServe, ConfigOptsWatch};
-pub use rt::{RtcBuild, RtcClean, RtcServe, RtcWatch};
+pub use models::{
+    ConfigOpts, ConfigOptsBackendCommand, ConfigOptsBuild, ConfigOptsClean, ConfigOptsDownload, ConfigOptsProxy, ConfigOptsServe, ConfigOptsWatch,
+};
+pub use rt::{dist_path_prefix, DownloadRule, RtcBuild, RtcClean, RtcServe, RtcWatch};
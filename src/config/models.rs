@@ -1,13 +1,14 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use http_types::Url;
 use serde::Deserialize;
 use structopt::StructOpt;
 
-use crate::common::parse_public_url;
-use crate::config::{RtcBuild, RtcClean, RtcServe, RtcWatch};
+use crate::common::{parse_public_url, HashAlgo};
+use crate::config::{schema, RtcBuild, RtcClean, RtcServe, RtcWatch};
 
 /// Config options for the build system.
 #[derive(Clone, Debug, Default, Deserialize, StructOpt)]
@@ -15,6 +16,10 @@ pub struct ConfigOptsBuild {
     /// The index HTML file to drive the bundling process [default: index.html]
     #[structopt(parse(from_os_str))]
     pub target: Option<PathBuf>,
+    /// Additional HTML files to build alongside the target, each of which is emitted to the dist
+    /// dir under its own file name [default: []]
+    #[structopt(long, parse(from_os_str))]
+    pub targets: Option<Vec<PathBuf>>,
     /// Build in release mode [default: false]
     #[structopt(long)]
     #[serde(default)]
@@ -25,6 +30,156 @@ pub struct ConfigOptsBuild {
     /// The public URL from which assets are to be served [default: /]
     #[structopt(long, parse(from_str=parse_public_url))]
     pub public_url: Option<String>,
+    /// Reference assets from the output HTML by `./`-relative URLs in place of the public URL, so
+    /// that the dist dir works when opened from disk via `file://` [default: false]
+    #[structopt(long = "relative-urls")]
+    #[serde(default)]
+    pub relative_urls: bool,
+    /// The href of a `<base>` element to inject into the output HTML, independent of the public
+    /// URL [default: None]
+    #[structopt(long = "base-href")]
+    pub base_href: Option<String>,
+    /// Fail when the `base_href` differs from the public URL by which assets are referenced,
+    /// instead of only warning [default: false]
+    #[structopt(long = "strict-base-href")]
+    #[serde(default)]
+    pub strict_base_href: bool,
+    /// Wait for a concurrent build of the same dist dir to finish, instead of failing [default: false]
+    #[structopt(long = "lock-wait")]
+    #[serde(default)]
+    pub lock_wait: bool,
+    /// Print the resolved build config & the files each asset pipeline would emit, without
+    /// running cargo or writing to the dist dir [default: false]
+    #[structopt(long = "dry-run")]
+    #[serde(default)]
+    pub dry_run: bool,
+    /// The cargo command used for building the Rust app, which may be a cargo wrapper [default: cargo]
+    #[structopt(long = "cargo-command")]
+    pub cargo_command: Option<String>,
+    /// Additional arguments to append to the cargo build invocation [default: []]
+    #[structopt(long = "cargo-args")]
+    pub cargo_args: Option<Vec<String>>,
+    /// Additional flags to pass to rustc for the Rust app's cargo build, appended to any
+    /// `RUSTFLAGS` already set in the environment [default: []]
+    #[structopt(long)]
+    pub rustflags: Option<Vec<String>>,
+    /// A script file, relative to the source HTML file, whose content is inlined into a
+    /// `<script nomodule>` fallback for browsers without ES module support [default: None]
+    #[structopt(long = "nomodule-fallback-path", parse(from_os_str))]
+    pub nomodule_fallback_path: Option<PathBuf>,
+    /// Inline script content for a `<script nomodule>` fallback for browsers without ES module
+    /// support, as an alternative to `nomodule_fallback_path` [default: None]
+    #[structopt(long = "nomodule-fallback-inline")]
+    pub nomodule_fallback_inline: Option<String>,
+    /// Follow symlinks when copying directories into the dist dir [default: true]
+    #[structopt(long = "copy-follow-symlinks")]
+    pub follow_symlinks: Option<bool>,
+    /// Print a report of per-asset size changes compared to the previous build [default: false]
+    #[structopt(long)]
+    #[serde(default)]
+    pub release_size_report: bool,
+    /// Cargo's verbosity level, passed to cargo as that many `-v` flags [default: 0]
+    #[structopt(long = "cargo-verbose")]
+    pub cargo_verbose: Option<u8>,
+    /// Pass the `--quiet` flag to cargo [default: false]
+    #[structopt(long = "cargo-quiet")]
+    #[serde(default)]
+    pub cargo_quiet: bool,
+    /// The maximum size in bytes of `rel="inline"` assets which will be inlined into the HTML;
+    /// larger assets are emitted as separate files [default: None]
+    #[structopt(long = "inline-max-size")]
+    pub inline_max_size: Option<u64>,
+    /// The hashing algorithm used for content fingerprints: seahash, sha256 or sha384 [default: seahash]
+    #[structopt(long = "hash-algo")]
+    pub hash_algo: Option<HashAlgo>,
+    /// Write a Makefile-style deps file to the given path, listing the build's inputs as
+    /// prerequisites of its outputs [default: None]
+    #[structopt(long = "emit-deps", parse(from_os_str))]
+    pub emit_deps: Option<PathBuf>,
+    /// The maximum number of asset pipelines to run concurrently [default: the number of CPUs]
+    #[structopt(long)]
+    pub jobs: Option<usize>,
+    /// Inject a `<meta name="trunk-build">` tag holding the build time & app version into the
+    /// output HTML [default: false]
+    #[structopt(long)]
+    #[serde(default)]
+    pub stamp: bool,
+    /// Build release mode Rust apps with `-C panic=abort`, dropping unwinding support for a
+    /// smaller wasm binary; as panics then abort, they can no longer be caught [default: false]
+    #[structopt(long = "panic-abort")]
+    #[serde(default)]
+    pub panic_abort: bool,
+    /// Treat warnings of the Rust app's cargo build as errors, via `-D warnings` [default: false]
+    #[structopt(long = "deny-warnings")]
+    #[serde(default)]
+    pub deny_warnings: bool,
+    /// Prepare the dist dir for GitHub Pages, by writing a `.nojekyll` file & copying the output
+    /// `index.html` to `404.html` for client-side routing [default: false]
+    #[structopt(long = "github-pages")]
+    #[serde(default)]
+    pub github_pages: bool,
+    /// The number of times to retry writing an asset to the dist dir when the write fails
+    /// transiently, such as when a file is briefly locked by antivirus software; at most 10
+    /// [default: 3]
+    #[structopt(long = "fs-retries")]
+    pub fs_retries: Option<u32>,
+    /// A dir relative to the dist dir into which the wasm-bindgen output of the Rust app is
+    /// emitted, such as `pkg` [default: None]
+    #[structopt(long = "bindgen-out-dir", parse(from_os_str))]
+    pub bindgen_out_dir: Option<PathBuf>,
+    /// A dir relative to the dist dir into which all hashed assets, including the Rust app unless
+    /// `bindgen_out_dir` is set, are emitted, such as `assets`; the output HTML stays at the root
+    /// of the dist dir [default: None]
+    #[structopt(long = "assets-dir", parse(from_os_str))]
+    pub assets_dir: Option<PathBuf>,
+    /// The exact version of wasm-bindgen required, such as `0.2.68`; the build fails if the
+    /// wasm-bindgen found on the `PATH` reports a different version [default: None]
+    #[structopt(long = "wasm-bindgen-version")]
+    pub wasm_bindgen_version: Option<String>,
+    /// The level at which wasm-opt optimizes the Rust app's wasm, one of `0` to `4`, `s` or `z`,
+    /// or `off` to skip wasm-opt [default: off]
+    #[structopt(long = "wasm-opt")]
+    pub wasm_opt: Option<String>,
+    /// Alongside each hashed output file, also write a copy under its unhashed name, such as
+    /// `app.css` next to `app-<hash>.css` [default: false]
+    #[structopt(long = "emit-unhashed-aliases")]
+    #[serde(default)]
+    pub emit_unhashed_aliases: bool,
+    /// Emit source maps & debug info into the dist dir; when disabled, the Rust app's WASM is
+    /// stripped of its DWARF debug info & any `.map` files are removed from the dist dir
+    /// [default: true for debug builds, false for release builds]
+    #[structopt(long = "source-maps")]
+    pub source_maps: Option<bool>,
+    /// After each build, remove hashed files left in the dist dir by previous builds, which no
+    /// longer match the hashed outputs of the current build [default: true]
+    #[structopt(long = "prune-stale")]
+    pub prune_stale: Option<bool>,
+    /// A template for the script which loads the Rust app, in place of the default module script;
+    /// `{base}`, `{js}` & `{wasm}` are replaced with the asset base URL & the paths of the JS
+    /// loader & WASM file [default: None]
+    #[structopt(long = "pattern-script")]
+    pub pattern_script: Option<String>,
+    /// A template for elements appended to the head of the output HTML, such as preload links for
+    /// the Rust app, with the same replacements as `pattern_script` [default: None]
+    #[structopt(long = "pattern-preload")]
+    pub pattern_preload: Option<String>,
+    /// Minify the output `index.html` of release builds, preserving the contents of `<pre>`,
+    /// `<script>`, `<style>` & `<textarea>` elements [default: false]
+    #[structopt(long = "minify-html")]
+    #[serde(default)]
+    pub minify_html: bool,
+    /// Fail the build when the source HTML has no `<link data-trunk rel="rust" .../>` link,
+    /// instead of building the Rust app of the `Cargo.toml` alongside it [default: false]
+    #[structopt(long = "require-rust-link")]
+    #[serde(default)]
+    pub require_rust_link: bool,
+    /// Additional env vars to set for the cargo build; `${VAR}` references in values are
+    /// substituted from the environment [default: None]
+    ///
+    /// This may only be configured via the `Trunk.toml` config file.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub env: Option<HashMap<String, String>>,
 }
 
 /// Config options for the watch system.
@@ -33,18 +188,64 @@ pub struct ConfigOptsWatch {
     /// Additional paths to ignore [default: []]
     #[structopt(short, long, parse(from_os_str))]
     pub ignore: Option<Vec<PathBuf>>,
+    /// The number of recent rebuild timings to retain & report after each rebuild [default: 0]
+    #[structopt(long = "rebuild-history")]
+    pub rebuild_history: Option<usize>,
+    /// The base directory to watch for changes, independent of the build dir [default: .]
+    #[structopt(long = "watch-root", parse(from_os_str))]
+    pub watch_root: Option<PathBuf>,
+    /// Follow symlinks when watching for changes [default: true]
+    #[structopt(long = "watch-follow-symlinks")]
+    pub follow_symlinks: Option<bool>,
+    /// Also watch the sources of local path dependencies which live outside of the watch root
+    /// [default: true]
+    #[structopt(long = "watch-path-deps")]
+    pub path_deps: Option<bool>,
+    /// Ignore changes to the temp & swap files of common editors [default: true]
+    #[structopt(long = "ignore-editor-temp")]
+    pub ignore_editor_temp: Option<bool>,
+    /// Ignore changes to hidden files & dirs, whose names start with a `.` [default: true]
+    #[structopt(long = "ignore-hidden")]
+    pub ignore_hidden: Option<bool>,
+    /// Poll the file system for changes on the given interval in milliseconds, instead of relying
+    /// on native file system events, which are unavailable on some network & VM shared file
+    /// systems [default: None]
+    #[structopt(long = "watch-poll")]
+    pub poll: Option<u64>,
+    /// Paths relative to the watch root which are never polled in poll mode, in addition to the
+    /// ignored paths [default: ["target/", ".git/"]]
+    #[structopt(long = "watch-poll-ignore")]
+    pub poll_ignore_extra: Option<Vec<String>>,
 }
 
 /// Config options for the serve system.
 #[derive(Clone, Debug, Default, Deserialize, StructOpt)]
 pub struct ConfigOptsServe {
-    /// The port to serve on [default: 8080]
+    /// The IP address to serve on, such as `127.0.0.1` or the IPv6 `::1`, which may be given in
+    /// brackets; `::` binds dual-stack to all IPv4 & IPv6 interfaces [default: 0.0.0.0]
+    #[structopt(long)]
+    pub address: Option<String>,
+    /// The port to serve on, where `0` picks a free port [default: 8080]
     #[structopt(long)]
     pub port: Option<u16>,
+    /// Serve on a Unix domain socket at the given path instead of a TCP port [default: None]
+    #[structopt(long = "unix-socket", parse(from_os_str))]
+    pub unix_socket: Option<PathBuf>,
+    /// The maximum number of pending connections queued on the server's TCP listener
+    /// [default: 128]
+    #[structopt(long)]
+    pub backlog: Option<u32>,
     /// Open a browser tab once the initial build is complete [default: false]
     #[structopt(long)]
     #[serde(default)]
     pub open: bool,
+    /// A path relative to the public URL to open in the browser [default: None]
+    #[structopt(long = "open-path")]
+    pub open_path: Option<String>,
+    /// The browser executable used to open the browser tab, instead of the system default
+    /// [default: None]
+    #[structopt(long)]
+    pub browser: Option<String>,
     /// A URL to which requests will be proxied [default: None]
     #[structopt(long = "proxy-backend")]
     #[serde(default)]
@@ -54,6 +255,56 @@ pub struct ConfigOptsServe {
     #[structopt(long = "proxy-rewrite")]
     #[serde(default)]
     pub proxy_rewrite: Option<String>,
+    /// The maximum size in bytes of request & response bodies proxied to & from the backend
+    /// [default: None]
+    #[structopt(long = "proxy-max-body-bytes")]
+    pub proxy_max_body_bytes: Option<usize>,
+    /// Relay `404` responses from proxy backends verbatim, instead of falling back to the
+    /// index.html as is done for static files [default: true]
+    #[structopt(long = "proxy-passthrough-404")]
+    pub proxy_passthrough_404: Option<bool>,
+    /// Render an HTML listing for dist directories which have no index.html [default: false]
+    #[structopt(long = "directory-listing")]
+    #[serde(default)]
+    pub directory_listing: bool,
+    /// Start serving even if the initial build fails, instead of exiting [default: false]
+    #[structopt(long = "serve-on-build-error")]
+    #[serde(default)]
+    pub serve_on_build_error: bool,
+    /// Keep serving the dist dir of the last successful build while rebuilds are failing
+    /// [default: false]
+    #[structopt(long = "serve-last-good")]
+    #[serde(default)]
+    pub serve_last_good: bool,
+    /// Require HTTP basic auth credentials of the form `user:pass` on all requests; `${VAR}`
+    /// references are substituted from the environment [default: None]
+    #[structopt(long = "basic-auth")]
+    pub basic_auth: Option<String>,
+    /// Only serve requests whose `Host` header names one of the given hosts, where `*.example`
+    /// matches any subdomain of `example`; `localhost`, loopback addresses & the configured
+    /// `address` are always allowed [default: None]
+    #[structopt(long = "allowed-hosts")]
+    pub allowed_hosts: Option<Vec<String>>,
+    /// A backend process to run alongside the server [default: None]
+    ///
+    /// This may only be configured via the `Trunk.toml` config file.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub backend_command: Option<ConfigOptsBackendCommand>,
+    /// Paths to serve as downloads, with a `Content-Disposition: attachment` header [default: None]
+    ///
+    /// This may only be configured via the `Trunk.toml` config file.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub download: Option<Vec<ConfigOptsDownload>>,
+    /// Serve the given prebuilt dir instead of the dist dir, without building or watching at all
+    /// [default: None]
+    #[structopt(long = "serve-dir", parse(from_os_str))]
+    pub serve_dir: Option<PathBuf>,
+    /// Run the initial build, but do not watch for changes or rebuild [default: false]
+    #[structopt(long = "no-watch")]
+    #[serde(default)]
+    pub no_watch: bool,
 }
 
 /// Config options for the serve system.
@@ -83,106 +334,265 @@ pub struct ConfigOptsProxy {
     /// When a value is specified, requests received on this URI will have this URI segment replaced
     /// with the URI of the `backend`.
     pub rewrite: Option<String>,
+    /// An optional maximum size in bytes of request & response bodies proxied to & from the
+    /// backend.
+    ///
+    /// Bodies declaring a larger size are rejected outright, and transfers which exceed the limit
+    /// while streaming are aborted.
+    pub max_body_bytes: Option<usize>,
+    /// An optional header name & value, given as `["name", "value"]`, which requests must carry
+    /// to be handled by this proxy.
+    ///
+    /// Header-matched proxies are evaluated before other proxies on the same path, which allows
+    /// multiple proxies to share a path while routing to different backends.
+    pub match_header: Option<(String, String)>,
+    /// An optional template for the path of proxied requests on the backend, used in place of
+    /// appending the request path to the backend URL's path.
+    ///
+    /// `{path}` is replaced with the request path following the listening prefix, or following
+    /// the `strip_prefix` if set, and `{1}`, `{2}` etc with the individual segments of that path.
+    /// E.G., a proxy listening on `/api/` with the template `/v2/{path}` proxies `/api/users/1` to
+    /// `/v2/users/1` on the backend.
+    ///
+    /// This is a separate key from `rewrite`, as `rewrite` already names the path on which the
+    /// proxy listens, which a template for the backend path can not replace.
+    pub path_template: Option<String>,
+    /// An optional regex applied to the full request path, whose matches are replaced with
+    /// `rewrite_replacement` to form the backend path, in place of appending the request path to
+    /// the backend URL's path.
+    ///
+    /// The replacement may refer to capture groups as `$1` or `$name`. E.G., the regex `^/v1/(.*)`
+    /// with the replacement `/api/$1` proxies `/v1/users` to `/api/users` on the backend.
+    pub rewrite_regex: Option<String>,
+    /// The replacement for matches of `rewrite_regex`, which must be set along with it.
+    pub rewrite_replacement: Option<String>,
+    /// An optional prefix stripped from the full request path, which is then sent to the backend
+    /// as is, rather than being appended to the backend URL's path.
+    ///
+    /// This lets the backend URL's path serve purely as the listening prefix. E.G., a proxy with
+    /// the backend `http://localhost:9000/api/` & the strip prefix `/api` proxies `/api/users` to
+    /// `http://localhost:9000/users`. The prefix is stripped before any `path_template` or
+    /// `rewrite_regex` is applied.
+    pub strip_prefix: Option<String>,
+}
+
+/// Config options for paths served as downloads.
+///
+/// NOTE WELL: this configuration type is only used when parsing the `Trunk.toml` config file. It
+/// is not intended to be configured via CLI or env vars.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigOptsDownload {
+    /// A glob matching the paths, relative to the public URL, of the files to serve as downloads.
+    ///
+    /// `*` & `?` match any run of characters & any single character within a path segment, and
+    /// `**` matches across segments, so `**/*.zip` matches zip files in any dir.
+    pub path: String,
+    /// The file name suggested to the browser for the download, which defaults to the name of the
+    /// requested file.
+    pub filename: Option<String>,
+}
+
+/// Config options for a backend process run alongside the server.
+///
+/// NOTE WELL: this configuration type is only used when parsing the `Trunk.toml` config file. It
+/// is not intended to be configured via CLI or env vars.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConfigOptsBackendCommand {
+    /// The command to run.
+    pub command: String,
+    /// Arguments to pass to the command.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// The working directory of the command, which defaults to the CWD.
+    pub cwd: Option<PathBuf>,
+    /// Additional environment variables to set for the command.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
 }
 
 /// A model of all potential configuration options for the Trunk CLI system.
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct ConfigOpts {
+    /// The version of the `Trunk.toml` schema which the config file was written against.
+    pub schema: Option<u32>,
+    /// Fail on unknown keys in the `Trunk.toml` config file, instead of only warning.
+    #[serde(default)]
+    pub strict: bool,
+    /// A parent `Trunk.toml` config file, relative to this one, from which this config inherits
+    /// all options which it does not set itself.
+    pub extends: Option<PathBuf>,
     pub build: Option<ConfigOptsBuild>,
     pub watch: Option<ConfigOptsWatch>,
     pub serve: Option<ConfigOptsServe>,
     pub clean: Option<ConfigOptsClean>,
     pub proxy: Option<Vec<ConfigOptsProxy>>,
+    /// Warnings raised while reading the config layers, for the caller to report.
+    #[serde(skip)]
+    pub warnings: Vec<String>,
 }
 
 impl ConfigOpts {
     /// Extract the runtime config for the build system based on all config layers.
-    pub async fn rtc_build(cli_build: ConfigOptsBuild, config: Option<PathBuf>) -> Result<Arc<RtcBuild>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+    pub async fn rtc_build(cli_build: ConfigOptsBuild, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<Arc<RtcBuild>> {
+        let base_layer = Self::file_and_env_layers(config, config_env, strict_config)?;
         let build_layer = Self::cli_opts_layer_build(cli_build, base_layer);
         let build_opts = build_layer.build.unwrap_or_default();
-        Ok(Arc::new(RtcBuild::new(build_opts)?))
+        Ok(Arc::new(RtcBuild::new(build_opts, build_layer.warnings)?))
     }
 
     /// Extract the runtime config for the watch system based on all config layers.
-    pub async fn rtc_watch(cli_build: ConfigOptsBuild, cli_watch: ConfigOptsWatch, config: Option<PathBuf>) -> Result<Arc<RtcWatch>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+    pub async fn rtc_watch(cli_build: ConfigOptsBuild, cli_watch: ConfigOptsWatch, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<Arc<RtcWatch>> {
+        let base_layer = Self::file_and_env_layers(config, config_env, strict_config)?;
         let build_layer = Self::cli_opts_layer_build(cli_build, base_layer);
         let watch_layer = Self::cli_opts_layer_watch(cli_watch, build_layer);
         let build_opts = watch_layer.build.unwrap_or_default();
         let watch_opts = watch_layer.watch.unwrap_or_default();
-        Ok(Arc::new(RtcWatch::new(build_opts, watch_opts)?))
+        Ok(Arc::new(RtcWatch::new(build_opts, watch_opts, watch_layer.warnings)?))
     }
 
     /// Extract the runtime config for the serve system based on all config layers.
     pub async fn rtc_serve(
-        cli_build: ConfigOptsBuild, cli_watch: ConfigOptsWatch, cli_serve: ConfigOptsServe, config: Option<PathBuf>,
+        cli_build: ConfigOptsBuild, cli_watch: ConfigOptsWatch, cli_serve: ConfigOptsServe, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool,
     ) -> Result<Arc<RtcServe>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+        let base_layer = Self::file_and_env_layers(config, config_env, strict_config)?;
         let build_layer = Self::cli_opts_layer_build(cli_build, base_layer);
         let watch_layer = Self::cli_opts_layer_watch(cli_watch, build_layer);
         let serve_layer = Self::cli_opts_layer_serve(cli_serve, watch_layer);
         let build_opts = serve_layer.build.unwrap_or_default();
         let watch_opts = serve_layer.watch.unwrap_or_default();
         let serve_opts = serve_layer.serve.unwrap_or_default();
-        Ok(Arc::new(RtcServe::new(build_opts, watch_opts, serve_opts, serve_layer.proxy)?))
+        Ok(Arc::new(RtcServe::new(build_opts, watch_opts, serve_opts, serve_layer.proxy, serve_layer.warnings)?))
     }
 
     /// Extract the runtime config for the clean system based on all config layers.
-    pub async fn rtc_clean(cli_clean: ConfigOptsClean, config: Option<PathBuf>) -> Result<Arc<RtcClean>> {
-        let base_layer = Self::file_and_env_layers(config)?;
+    pub async fn rtc_clean(cli_clean: ConfigOptsClean, config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<Arc<RtcClean>> {
+        let base_layer = Self::file_and_env_layers(config, config_env, strict_config)?;
         let clean_layer = Self::cli_opts_layer_clean(cli_clean, base_layer);
         let clean_opts = clean_layer.clean.unwrap_or_default();
-        Ok(Arc::new(RtcClean::new(clean_opts)?))
+        Ok(Arc::new(RtcClean::new(clean_opts, clean_layer.warnings)?))
     }
 
     /// Return the full configuration based on config file & environment variables.
-    pub async fn full(config: Option<PathBuf>) -> Result<Self> {
-        Self::file_and_env_layers(config)
+    pub async fn full(config: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<Self> {
+        Self::file_and_env_layers(config, config_env, strict_config)
     }
 
     fn cli_opts_layer_build(cli: ConfigOptsBuild, cfg_base: Self) -> Self {
         let opts = ConfigOptsBuild {
             target: cli.target,
+            targets: cli.targets,
             release: cli.release,
             dist: cli.dist,
             public_url: cli.public_url,
+            relative_urls: cli.relative_urls,
+            base_href: cli.base_href,
+            strict_base_href: cli.strict_base_href,
+            lock_wait: cli.lock_wait,
+            dry_run: cli.dry_run,
+            cargo_command: cli.cargo_command,
+            cargo_args: cli.cargo_args,
+            rustflags: cli.rustflags,
+            nomodule_fallback_path: cli.nomodule_fallback_path,
+            nomodule_fallback_inline: cli.nomodule_fallback_inline,
+            follow_symlinks: cli.follow_symlinks,
+            release_size_report: cli.release_size_report,
+            cargo_verbose: cli.cargo_verbose,
+            cargo_quiet: cli.cargo_quiet,
+            inline_max_size: cli.inline_max_size,
+            hash_algo: cli.hash_algo,
+            emit_deps: cli.emit_deps,
+            jobs: cli.jobs,
+            stamp: cli.stamp,
+            panic_abort: cli.panic_abort,
+            deny_warnings: cli.deny_warnings,
+            github_pages: cli.github_pages,
+            fs_retries: cli.fs_retries,
+            bindgen_out_dir: cli.bindgen_out_dir,
+            assets_dir: cli.assets_dir,
+            wasm_bindgen_version: cli.wasm_bindgen_version,
+            wasm_opt: cli.wasm_opt,
+            emit_unhashed_aliases: cli.emit_unhashed_aliases,
+            source_maps: cli.source_maps,
+            prune_stale: cli.prune_stale,
+            pattern_script: cli.pattern_script,
+            pattern_preload: cli.pattern_preload,
+            minify_html: cli.minify_html,
+            require_rust_link: cli.require_rust_link,
+            env: cli.env,
         };
         let cfg_build = ConfigOpts {
+            schema: None,
+            strict: false,
+            extends: None,
             build: Some(opts),
             watch: None,
             serve: None,
             clean: None,
             proxy: None,
+            warnings: vec![],
         };
         Self::merge(cfg_base, cfg_build)
     }
 
     fn cli_opts_layer_watch(cli: ConfigOptsWatch, cfg_base: Self) -> Self {
-        let opts = ConfigOptsWatch { ignore: cli.ignore };
+        let opts = ConfigOptsWatch {
+            ignore: cli.ignore,
+            rebuild_history: cli.rebuild_history,
+            watch_root: cli.watch_root,
+            follow_symlinks: cli.follow_symlinks,
+            path_deps: cli.path_deps,
+            ignore_editor_temp: cli.ignore_editor_temp,
+            ignore_hidden: cli.ignore_hidden,
+            poll: cli.poll,
+            poll_ignore_extra: cli.poll_ignore_extra,
+        };
         let cfg = ConfigOpts {
+            schema: None,
+            strict: false,
+            extends: None,
             build: None,
             watch: Some(opts),
             serve: None,
             clean: None,
             proxy: None,
+            warnings: vec![],
         };
         Self::merge(cfg_base, cfg)
     }
 
     fn cli_opts_layer_serve(cli: ConfigOptsServe, cfg_base: Self) -> Self {
         let opts = ConfigOptsServe {
+            address: cli.address,
             port: cli.port,
+            unix_socket: cli.unix_socket,
+            backlog: cli.backlog,
             open: cli.open,
+            open_path: cli.open_path,
+            browser: cli.browser,
             proxy_backend: cli.proxy_backend,
             proxy_rewrite: cli.proxy_rewrite,
+            proxy_max_body_bytes: cli.proxy_max_body_bytes,
+            proxy_passthrough_404: cli.proxy_passthrough_404,
+            directory_listing: cli.directory_listing,
+            serve_on_build_error: cli.serve_on_build_error,
+            serve_last_good: cli.serve_last_good,
+            basic_auth: cli.basic_auth,
+            allowed_hosts: cli.allowed_hosts,
+            backend_command: cli.backend_command,
+            download: cli.download,
+            serve_dir: cli.serve_dir,
+            no_watch: cli.no_watch,
         };
         let cfg = ConfigOpts {
+            schema: None,
+            strict: false,
+            extends: None,
             build: None,
             watch: None,
             serve: Some(opts),
             clean: None,
             proxy: None,
+            warnings: vec![],
         };
         Self::merge(cfg_base, cfg)
     }
@@ -193,18 +603,22 @@ impl ConfigOpts {
             cargo: cli.cargo,
         };
         let cfg = ConfigOpts {
+            schema: None,
+            strict: false,
+            extends: None,
             build: None,
             watch: None,
             serve: None,
             clean: Some(opts),
             proxy: None,
+            warnings: vec![],
         };
         Self::merge(cfg_base, cfg)
     }
 
-    fn file_and_env_layers(path: Option<PathBuf>) -> Result<Self> {
-        let toml_cfg = Self::from_file(path)?;
-        let env_cfg = Self::from_env().context("error reading trunk env var config")?;
+    fn file_and_env_layers(path: Option<PathBuf>, config_env: Option<String>, strict_config: bool) -> Result<Self> {
+        let toml_cfg = Self::from_file(path, strict_config)?;
+        let env_cfg = Self::from_env(config_env.as_deref()).context("error reading trunk env var config")?;
         let cfg = Self::merge(toml_cfg, env_cfg);
         Ok(cfg)
     }
@@ -213,18 +627,49 @@ impl ConfigOpts {
     ///
     /// NOTE WELL: any paths specified in a Trunk.toml file must be interpreted as being relative
     /// to the file itself.
-    fn from_file(path: Option<PathBuf>) -> Result<Self> {
+    fn from_file(path: Option<PathBuf>, strict_config: bool) -> Result<Self> {
         let mut path = path.unwrap_or_else(|| "Trunk.toml".into());
         if !path.exists() {
             return Ok(Default::default());
         }
+        let mut warnings = vec![];
         if !path.is_absolute() {
-            path = path
-                .canonicalize()
-                .with_context(|| format!("error getting canonical path to Trunk config file {:?}", &path))?;
+            // Canonicalization may fail on some file systems even though the file is readable, in
+            // which case relative paths are resolved against the CWD instead.
+            path = match path.canonicalize() {
+                Ok(canonical) => canonical,
+                Err(err) => {
+                    let cwd = std::env::current_dir().context("error getting CWD to resolve Trunk config file path")?;
+                    warnings.push(format!(
+                        "error getting canonical path to Trunk config file {:?} ({}); resolving relative to the CWD",
+                        &path, err
+                    ));
+                    cwd.join(&path)
+                }
+            };
         }
-        let cfg_bytes = std::fs::read(&path).context("error reading config file")?;
-        let mut cfg: Self = toml::from_slice(&cfg_bytes).context("error reading config file contents as TOML data")?;
+        let mut cfg = Self::from_file_chain(path, strict_config, &mut vec![])?;
+        warnings.append(&mut cfg.warnings);
+        cfg.warnings = warnings;
+        Ok(cfg)
+    }
+
+    /// Read config from the `Trunk.toml` file at the given path, merged over the config of the
+    /// file it extends, if any.
+    ///
+    /// `chain` holds the files which are already being read further down the `extends` chain, in
+    /// order to guard against cyclic `extends`.
+    fn from_file_chain(path: PathBuf, strict_config: bool, chain: &mut Vec<PathBuf>) -> Result<Self> {
+        if chain.contains(&path) {
+            let cycle: Vec<_> = chain.iter().chain(Some(&path)).map(|path| format!("{:?}", path)).collect();
+            bail!("cyclic `extends` in Trunk config files: {}", cycle.join(" -> "));
+        }
+        chain.push(path.clone());
+        let cfg_bytes = std::fs::read(&path).with_context(|| format!("error reading config file {:?}", &path))?;
+        let cfg_value: toml::Value = toml::from_slice(&cfg_bytes).context("error reading config file contents as TOML data")?;
+        let warnings = schema::check(&path, &cfg_value, strict_config)?;
+        let mut cfg: Self = cfg_value.try_into().context("error reading config file contents as TOML data")?;
+        cfg.warnings = warnings;
         if let Some(parent) = path.parent() {
             cfg.build.iter_mut().for_each(|build| {
                 build.target.iter_mut().for_each(|target| {
@@ -232,11 +677,21 @@ impl ConfigOpts {
                         *target = parent.join(&target);
                     }
                 });
+                build.targets.iter_mut().flatten().for_each(|target| {
+                    if !target.is_absolute() {
+                        *target = parent.join(&target);
+                    }
+                });
                 build.dist.iter_mut().for_each(|dist| {
                     if !dist.is_absolute() {
                         *dist = parent.join(&dist);
                     }
                 });
+                build.emit_deps.iter_mut().for_each(|emit_deps| {
+                    if !emit_deps.is_absolute() {
+                        *emit_deps = parent.join(&emit_deps);
+                    }
+                });
             });
             cfg.watch.iter_mut().for_each(|watch| {
                 watch.ignore.iter_mut().for_each(|ignores_vec| {
@@ -246,6 +701,30 @@ impl ConfigOpts {
                         }
                     });
                 });
+                watch.watch_root.iter_mut().for_each(|watch_root| {
+                    if !watch_root.is_absolute() {
+                        *watch_root = parent.join(&watch_root);
+                    }
+                });
+            });
+            cfg.serve.iter_mut().for_each(|serve| {
+                serve.unix_socket.iter_mut().for_each(|unix_socket| {
+                    if !unix_socket.is_absolute() {
+                        *unix_socket = parent.join(&unix_socket);
+                    }
+                });
+                serve.serve_dir.iter_mut().for_each(|serve_dir| {
+                    if !serve_dir.is_absolute() {
+                        *serve_dir = parent.join(&serve_dir);
+                    }
+                });
+                serve.backend_command.iter_mut().for_each(|backend| {
+                    backend.cwd.iter_mut().for_each(|cwd| {
+                        if !cwd.is_absolute() {
+                            *cwd = parent.join(&cwd);
+                        }
+                    });
+                });
             });
             cfg.clean.iter_mut().for_each(|clean| {
                 clean.dist.iter_mut().for_each(|dist| {
@@ -254,37 +733,138 @@ impl ConfigOpts {
                     }
                 });
             });
+            cfg.extends.iter_mut().for_each(|extends| {
+                if !extends.is_absolute() {
+                    *extends = parent.join(&extends);
+                }
+            });
+        }
+        match cfg.extends.take() {
+            Some(extends) => {
+                let extends = extends
+                    .canonicalize()
+                    .with_context(|| format!("error getting canonical path to config file {:?} extended by {:?}", &extends, &path))?;
+                let base = Self::from_file_chain(extends, strict_config, chain)?;
+                Ok(Self::merge(base, cfg))
+            }
+            None => Ok(cfg),
         }
-        Ok(cfg)
     }
 
-    fn from_env() -> Result<Self> {
-        let build: ConfigOptsBuild = envy::prefixed("TRUNK_BUILD_").from_env()?;
-        let watch: ConfigOptsWatch = envy::prefixed("TRUNK_WATCH_").from_env()?;
-        let serve: ConfigOptsServe = envy::prefixed("TRUNK_SERVE_").from_env()?;
-        let clean: ConfigOptsClean = envy::prefixed("TRUNK_CLEAN_").from_env()?;
+    /// Read config from env vars of the form `TRUNK_<SECTION>_<ITEM>`, or of the form
+    /// `TRUNK_<ENV>_<SECTION>_<ITEM>` when a config env is given.
+    fn from_env(config_env: Option<&str>) -> Result<Self> {
+        let prefix = match config_env {
+            Some(env) => format!("TRUNK_{}_", env.to_uppercase().replace('-', "_")),
+            None => String::from("TRUNK_"),
+        };
+        let build: ConfigOptsBuild = envy::prefixed(format!("{}BUILD_", prefix)).from_env()?;
+        let watch: ConfigOptsWatch = envy::prefixed(format!("{}WATCH_", prefix)).from_env()?;
+        let serve: ConfigOptsServe = envy::prefixed(format!("{}SERVE_", prefix)).from_env()?;
+        let clean: ConfigOptsClean = envy::prefixed(format!("{}CLEAN_", prefix)).from_env()?;
         Ok(ConfigOpts {
+            schema: None,
+            strict: false,
+            extends: None,
             build: Some(build),
             watch: Some(watch),
             serve: Some(serve),
             clean: Some(clean),
             proxy: None,
+            warnings: vec![],
         })
     }
 
     /// Merge the given layers, where the `greater` layer takes precedence.
     fn merge(mut lesser: Self, mut greater: Self) -> Self {
+        lesser.warnings.append(&mut greater.warnings);
+        greater.warnings = std::mem::take(&mut lesser.warnings);
         greater.build = match (lesser.build.take(), greater.build.take()) {
             (None, None) => None,
             (Some(val), None) | (None, Some(val)) => Some(val),
             (Some(l), Some(mut g)) => {
                 g.target = g.target.or(l.target);
+                g.targets = g.targets.or(l.targets);
                 g.dist = g.dist.or(l.dist);
                 g.public_url = g.public_url.or(l.public_url);
+                g.base_href = g.base_href.or(l.base_href);
+                g.cargo_command = g.cargo_command.or(l.cargo_command);
+                g.cargo_args = g.cargo_args.or(l.cargo_args);
+                g.rustflags = g.rustflags.or(l.rustflags);
+                g.nomodule_fallback_path = g.nomodule_fallback_path.or(l.nomodule_fallback_path);
+                g.nomodule_fallback_inline = g.nomodule_fallback_inline.or(l.nomodule_fallback_inline);
+                g.follow_symlinks = g.follow_symlinks.or(l.follow_symlinks);
+                g.cargo_verbose = g.cargo_verbose.or(l.cargo_verbose);
+                g.inline_max_size = g.inline_max_size.or(l.inline_max_size);
+                g.hash_algo = g.hash_algo.or(l.hash_algo);
+                g.emit_deps = g.emit_deps.or(l.emit_deps);
+                g.jobs = g.jobs.or(l.jobs);
+                g.fs_retries = g.fs_retries.or(l.fs_retries);
+                g.bindgen_out_dir = g.bindgen_out_dir.or(l.bindgen_out_dir);
+                g.assets_dir = g.assets_dir.or(l.assets_dir);
+                g.wasm_bindgen_version = g.wasm_bindgen_version.or(l.wasm_bindgen_version);
+                g.wasm_opt = g.wasm_opt.or(l.wasm_opt);
+                g.source_maps = g.source_maps.or(l.source_maps);
+                g.prune_stale = g.prune_stale.or(l.prune_stale);
+                g.pattern_script = g.pattern_script.or(l.pattern_script);
+                g.pattern_preload = g.pattern_preload.or(l.pattern_preload);
+                g.env = g.env.or(l.env);
                 // NOTE: this can not be disabled in the cascade.
                 if l.release {
                     g.release = true
                 }
+                // NOTE: this can not be disabled in the cascade.
+                if l.strict_base_href {
+                    g.strict_base_href = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.relative_urls {
+                    g.relative_urls = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.emit_unhashed_aliases {
+                    g.emit_unhashed_aliases = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.lock_wait {
+                    g.lock_wait = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.dry_run {
+                    g.dry_run = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.stamp {
+                    g.stamp = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.panic_abort {
+                    g.panic_abort = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.deny_warnings {
+                    g.deny_warnings = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.github_pages {
+                    g.github_pages = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.minify_html {
+                    g.minify_html = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.require_rust_link {
+                    g.require_rust_link = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.release_size_report {
+                    g.release_size_report = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.cargo_quiet {
+                    g.cargo_quiet = true
+                }
                 Some(g)
             }
         };
@@ -293,6 +873,14 @@ impl ConfigOpts {
             (Some(val), None) | (None, Some(val)) => Some(val),
             (Some(l), Some(mut g)) => {
                 g.ignore = g.ignore.or(l.ignore);
+                g.rebuild_history = g.rebuild_history.or(l.rebuild_history);
+                g.watch_root = g.watch_root.or(l.watch_root);
+                g.follow_symlinks = g.follow_symlinks.or(l.follow_symlinks);
+                g.path_deps = g.path_deps.or(l.path_deps);
+                g.ignore_editor_temp = g.ignore_editor_temp.or(l.ignore_editor_temp);
+                g.ignore_hidden = g.ignore_hidden.or(l.ignore_hidden);
+                g.poll = g.poll.or(l.poll);
+                g.poll_ignore_extra = g.poll_ignore_extra.or(l.poll_ignore_extra);
                 Some(g)
             }
         };
@@ -302,11 +890,39 @@ impl ConfigOpts {
             (Some(l), Some(mut g)) => {
                 g.proxy_backend = g.proxy_backend.or(l.proxy_backend);
                 g.proxy_rewrite = g.proxy_rewrite.or(l.proxy_rewrite);
+                g.proxy_max_body_bytes = g.proxy_max_body_bytes.or(l.proxy_max_body_bytes);
+                g.proxy_passthrough_404 = g.proxy_passthrough_404.or(l.proxy_passthrough_404);
+                g.address = g.address.or(l.address);
                 g.port = g.port.or(l.port);
+                g.open_path = g.open_path.or(l.open_path);
+                g.browser = g.browser.or(l.browser);
+                g.unix_socket = g.unix_socket.or(l.unix_socket);
+                g.backlog = g.backlog.or(l.backlog);
+                g.serve_dir = g.serve_dir.or(l.serve_dir);
+                g.basic_auth = g.basic_auth.or(l.basic_auth);
+                g.allowed_hosts = g.allowed_hosts.or(l.allowed_hosts);
+                g.backend_command = g.backend_command.or(l.backend_command);
+                g.download = g.download.or(l.download);
                 // NOTE: this can not be disabled in the cascade.
                 if l.open {
                     g.open = true
                 }
+                // NOTE: this can not be disabled in the cascade.
+                if l.directory_listing {
+                    g.directory_listing = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.serve_on_build_error {
+                    g.serve_on_build_error = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.serve_last_good {
+                    g.serve_last_good = true
+                }
+                // NOTE: this can not be disabled in the cascade.
+                if l.no_watch {
+                    g.no_watch = true
+                }
                 Some(g)
             }
         };
@@ -322,6 +938,9 @@ impl ConfigOpts {
                 Some(g)
             }
         };
+        greater.schema = greater.schema.or(lesser.schema);
+        greater.strict = greater.strict || lesser.strict;
+        greater.extends = greater.extends.or(lesser.extends);
         greater.proxy = match (lesser.proxy.take(), greater.proxy.take()) {
             (None, None) => None,
             (Some(val), None) | (None, Some(val)) => Some(val),
@@ -330,3 +949,57 @@ impl ConfigOpts {
         greater
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn extends_merges_over_the_parent_config() {
+        let dir = TestDir::new(
+            "extends",
+            &[
+                ("Trunk.base.toml", "[build]\ndist = \"base-dist\"\npublic_url = \"/base/\"\n"),
+                ("app/Trunk.toml", "extends = \"../Trunk.base.toml\"\n[build]\npublic_url = \"/app/\"\n"),
+            ],
+        );
+        let cfg = ConfigOpts::from_file(Some(dir.join("app/Trunk.toml")), false).unwrap();
+        let build = cfg.build.unwrap();
+        // Paths in the parent config are relative to the parent config file.
+        assert_eq!(build.dist, Some(dir.join("base-dist")));
+        assert_eq!(build.public_url.as_deref(), Some("/app/"));
+    }
+
+    #[test]
+    fn emit_deps_is_relative_to_the_config_file() {
+        let dir = TestDir::new("emit-deps-config", &[("app/Trunk.toml", "[build]\nemit_deps = \"build/trunk.d\"\n")]);
+        let cfg = ConfigOpts::from_file(Some(dir.join("app/Trunk.toml")), false).unwrap();
+        assert_eq!(cfg.build.unwrap().emit_deps, Some(dir.join("app/build/trunk.d")));
+    }
+
+    #[test]
+    fn config_env_selects_the_env_var_prefix() {
+        std::env::set_var("TRUNK_UNIT_TEST_ENV_BUILD_PUBLIC_URL", "/staging/");
+        let cfg = ConfigOpts::from_env(Some("unit-test-env")).unwrap();
+        assert_eq!(cfg.build.unwrap().public_url.as_deref(), Some("/staging/"));
+        // Vars of other envs are not picked up.
+        let cfg = ConfigOpts::from_env(Some("unit-test-other-env")).unwrap();
+        assert_eq!(cfg.build.unwrap().public_url, None);
+        std::env::remove_var("TRUNK_UNIT_TEST_ENV_BUILD_PUBLIC_URL");
+    }
+
+    #[test]
+    fn cyclic_extends_is_an_error() {
+        let dir = TestDir::new(
+            "extends-cycle",
+            &[
+                ("Trunk.base.toml", "extends = \"app/Trunk.toml\"\n"),
+                ("app/Trunk.toml", "extends = \"../Trunk.base.toml\"\n"),
+            ],
+        );
+        let err = ConfigOpts::from_file(Some(dir.join("app/Trunk.toml")), false).unwrap_err().to_string();
+        assert!(err.starts_with("cyclic `extends` in Trunk config files"), "{}", err);
+        assert!(err.contains("Trunk.base.toml"), "{}", err);
+    }
+}
@@ -1,28 +1,123 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, ensure, Context, Result};
 use http_types::Url;
+use regex::Regex;
 
-use crate::config::{ConfigOptsBuild, ConfigOptsClean, ConfigOptsProxy, ConfigOptsServe, ConfigOptsWatch};
+use crate::common::{is_executable_available, HashAlgo};
+use crate::config::{ConfigOptsBackendCommand, ConfigOptsBuild, ConfigOptsClean, ConfigOptsProxy, ConfigOptsServe, ConfigOptsWatch};
+
+/// Env vars which Trunk relies upon for locating cargo's build output, and which may therefore not
+/// be overridden via `build.env`.
+const RESERVED_CARGO_ENV_VARS: &[&str] = &["CARGO_TARGET_DIR", "CARGO_BUILD_TARGET", "CARGO_BUILD_TARGET_DIR"];
+
+/// Conventional locations of the source HTML file, searched in order when no target is configured.
+const TARGET_SEARCH_PATHS: &[&str] = &["index.html", "src/index.html", "www/index.html", "static/index.html", "public/index.html"];
+
+/// The optimization levels accepted by wasm-opt, each passed as `-O<level>`.
+const WASM_OPT_LEVELS: &[&str] = &["0", "1", "2", "3", "4", "s", "z"];
+
+/// The maximum number of retries of transient filesystem failures, which bounds the time spent
+/// retrying a failure which turns out to be persistent.
+const MAX_FS_RETRIES: u32 = 10;
 
 /// Runtime config for the build system.
 #[derive(Clone, Debug)]
 pub struct RtcBuild {
     /// The index HTML file to drive the bundling process.
     pub target: PathBuf,
+    /// Additional HTML files to build alongside the target, each emitted under its own file name.
+    pub targets: Vec<PathBuf>,
     /// Build in release mode.
     pub release: bool,
     /// The output dir for all final assets.
     pub dist: PathBuf,
     /// The public URL from which assets are to be served.
     pub public_url: String,
+    /// Reference assets from the output HTML by `./`-relative URLs in place of the public URL.
+    pub relative_urls: bool,
+    /// The href of a `<base>` element to inject into the output HTML.
+    pub base_href: Option<String>,
+    /// Wait for a concurrent build of the same dist dir to finish, instead of failing.
+    pub lock_wait: bool,
+    /// Only print what the build would emit, without running cargo or writing to the dist dir.
+    pub dry_run: bool,
+    /// The cargo command used for building the Rust app, which may be a cargo wrapper.
+    pub cargo_command: String,
+    /// Additional arguments to append to the cargo build invocation.
+    pub cargo_args: Vec<String>,
+    /// Additional flags to pass to rustc via `RUSTFLAGS`, appended to any ambient `RUSTFLAGS`.
+    pub rustflags: Vec<String>,
+    /// A script file, relative to the source HTML file, whose content is inlined into a
+    /// `<script nomodule>` fallback for browsers without ES module support.
+    pub nomodule_fallback_path: Option<PathBuf>,
+    /// Inline script content for a `<script nomodule>` fallback.
+    pub nomodule_fallback_inline: Option<String>,
+    /// Follow symlinks when copying directories into the dist dir.
+    pub follow_symlinks: bool,
+    /// Print a report of per-asset size changes compared to the previous build.
+    pub release_size_report: bool,
+    /// Cargo's verbosity level, passed to cargo as that many `-v` flags.
+    pub cargo_verbose: u8,
+    /// Pass the `--quiet` flag to cargo.
+    pub cargo_quiet: bool,
+    /// The maximum size in bytes of `rel="inline"` assets which will be inlined into the HTML.
+    pub inline_max_size: Option<u64>,
+    /// The hashing algorithm used for content fingerprints.
+    pub hash_algo: HashAlgo,
+    /// The path to which a Makefile-style deps file for the build will be written.
+    pub emit_deps: Option<PathBuf>,
+    /// The maximum number of asset pipelines to run concurrently.
+    pub jobs: usize,
+    /// Inject a `<meta name="trunk-build">` tag holding the build time & app version into the output HTML.
+    pub stamp: bool,
+    /// Build release mode Rust apps with `-C panic=abort`.
+    pub panic_abort: bool,
+    /// Treat warnings of the Rust app's cargo build as errors.
+    pub deny_warnings: bool,
+    /// Write a `.nojekyll` file & a `404.html` copy of the output `index.html` for GitHub Pages.
+    pub github_pages: bool,
+    /// The number of times to retry transiently failing writes of assets to the dist dir.
+    pub fs_retries: u32,
+    /// A dir relative to the dist dir into which the wasm-bindgen output of the Rust app is emitted.
+    pub bindgen_out_dir: Option<PathBuf>,
+    /// A dir relative to the dist dir into which hashed assets are emitted.
+    pub assets_dir: Option<PathBuf>,
+    /// The exact version of wasm-bindgen required.
+    pub wasm_bindgen_version: Option<String>,
+    /// The level at which wasm-opt optimizes the Rust app's wasm, or `None` to skip wasm-opt.
+    pub wasm_opt: Option<String>,
+    /// Alongside each hashed output file, also write a copy under its unhashed name.
+    pub emit_unhashed_aliases: bool,
+    /// Emit source maps & debug info into the dist dir.
+    pub source_maps: bool,
+    /// Remove stale hashed files of previous builds from the dist dir after each build.
+    pub prune_stale: bool,
+    /// A template for the script which loads the Rust app, in place of the default module script.
+    pub pattern_script: Option<String>,
+    /// A template for elements appended to the head of the output HTML, such as preload links.
+    pub pattern_preload: Option<String>,
+    /// Minify the output `index.html` of release builds.
+    pub minify_html: bool,
+    /// Fail the build when the source HTML has no Rust app link.
+    pub require_rust_link: bool,
+    /// Additional env vars to set for the cargo build, with all env var references substituted.
+    pub env: HashMap<String, String>,
+    /// Warnings raised while reading the config, reported once the build system is created.
+    pub warnings: Vec<String>,
 }
 
 impl RtcBuild {
-    /// Construct a new instance.
-    pub(super) fn new(opts: ConfigOptsBuild) -> Result<Self> {
-        let pre_target = opts.target.clone().unwrap_or_else(|| "index.html".into());
+    /// Construct a new instance, along with the given warnings raised by the config layers.
+    pub(super) fn new(opts: ConfigOptsBuild, mut warnings: Vec<String>) -> Result<Self> {
+        let pre_target = match opts.target.clone() {
+            Some(target) => target,
+            None => discover_target(Path::new(""), &mut warnings)?,
+        };
         let target = pre_target
             .canonicalize()
             .with_context(|| format!("error getting canonical path to source HTML file {:?}", &pre_target))?;
@@ -30,13 +125,180 @@ impl RtcBuild {
             .parent()
             .map(|path| path.to_owned())
             .unwrap_or_else(|| PathBuf::from(std::path::MAIN_SEPARATOR.to_string()));
+        let mut targets: Vec<PathBuf> = vec![];
+        for pre_target in opts.targets.unwrap_or_default() {
+            let path = pre_target
+                .canonicalize()
+                .with_context(|| format!("error getting canonical path to source HTML file {:?}", &pre_target))?;
+            if path == target || targets.contains(&path) {
+                continue;
+            }
+            let name = path.file_name();
+            ensure!(
+                name.is_some() && name != Some(std::ffi::OsStr::new("index.html")) && targets.iter().all(|other| other.file_name() != name),
+                "target HTML file {:?} would be emitted under the same file name as another target",
+                &pre_target
+            );
+            targets.push(path);
+        }
+        if let Some(dir) = &opts.bindgen_out_dir {
+            ensure!(
+                dir.components().all(|component| matches!(component, Component::Normal(_))),
+                "the `bindgen_out_dir` option must be a relative path within the dist dir, got {:?}",
+                dir
+            );
+        }
+        if let Some(dir) = &opts.assets_dir {
+            ensure!(
+                dir.components().all(|component| matches!(component, Component::Normal(_))),
+                "the `assets_dir` option must be a relative path within the dist dir, got {:?}",
+                dir
+            );
+        }
+        let public_url = opts.public_url.unwrap_or_else(|| "/".into());
+        if let Some(base_href) = &opts.base_href {
+            check_base_href(base_href, if opts.relative_urls { "./" } else { &public_url }, opts.strict_base_href, &mut warnings)?;
+        }
+        let cargo_command = opts.cargo_command.unwrap_or_else(|| "cargo".into());
+        ensure!(
+            is_executable_available(&cargo_command),
+            "cargo command {:?} could not be found; ensure it is installed & available on your PATH",
+            &cargo_command
+        );
+        ensure!(
+            opts.nomodule_fallback_path.is_none() || opts.nomodule_fallback_inline.is_none(),
+            "the `nomodule_fallback_path` & `nomodule_fallback_inline` options are mutually exclusive"
+        );
+        let wasm_opt = match opts.wasm_opt.as_deref() {
+            None | Some("off") => None,
+            Some(level) => {
+                ensure!(
+                    WASM_OPT_LEVELS.contains(&level),
+                    "invalid `wasm_opt` level {:?}; expected one of `off`, {}",
+                    level,
+                    WASM_OPT_LEVELS.iter().map(|level| format!("`{}`", level)).collect::<Vec<_>>().join(", ")
+                );
+                Some(level.to_string())
+            }
+        };
+        let fs_retries = opts.fs_retries.unwrap_or(3);
+        ensure!(
+            fs_retries <= MAX_FS_RETRIES,
+            "the `fs_retries` build option may be at most {}",
+            MAX_FS_RETRIES
+        );
+        let cargo_verbose = opts.cargo_verbose.unwrap_or_default();
+        ensure!(
+            !(opts.cargo_quiet && cargo_verbose > 0),
+            "the `cargo_quiet` & `cargo_verbose` options can not be used together"
+        );
+        let jobs = opts.jobs.unwrap_or_else(num_cpus::get);
+        ensure!(jobs > 0, "the `jobs` option must be greater than 0");
+        let env = opts
+            .env
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, val)| {
+                ensure!(
+                    !RESERVED_CARGO_ENV_VARS.contains(&key.as_str()),
+                    "the env var {:?} is managed by Trunk & may not be set via `build.env`",
+                    key
+                );
+                Ok((key, substitute_env_vars(&val)?))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
         Ok(Self {
             target,
+            targets,
             release: opts.release,
             dist: opts.dist.unwrap_or_else(|| target_parent_dir.join("dist")),
-            public_url: opts.public_url.unwrap_or_else(|| "/".into()),
+            public_url,
+            relative_urls: opts.relative_urls,
+            base_href: opts.base_href,
+            lock_wait: opts.lock_wait,
+            dry_run: opts.dry_run,
+            cargo_command,
+            cargo_args: opts.cargo_args.unwrap_or_default(),
+            rustflags: opts.rustflags.unwrap_or_default(),
+            nomodule_fallback_path: opts.nomodule_fallback_path,
+            nomodule_fallback_inline: opts.nomodule_fallback_inline,
+            follow_symlinks: opts.follow_symlinks.unwrap_or(true),
+            release_size_report: opts.release_size_report,
+            cargo_verbose,
+            cargo_quiet: opts.cargo_quiet,
+            inline_max_size: opts.inline_max_size,
+            hash_algo: opts.hash_algo.unwrap_or_default(),
+            emit_deps: opts.emit_deps,
+            jobs,
+            stamp: opts.stamp,
+            panic_abort: opts.panic_abort,
+            deny_warnings: opts.deny_warnings,
+            github_pages: opts.github_pages,
+            fs_retries,
+            bindgen_out_dir: opts.bindgen_out_dir,
+            assets_dir: opts.assets_dir,
+            wasm_bindgen_version: opts.wasm_bindgen_version,
+            wasm_opt,
+            emit_unhashed_aliases: opts.emit_unhashed_aliases,
+            source_maps: opts.source_maps.unwrap_or(!opts.release),
+            prune_stale: opts.prune_stale.unwrap_or(true),
+            pattern_script: opts.pattern_script,
+            pattern_preload: opts.pattern_preload,
+            minify_html: opts.minify_html,
+            require_rust_link: opts.require_rust_link,
+            env,
+            warnings,
         })
     }
+
+    /// The base URL by which the output HTML references assets, which is the public URL unless
+    /// `relative_urls` is enabled. The public URL is still the path from which `trunk serve`
+    /// serves the dist dir.
+    pub fn asset_base(&self) -> &str {
+        if self.relative_urls {
+            "./"
+        } else {
+            &self.public_url
+        }
+    }
+
+    /// The dir into which hashed assets are emitted, which is the dist dir unless an `assets_dir`
+    /// is configured.
+    pub fn assets_out_dir(&self) -> PathBuf {
+        match &self.assets_dir {
+            Some(dir) => self.dist.join(dir),
+            None => self.dist.clone(),
+        }
+    }
+
+    /// The path prefix, relative to the dist dir & using `/` separators, of hashed assets, which
+    /// is empty unless an `assets_dir` is configured.
+    pub fn assets_prefix(&self) -> String {
+        dist_path_prefix(self.assets_dir.as_deref())
+    }
+
+    /// The URL by which the output HTML references the hashed asset of the given file name.
+    pub fn asset_url(&self, file_name: &str) -> String {
+        format!("{}{}{}", self.asset_base(), self.assets_prefix(), file_name)
+    }
+
+    /// The dir, relative to the dist dir, into which the Rust app's wasm-bindgen output is
+    /// emitted, which is the `bindgen_out_dir`, falling back to the `assets_dir`.
+    pub fn rust_app_out_dir(&self) -> Option<&Path> {
+        self.bindgen_out_dir.as_deref().or(self.assets_dir.as_deref())
+    }
+}
+
+/// Build the `/` separated path prefix of the given dir relative to the dist dir, which is empty
+/// for the dist dir itself.
+pub fn dist_path_prefix(dir: Option<&Path>) -> String {
+    match dir {
+        Some(dir) => dir
+            .components()
+            .map(|component| format!("{}/", component.as_os_str().to_string_lossy()))
+            .collect(),
+        None => String::new(),
+    }
 }
 
 /// Runtime config for the watch system.
@@ -46,14 +308,49 @@ pub struct RtcWatch {
     pub build: Arc<RtcBuild>,
     /// Additional paths to ignore.
     pub ignore: Vec<PathBuf>,
+    /// The number of recent rebuild timings to retain & report after each rebuild.
+    pub rebuild_history: usize,
+    /// The base directory to watch for changes.
+    pub watch_root: PathBuf,
+    /// Follow symlinks when watching for changes.
+    pub follow_symlinks: bool,
+    /// Also watch the sources of local path dependencies which live outside of the watch root.
+    pub path_deps: bool,
+    /// Ignore changes to the temp & swap files of common editors.
+    pub ignore_editor_temp: bool,
+    /// Ignore changes to hidden files & dirs, whose names start with a `.`.
+    pub ignore_hidden: bool,
+    /// The interval on which to poll the file system for changes, instead of relying on native
+    /// file system events.
+    pub poll: Option<Duration>,
+    /// The paths which are never polled in poll mode, in addition to the ignored paths.
+    pub poll_ignore: Vec<PathBuf>,
 }
 
 impl RtcWatch {
-    pub(super) fn new(build_opts: ConfigOptsBuild, opts: ConfigOptsWatch) -> Result<Self> {
-        let build = Arc::new(RtcBuild::new(build_opts)?);
+    pub(super) fn new(build_opts: ConfigOptsBuild, opts: ConfigOptsWatch, warnings: Vec<String>) -> Result<Self> {
+        let build = Arc::new(RtcBuild::new(build_opts, warnings)?);
+        ensure!(!build.dry_run, "the `dry_run` build option is only supported by `trunk build`");
+        let pre_watch_root = opts.watch_root.unwrap_or_else(|| ".".into());
+        let watch_root = pre_watch_root
+            .canonicalize()
+            .with_context(|| format!("error getting canonical path to watch root {:?}", &pre_watch_root))?;
         Ok(Self {
             build,
             ignore: opts.ignore.unwrap_or_default(),
+            rebuild_history: opts.rebuild_history.unwrap_or_default(),
+            follow_symlinks: opts.follow_symlinks.unwrap_or(true),
+            path_deps: opts.path_deps.unwrap_or(true),
+            ignore_editor_temp: opts.ignore_editor_temp.unwrap_or(true),
+            ignore_hidden: opts.ignore_hidden.unwrap_or(true),
+            poll: opts.poll.map(Duration::from_millis),
+            poll_ignore: opts
+                .poll_ignore_extra
+                .unwrap_or_else(|| vec!["target/".into(), ".git/".into()])
+                .iter()
+                .map(|path| watch_root.join(path))
+                .collect(),
+            watch_root,
         })
     }
 }
@@ -61,34 +358,321 @@ impl RtcWatch {
 /// Runtime config for the serve system.
 #[derive(Clone, Debug)]
 pub struct RtcServe {
-    /// Runtime config for the watch system.
-    pub watch: Arc<RtcWatch>,
+    /// Runtime config for the watch system, which is absent when serving a prebuilt serve dir.
+    pub watch: Option<Arc<RtcWatch>>,
+    /// The public URL from which assets are served.
+    pub public_url: String,
+    /// The IP address to serve on.
+    pub address: IpAddr,
     /// The port to serve on.
     pub port: u16,
+    /// A Unix domain socket to serve on instead of a TCP port.
+    pub unix_socket: Option<PathBuf>,
+    /// The maximum number of pending connections queued on the server's TCP listener.
+    pub backlog: u32,
     /// Open a browser tab once the initial build is complete.
     pub open: bool,
+    /// A path relative to the public URL to open in the browser.
+    pub open_path: Option<String>,
+    /// The browser executable used to open the browser tab, instead of the system default.
+    pub browser: Option<String>,
     /// A URL to which requests will be proxied.
     pub proxy_backend: Option<Url>,
     /// The URI on which to accept requests which are to be rewritten and proxied to backend.
     pub proxy_rewrite: Option<String>,
+    /// The maximum size in bytes of bodies proxied to & from the `proxy_backend`.
+    pub proxy_max_body_bytes: Option<usize>,
+    /// Relay `404` responses from proxy backends verbatim, instead of falling back to the index.html.
+    pub proxy_passthrough_404: bool,
     /// Any proxies configured to run along with the server.
     pub proxies: Option<Vec<ConfigOptsProxy>>,
+    /// Render an HTML listing for dist directories which have no index.html.
+    pub directory_listing: bool,
+    /// Start serving even if the initial build fails, instead of exiting.
+    pub serve_on_build_error: bool,
+    /// Keep serving the dist dir of the last successful build while rebuilds are failing.
+    pub serve_last_good: bool,
+    /// HTTP basic auth credentials of the form `user:pass` required on all requests.
+    pub basic_auth: Option<String>,
+    /// The lowercased hosts which requests must name in their `Host` header, in addition to
+    /// `localhost` & loopback addresses, including the configured address unless unspecified.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// A backend process to run alongside the server.
+    pub backend_command: Option<ConfigOptsBackendCommand>,
+    /// Rules for paths served as downloads.
+    pub downloads: Vec<DownloadRule>,
+    /// A prebuilt dir to serve instead of the dist dir, in which case no builds are run, and the
+    /// build & watch config is not validated.
+    pub serve_dir: Option<PathBuf>,
+    /// Run the initial build, but do not watch for changes or rebuild.
+    pub no_watch: bool,
+    /// Warnings raised while reading the config, for the caller to report when serving a serve
+    /// dir; they are otherwise reported by the build system.
+    pub warnings: Vec<String>,
 }
 
 impl RtcServe {
     pub(super) fn new(
-        build_opts: ConfigOptsBuild, watch_opts: ConfigOptsWatch, opts: ConfigOptsServe, proxies: Option<Vec<ConfigOptsProxy>>,
+        build_opts: ConfigOptsBuild, watch_opts: ConfigOptsWatch, opts: ConfigOptsServe, proxies: Option<Vec<ConfigOptsProxy>>, warnings: Vec<String>,
     ) -> Result<Self> {
-        let watch = Arc::new(RtcWatch::new(build_opts, watch_opts)?);
+        let serve_dir = opts
+            .serve_dir
+            .map(|serve_dir| {
+                let dir = serve_dir
+                    .canonicalize()
+                    .with_context(|| format!("error getting canonical path to serve dir {:?}", &serve_dir))?;
+                ensure!(dir.is_dir(), "serve dir {:?} is not a directory", &serve_dir);
+                Ok(dir)
+            })
+            .transpose()?;
+        let public_url = build_opts.public_url.clone().unwrap_or_else(|| "/".into());
+        // A prebuilt serve dir needs no build, so the source HTML & cargo need not be available.
+        let (watch, warnings) = match &serve_dir {
+            Some(_) => (None, warnings),
+            None => (Some(Arc::new(RtcWatch::new(build_opts, watch_opts, warnings)?)), vec![]),
+        };
+        ensure!(
+            opts.unix_socket.is_none() || opts.port.is_none(),
+            "the `unix_socket` & `port` serve options are mutually exclusive"
+        );
+        ensure!(
+            opts.unix_socket.is_none() || opts.address.is_none(),
+            "the `unix_socket` & `address` serve options are mutually exclusive"
+        );
+        let address = match &opts.address {
+            Some(address) => parse_ip_addr(address)?,
+            None => IpAddr::from([0, 0, 0, 0]),
+        };
+        ensure!(
+            opts.unix_socket.is_none() || opts.backlog.is_none(),
+            "the `backlog` serve option only applies to TCP listeners, and may not be used with `unix_socket`"
+        );
+        let backlog = opts.backlog.unwrap_or(128);
+        ensure!(
+            backlog > 0 && backlog <= i32::MAX as u32,
+            "the `backlog` serve option must be between 1 & {}",
+            i32::MAX
+        );
+        let basic_auth = opts.basic_auth.as_deref().map(substitute_env_vars).transpose()?;
+        if let Some(basic_auth) = &basic_auth {
+            ensure!(basic_auth.contains(':'), "the `basic_auth` serve option must be of the form `user:pass`");
+        }
+        let mut allowed_hosts = opts
+            .allowed_hosts
+            .map(|hosts| hosts.into_iter().map(|host| host.to_ascii_lowercase()).collect::<Vec<_>>());
+        for host in allowed_hosts.iter().flatten() {
+            ensure!(
+                !host.is_empty() && !host.trim_start_matches("*.").contains('*'),
+                "invalid allowed host {:?}; wildcards are only supported as a leading `*.`",
+                host
+            );
+        }
+        // The configured address is allowed as well, unless it binds to all interfaces.
+        if let Some(hosts) = &mut allowed_hosts {
+            match address {
+                IpAddr::V4(addr) if !addr.is_unspecified() => hosts.push(addr.to_string()),
+                IpAddr::V6(addr) if !addr.is_unspecified() => hosts.push(format!("[{}]", addr)),
+                _ => (),
+            }
+        }
+        let downloads = opts
+            .download
+            .unwrap_or_default()
+            .into_iter()
+            .map(|download| {
+                ensure!(
+                    download
+                        .filename
+                        .as_deref()
+                        .map(|name| !name.is_empty() && !name.contains(&['/', '\\'][..]))
+                        .unwrap_or(true),
+                    "the download `filename` {:?} must be a plain, non-empty file name",
+                    download.filename
+                );
+                Ok(DownloadRule {
+                    pattern: glob_to_regex(&download.path).with_context(|| format!("invalid download path glob {:?}", &download.path))?,
+                    filename: download.filename,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for proxy in proxies.iter().flatten() {
+            ensure!(
+                proxy.rewrite_regex.is_some() == proxy.rewrite_replacement.is_some(),
+                "proxy backend {} must declare both or neither of `rewrite_regex` & `rewrite_replacement`",
+                &proxy.backend
+            );
+            ensure!(
+                proxy.path_template.is_none() || proxy.rewrite_regex.is_none(),
+                "proxy backend {} declares both a `path_template` & a `rewrite_regex`; only one may be used to rewrite the backend path",
+                &proxy.backend
+            );
+            ensure!(
+                (proxy.path_template.is_none() && proxy.rewrite_regex.is_none() && proxy.strip_prefix.is_none())
+                    || proxy.rewrite.is_none()
+                    || proxy.backend.path() == "/",
+                "proxy backend {} declares both a path & a `path_template`, `rewrite_regex` or `strip_prefix`; only one may be used to rewrite the backend path",
+                &proxy.backend
+            );
+            if let Some(strip_prefix) = &proxy.strip_prefix {
+                let listen_path = proxy.rewrite.as_deref().unwrap_or_else(|| proxy.backend.path());
+                ensure!(
+                    strip_prefix.starts_with('/') && listen_path.starts_with(strip_prefix.trim_end_matches('/')),
+                    "the `strip_prefix` {:?} of proxy backend {} must be a prefix of the path {:?} on which the proxy listens",
+                    strip_prefix,
+                    &proxy.backend,
+                    listen_path
+                );
+            }
+            if let Some(rewrite_regex) = &proxy.rewrite_regex {
+                Regex::new(rewrite_regex).with_context(|| format!("invalid `rewrite_regex` for proxy backend {}", &proxy.backend))?;
+            }
+        }
+        #[cfg(not(unix))]
+        ensure!(opts.unix_socket.is_none(), "the `unix_socket` serve option is only supported on unix platforms");
         Ok(Self {
             watch,
+            public_url,
+            address,
             port: opts.port.unwrap_or(8080),
+            unix_socket: opts.unix_socket,
+            backlog,
             open: opts.open,
+            open_path: opts.open_path,
+            browser: opts.browser,
             proxy_backend: opts.proxy_backend,
             proxy_rewrite: opts.proxy_rewrite,
+            proxy_max_body_bytes: opts.proxy_max_body_bytes,
+            proxy_passthrough_404: opts.proxy_passthrough_404.unwrap_or(true),
             proxies,
+            directory_listing: opts.directory_listing,
+            serve_on_build_error: opts.serve_on_build_error,
+            serve_last_good: opts.serve_last_good,
+            basic_auth,
+            allowed_hosts,
+            backend_command: opts.backend_command,
+            downloads,
+            serve_dir,
+            no_watch: opts.no_watch,
+            warnings,
         })
     }
+
+    /// The dir to be served, which is the serve dir if configured, else the dist dir.
+    pub fn serve_root(&self) -> &Path {
+        match (&self.serve_dir, &self.watch) {
+            (Some(serve_dir), _) => serve_dir,
+            (None, Some(watch)) => &watch.build.dist,
+            (None, None) => unreachable!("the watch config is only absent with a serve dir"),
+        }
+    }
+}
+
+/// Parse the given IP address, where IPv6 addresses may be given in brackets, as in URLs.
+fn parse_ip_addr(address: &str) -> Result<IpAddr> {
+    let unbracketed = address
+        .strip_prefix('[')
+        .and_then(|address| address.strip_suffix(']'))
+        .unwrap_or(address);
+    let ip: IpAddr = unbracketed
+        .parse()
+        .with_context(|| format!("invalid serve address {:?}; expected an IPv4 or IPv6 address, such as `127.0.0.1` or `::1`", address))?;
+    ensure!(
+        ip.is_ipv6() || unbracketed.len() == address.len(),
+        "invalid serve address {:?}; only IPv6 addresses may be given in brackets",
+        address
+    );
+    Ok(ip)
+}
+
+/// A rule for serving matching paths as downloads.
+#[derive(Clone, Debug)]
+pub struct DownloadRule {
+    /// The pattern matching the paths, relative to the public URL & without a leading `/`, of the
+    /// files to serve as downloads.
+    pub pattern: Regex,
+    /// The file name suggested to the browser for the download.
+    pub filename: Option<String>,
+}
+
+/// Compile the given path glob into an anchored regex.
+///
+/// `*` & `?` do not match across `/`, while `**` does, and a `**/` prefix also matches no dir at
+/// all. Any leading `/` of the glob is ignored, as paths are matched relative to the public URL.
+fn glob_to_regex(glob: &str) -> Result<Regex> {
+    let mut pattern = String::from("^");
+    let mut rest = glob.trim_start_matches('/');
+    while let Some(ch) = rest.chars().next() {
+        if rest.starts_with("**/") {
+            pattern.push_str("(?:.*/)?");
+            rest = &rest[3..];
+        } else if rest.starts_with("**") {
+            pattern.push_str(".*");
+            rest = &rest[2..];
+        } else {
+            match ch {
+                '*' => pattern.push_str("[^/]*"),
+                '?' => pattern.push_str("[^/]"),
+                _ => pattern.push_str(&regex::escape(ch.encode_utf8(&mut [0; 4]))),
+            }
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+    pattern.push('$');
+    Ok(Regex::new(&pattern)?)
+}
+
+/// Check that the given base href agrees with the given base URL by which assets are referenced,
+/// erroring on a conflict when `strict`, else recording a warning.
+///
+/// Asset references are always rendered with the public URL (or `./` with `relative_urls`), and
+/// the `<base>` element does not change them. It does however change how the browser resolves
+/// relative URLs, so a conflicting base href may break the resolution of `./`-relative asset
+/// references & of relative URLs in the app itself.
+fn check_base_href(base_href: &str, asset_base: &str, strict: bool, warnings: &mut Vec<String>) -> Result<()> {
+    if base_href.trim_end_matches('/') == asset_base.trim_end_matches('/') {
+        return Ok(());
+    }
+    let msg = format!(
+        "the `base_href` {:?} differs from the base URL {:?} by which assets are referenced; asset references keep using {:?}, while \
+         relative URLs resolve against {:?}",
+        base_href, asset_base, asset_base, base_href
+    );
+    ensure!(!strict, "{} (disable `strict_base_href` to allow this)", msg);
+    warnings.push(msg);
+    Ok(())
+}
+
+/// Discover the source HTML file by searching conventional locations relative to the given dir,
+/// recording a warning when it is not found at the first & default location.
+fn discover_target(dir: &Path, warnings: &mut Vec<String>) -> Result<PathBuf> {
+    let target = TARGET_SEARCH_PATHS
+        .iter()
+        .map(|path| dir.join(path))
+        .find(|path| path.is_file())
+        .ok_or_else(|| anyhow!("no target HTML file configured, and none found in any of {:?}", TARGET_SEARCH_PATHS))?;
+    if target != dir.join(TARGET_SEARCH_PATHS[0]) {
+        warnings.push(format!("no target HTML file configured; using discovered {:?}", &target));
+    }
+    Ok(target)
+}
+
+/// Substitute all `${VAR}` references in the given value with the value of the referenced env var.
+fn substitute_env_vars(val: &str) -> Result<String> {
+    let mut out = String::with_capacity(val.len());
+    let mut rest = val;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| anyhow!("unterminated env var reference in {:?}", val))?;
+        let name = &rest[start + 2..end];
+        let var = std::env::var(name).with_context(|| format!("error reading env var {:?}", name))?;
+        out.push_str(&rest[..start]);
+        out.push_str(&var);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
 }
 
 /// Runtime config for the clean system.
@@ -98,13 +682,212 @@ pub struct RtcClean {
     pub dist: PathBuf,
     /// Optionally perform a cargo clean.
     pub cargo: bool,
+    /// Warnings raised while reading the config, for the caller to report.
+    pub warnings: Vec<String>,
 }
 
 impl RtcClean {
-    pub(super) fn new(opts: ConfigOptsClean) -> Result<Self> {
+    pub(super) fn new(opts: ConfigOptsClean, warnings: Vec<String>) -> Result<Self> {
         Ok(Self {
             dist: opts.dist.unwrap_or_else(|| "dist".into()),
             cargo: opts.cargo,
+            warnings,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigOptsDownload;
+    use crate::test_util::TestDir;
+
+    #[test]
+    fn conflicting_base_href_is_a_warning_unless_strict() {
+        let mut warnings = vec![];
+        check_base_href("/app/", "/app", false, &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+
+        check_base_href("/other/", "/app/", false, &mut warnings).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("differs from the base URL"), "{}", warnings[0]);
+
+        let err = check_base_href("/other/", "/app/", true, &mut warnings).unwrap_err();
+        assert!(err.to_string().contains("strict_base_href"), "{}", err);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn additional_targets_need_distinct_file_names() {
+        let dir = TestDir::new("targets", &[("index.html", ""), ("admin.html", ""), ("other/admin.html", ""), ("other/index.html", "")]);
+        let build = |targets: &[&str]| {
+            let opts = ConfigOptsBuild {
+                target: Some(dir.join("index.html")),
+                targets: Some(targets.iter().map(|target| dir.join(target)).collect()),
+                ..Default::default()
+            };
+            RtcBuild::new(opts, vec![]).map(|cfg| cfg.targets)
+        };
+        // The main target & duplicates are skipped.
+        assert_eq!(build(&["admin.html", "index.html", "admin.html"]).unwrap(), vec![dir.join("admin.html")]);
+        for targets in &[&["admin.html", "other/admin.html"][..], &["other/index.html"][..]] {
+            let err = build(targets).unwrap_err();
+            assert!(err.to_string().contains("same file name as another target"), "{}", err);
+        }
+        assert!(build(&["missing.html"]).is_err());
+    }
+
+    #[test]
+    fn jobs_default_to_the_cpu_count_and_must_be_positive() {
+        let dir = TestDir::new("jobs", &[("index.html", "")]);
+        let build = |jobs: Option<usize>| {
+            let opts = ConfigOptsBuild {
+                target: Some(dir.join("index.html")),
+                jobs,
+                ..Default::default()
+            };
+            RtcBuild::new(opts, vec![]).map(|cfg| cfg.jobs)
+        };
+        assert_eq!(build(None).unwrap(), num_cpus::get());
+        assert_eq!(build(Some(2)).unwrap(), 2);
+        let err = build(Some(0)).unwrap_err();
+        assert!(err.to_string().contains("`jobs` option must be greater than 0"), "{}", err);
+    }
+
+    #[test]
+    fn bindgen_out_dir_must_be_within_dist() {
+        let dir = TestDir::new("bindgen-out-dir", &[("index.html", "")]);
+        let build = |bindgen_out_dir: &str| {
+            let opts = ConfigOptsBuild {
+                target: Some(dir.join("index.html")),
+                bindgen_out_dir: Some(PathBuf::from(bindgen_out_dir)),
+                ..Default::default()
+            };
+            RtcBuild::new(opts, vec![]).map(|cfg| cfg.bindgen_out_dir)
+        };
+        assert_eq!(build("pkg/wasm").unwrap(), Some(PathBuf::from("pkg/wasm")));
+        for bindgen_out_dir in &["../pkg", "/pkg", "./pkg"] {
+            let err = build(bindgen_out_dir).unwrap_err();
+            assert!(err.to_string().contains("must be a relative path within the dist dir"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn ip_addrs_may_be_ipv6_in_brackets() {
+        assert_eq!(parse_ip_addr("127.0.0.1").unwrap(), IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(parse_ip_addr("::1").unwrap(), "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(parse_ip_addr("[::]").unwrap(), "::".parse::<IpAddr>().unwrap());
+        for address in &["[127.0.0.1]", "localhost", "[::1", "::1]", "[::1]:8080"] {
+            assert!(parse_ip_addr(address).is_err(), "{}", address);
+        }
+    }
+
+    #[test]
+    fn download_globs_match_within_or_across_segments() {
+        let matches = |glob: &str, path: &str| glob_to_regex(glob).unwrap().is_match(path);
+        assert!(matches("*.zip", "app.zip"));
+        assert!(!matches("*.zip", "files/app.zip"));
+        assert!(matches("/files/?.pdf", "files/a.pdf"));
+        assert!(!matches("files/?.pdf", "files/ab.pdf"));
+        assert!(matches("**/*.zip", "app.zip"));
+        assert!(matches("**/*.zip", "files/v1/app.zip"));
+        assert!(matches("files/**", "files/v1/app.zip"));
+        // Regex metacharacters are matched literally.
+        assert!(matches("app(1).tar.gz", "app(1).tar.gz"));
+        assert!(!matches("app(1).tar.gz", "app(1)xtar.gz"));
+    }
+
+    #[test]
+    fn download_filenames_must_be_plain() {
+        let dir = TestDir::new("download-filename", &[]);
+        let serve = |filename: &str| {
+            let opts = ConfigOptsServe {
+                serve_dir: Some(dir.to_path_buf()),
+                download: Some(vec![ConfigOptsDownload {
+                    path: "*.zip".into(),
+                    filename: Some(filename.into()),
+                }]),
+                ..Default::default()
+            };
+            RtcServe::new(Default::default(), Default::default(), opts, None, vec![])
+        };
+        assert!(serve("app.zip").is_ok());
+        for filename in &["", "../app.zip", "dir\\app.zip"] {
+            assert!(serve(filename).is_err(), "{}", filename);
+        }
+    }
+
+    #[test]
+    fn target_is_discovered_in_conventional_locations() {
+        let dir = TestDir::new("discover-target", &[("www/index.html", "")]);
+        let mut warnings = vec![];
+        let target = discover_target(&dir, &mut warnings).unwrap();
+        assert_eq!(target, dir.join("www/index.html"));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains(&format!("{:?}", dir.join("www/index.html"))), "{}", warnings[0]);
+
+        dir.write("index.html", "");
+        let mut warnings = vec![];
+        assert_eq!(discover_target(&dir, &mut warnings).unwrap(), dir.join("index.html"));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn wasm_opt_levels_are_validated() {
+        let dir = TestDir::new("wasm-opt", &[("index.html", "")]);
+        let build = |wasm_opt: Option<&str>| {
+            let opts = ConfigOptsBuild {
+                target: Some(dir.join("index.html")),
+                wasm_opt: wasm_opt.map(String::from),
+                ..Default::default()
+            };
+            RtcBuild::new(opts, vec![]).map(|cfg| cfg.wasm_opt)
+        };
+        assert_eq!(build(None).unwrap(), None);
+        assert_eq!(build(Some("off")).unwrap(), None);
+        assert_eq!(build(Some("z")).unwrap().as_deref(), Some("z"));
+        assert!(build(Some("5")).unwrap_err().to_string().contains("invalid `wasm_opt` level"));
+    }
+
+    #[test]
+    fn fs_retries_are_bounded() {
+        let dir = TestDir::new("fs-retries", &[("index.html", "")]);
+        let build = |fs_retries: u32| {
+            let opts = ConfigOptsBuild {
+                target: Some(dir.join("index.html")),
+                fs_retries: Some(fs_retries),
+                ..Default::default()
+            };
+            RtcBuild::new(opts, vec![]).map(|cfg| cfg.fs_retries)
+        };
+        assert_eq!(build(MAX_FS_RETRIES).unwrap(), MAX_FS_RETRIES);
+        assert!(build(MAX_FS_RETRIES + 1).unwrap_err().to_string().contains("`fs_retries`"));
+    }
+
+    #[test]
+    fn serve_dir_skips_the_build_config() {
+        let dir = TestDir::new("serve-dir", &[]);
+        let build_opts = ConfigOptsBuild {
+            target: Some(dir.join("missing.html")),
+            cargo_command: Some("trunk-test-no-such-cargo".into()),
+            public_url: Some("/app/".into()),
+            ..Default::default()
+        };
+        let serve_opts = ConfigOptsServe {
+            serve_dir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let cfg = RtcServe::new(build_opts.clone(), Default::default(), serve_opts, None, vec!["config warning".into()]).unwrap();
+        assert!(cfg.watch.is_none());
+        assert_eq!(cfg.serve_root(), dir.to_path_buf());
+        assert_eq!(cfg.public_url, "/app/");
+        assert_eq!(cfg.warnings, vec![String::from("config warning")]);
+
+        assert!(RtcServe::new(build_opts, Default::default(), Default::default(), None, vec![]).is_err());
+        let serve_opts = ConfigOptsServe {
+            serve_dir: Some(dir.join("missing")),
+            ..Default::default()
+        };
+        assert!(RtcServe::new(Default::default(), Default::default(), serve_opts, None, vec![]).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! `Trunk.toml` schema checks.
+//!
+//! Unknown keys in the config file are otherwise silently ignored, so typos & options which have
+//! since been renamed or removed would go unnoticed. The keys of the config file are checked
+//! against the fields known to the config models, warning about unknown keys, or failing in strict
+//! mode, which is enabled by `--strict-config` or by the config file's own `strict` key.
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use toml::Value;
+
+use super::models::{
+    ConfigOpts, ConfigOptsBackendCommand, ConfigOptsBuild, ConfigOptsClean, ConfigOptsDownload, ConfigOptsProxy, ConfigOptsServe, ConfigOptsWatch,
+};
+
+/// The current version of the `Trunk.toml` schema, which config files may declare via the
+/// top-level `schema` key.
+pub const CURRENT_SCHEMA: u32 = 1;
+
+/// Config keys which have been renamed or removed, as `(schema, section, key, note)`, where
+/// `schema` is the first schema version in which the key is no longer recognized.
+///
+/// This is empty for the initial schema, & is to be extended whenever an option is renamed or
+/// removed, along with bumping `CURRENT_SCHEMA`.
+const MIGRATIONS: &[(u32, &str, &str, &str)] = &[];
+
+/// Check the keys of the given parsed config file against the known config fields.
+///
+/// Unknown keys produce a warning, unless `strict_config` is given or `strict` is enabled in the
+/// config file, in which case they produce an error naming the offending keys. Keys of config
+/// files declaring an older schema which have since been renamed or removed produce a migration
+/// note. The warnings are returned for the caller to report.
+pub(super) fn check(path: &Path, cfg: &Value, strict_config: bool) -> Result<Vec<String>> {
+    let mut warnings = vec![];
+    let root = match cfg.as_table() {
+        Some(root) => root,
+        None => return Ok(warnings),
+    };
+    let strict = strict_config || root.get("strict").and_then(Value::as_bool).unwrap_or(false);
+    let schema = root
+        .get("schema")
+        .and_then(Value::as_integer)
+        .unwrap_or_else(|| i64::from(CURRENT_SCHEMA));
+    if schema > i64::from(CURRENT_SCHEMA) {
+        warnings.push(format!(
+            "config file {:?} declares schema {}, but this version of Trunk only supports up to schema {}; some options may be ignored",
+            path, schema, CURRENT_SCHEMA
+        ));
+    }
+
+    // Gather the dotted paths of all unknown keys.
+    let mut unknown = vec![];
+    check_table(root.keys(), struct_fields::<ConfigOpts>(), "", &mut unknown);
+    let sections = [
+        ("build", struct_fields::<ConfigOptsBuild>()),
+        ("watch", struct_fields::<ConfigOptsWatch>()),
+        ("serve", struct_fields::<ConfigOptsServe>()),
+        ("clean", struct_fields::<ConfigOptsClean>()),
+    ];
+    for (section, fields) in sections.iter() {
+        if let Some(table) = root.get(*section).and_then(Value::as_table) {
+            check_table(table.keys(), fields, section, &mut unknown);
+        }
+    }
+    if let Some(backend) = root.get("serve").and_then(|serve| serve.get("backend_command")).and_then(Value::as_table) {
+        check_table(backend.keys(), struct_fields::<ConfigOptsBackendCommand>(), "serve.backend_command", &mut unknown);
+    }
+    for download in root.get("serve").and_then(|serve| serve.get("download")).and_then(Value::as_array).into_iter().flatten() {
+        if let Some(table) = download.as_table() {
+            check_table(table.keys(), struct_fields::<ConfigOptsDownload>(), "serve.download", &mut unknown);
+        }
+    }
+    for proxy in root.get("proxy").and_then(Value::as_array).into_iter().flatten() {
+        if let Some(table) = proxy.as_table() {
+            check_table(table.keys(), struct_fields::<ConfigOptsProxy>(), "proxy", &mut unknown);
+        }
+    }
+    if unknown.is_empty() {
+        return Ok(warnings);
+    }
+
+    // Report each unknown key, along with a migration note for keys which have been renamed or removed.
+    let mut messages = vec![];
+    for key in unknown.iter() {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|(since, section, name, _)| schema < i64::from(*since) && key == &dotted(section, name));
+        messages.push(match migration {
+            Some((since, _, _, note)) => format!("unknown key `{}`, which is no longer recognized as of schema {}: {}", key, since, note),
+            None => format!("unknown key `{}`", key),
+        });
+    }
+    if strict {
+        bail!("config file {:?} is invalid in strict mode:\n  {}", path, messages.join("\n  "));
+    }
+    warnings.extend(messages.into_iter().map(|message| format!("config file {:?}: {}", path, message)));
+    Ok(warnings)
+}
+
+/// Record the dotted path of each of the given keys which is not among the given known fields.
+fn check_table<'a>(keys: impl Iterator<Item = &'a String>, fields: &[&str], section: &str, unknown: &mut Vec<String>) {
+    for key in keys.filter(|key| !fields.contains(&key.as_str())) {
+        let key = dotted(section, key);
+        if !unknown.contains(&key) {
+            unknown.push(key);
+        }
+    }
+}
+
+/// Join the given section & key into a dotted key path.
+fn dotted(section: &str, key: &str) -> String {
+    if section.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", section, key)
+    }
+}
+
+/// Get the field names of the given struct type as known to its derived `Deserialize` impl.
+///
+/// Derived impls pass their field names to `Deserializer::deserialize_struct`, so a deserializer
+/// which only captures those names avoids maintaining a separate list of known keys.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldsDeserializer<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de, 'a> Deserializer<'de> for FieldsDeserializer<'a> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _: V) -> std::result::Result<V::Value, Self::Error> {
+            Err(de::Error::custom("only structs are supported"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self, _: &'static str, fields: &'static [&'static str], _: V,
+        ) -> std::result::Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("fields captured"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldsDeserializer(&mut fields));
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(cfg: &str) -> Value {
+        toml::from_str(cfg).unwrap()
+    }
+
+    #[test]
+    fn unknown_keys_are_returned_as_warnings() {
+        let cfg = parse("[build]\ntarget = \"index.html\"\ntargte = \"index.html\"\n\n[[proxy]]\nbackend = \"http://localhost:9000\"\nrewite = \"/api/\"\n");
+        let warnings = check(Path::new("Trunk.toml"), &cfg, false).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("unknown key `build.targte`"), "{}", warnings[0]);
+        assert!(warnings[1].contains("unknown key `proxy.rewite`"), "{}", warnings[1]);
+    }
+
+    #[test]
+    fn unknown_keys_are_errors_in_strict_mode() {
+        let cfg = parse("[serve]\nprot = 8080\n");
+        let err = check(Path::new("Trunk.toml"), &cfg, true).unwrap_err();
+        assert!(err.to_string().contains("unknown key `serve.prot`"), "{}", err);
+
+        let cfg = parse("strict = true\n[serve]\nprot = 8080\n");
+        assert!(check(Path::new("Trunk.toml"), &cfg, false).is_err());
+    }
+
+    #[test]
+    fn newer_schema_is_a_warning() {
+        let cfg = parse(&format!("schema = {}\n", CURRENT_SCHEMA + 1));
+        let warnings = check(Path::new("Trunk.toml"), &cfg, true).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("only supports up to schema"), "{}", warnings[0]);
+    }
+}
@@ -0,0 +1,20 @@
+//! Build, bundle & ship your Rust WASM application to the web.
+//!
+//! Besides the `trunk` CLI, Trunk's build system may be embedded in other tools. A `BuildSystem`
+//! is created from the runtime config produced by `ConfigOpts::rtc_build`, and reports on the
+//! progress of its builds to any `BuildObserver` registered via `BuildSystem::add_observer`.
+//! Likewise, a `ServeSystem` is created from the runtime config produced by
+//! `ConfigOpts::rtc_serve`, and exposes the address its server is bound to via
+//! `ServeSystem::local_addr`.
+
+mod backend;
+pub mod build;
+pub mod cmd;
+mod common;
+pub mod config;
+mod pipelines;
+mod proxy;
+pub mod serve;
+#[cfg(test)]
+mod test_util;
+mod watch;
@@ -1,16 +1,8 @@
-mod build;
-mod cmd;
-mod common;
-mod config;
-mod pipelines;
-mod proxy;
-mod serve;
-mod watch;
-
 use std::path::PathBuf;
 
 use anyhow::Result;
 use structopt::StructOpt;
+use trunk::cmd;
 
 #[async_std::main]
 async fn main() -> Result<()> {
@@ -27,21 +19,34 @@ struct Trunk {
     /// Path to the Trunk config file [default: Trunk.toml]
     #[structopt(long, parse(from_os_str), env = "TRUNK_CONFIG")]
     pub config: Option<PathBuf>,
+    /// Read env var config from `TRUNK_<NAME>_<SECTION>_<ITEM>` instead of `TRUNK_<SECTION>_<ITEM>`
+    #[structopt(long = "config-env", env = "TRUNK_CONFIG_ENV")]
+    pub config_env: Option<String>,
+    /// Fail on unknown keys in the Trunk config file, instead of only warning; also enabled by
+    /// setting `TRUNK_STRICT_CONFIG=true`
+    #[structopt(long = "strict-config")]
+    pub strict_config: bool,
 }
 
 impl Trunk {
-    pub async fn run(self) -> Result<()> {
+    pub async fn run(mut self) -> Result<()> {
+        // NOTE: the env var is read by hand, as clap flags can not be sourced from env vars.
+        self.strict_config |= std::env::var("TRUNK_STRICT_CONFIG")
+            .map(|val| val == "true" || val == "1")
+            .unwrap_or(false);
         match self.action {
-            TrunkSubcommands::Build(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Clean(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Serve(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Watch(inner) => inner.run(self.config).await,
-            TrunkSubcommands::Config(inner) => inner.run(self.config).await,
+            TrunkSubcommands::Build(inner) => inner.run(self.config, self.config_env, self.strict_config).await,
+            TrunkSubcommands::Clean(inner) => inner.run(self.config, self.config_env, self.strict_config).await,
+            TrunkSubcommands::Serve(inner) => inner.run(self.config, self.config_env, self.strict_config).await,
+            TrunkSubcommands::Watch(inner) => inner.run(self.config, self.config_env, self.strict_config).await,
+            TrunkSubcommands::Config(inner) => inner.run(self.config, self.config_env, self.strict_config).await,
+            TrunkSubcommands::New(inner) => inner.run().await,
         }
     }
 }
 
 #[derive(StructOpt)]
+#[allow(clippy::large_enum_variant)]
 enum TrunkSubcommands {
     /// Build the Rust WASM app and all of its assets.
     Build(cmd::build::Build),
@@ -53,4 +58,6 @@ enum TrunkSubcommands {
     Clean(cmd::clean::Clean),
     /// Trunk config controls.
     Config(cmd::config::Config),
+    /// Create a new Trunk project.
+    New(cmd::new::New),
 }
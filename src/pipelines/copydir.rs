@@ -1,6 +1,6 @@
 //! Copy-dir asset pipeline.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Context, Result};
@@ -41,6 +41,19 @@ impl CopyDir {
         Ok(Self { id, cfg, progress, path })
     }
 
+    /// The path of the source directory consumed by this pipeline.
+    pub fn input_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The names of the dirs this pipeline intends to emit to the dist dir.
+    pub fn planned_outputs(&self) -> Vec<String> {
+        self.path
+            .file_name()
+            .map(|dir_name| vec![format!("{}/", dir_name.to_string_lossy())])
+            .unwrap_or_default()
+    }
+
     /// Spawn the pipeline for this asset type.
     pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
         spawn(async move {
@@ -53,17 +66,22 @@ impl CopyDir {
                 .file_name()
                 .ok_or_else(|| anyhow!("could not get directory name of dir {:?}", &canonical_path))?;
             let dir_out = self.cfg.dist.join(dir_name);
-            copy_dir_recursive(canonical_path.into(), dir_out).await?;
+            copy_dir_recursive(canonical_path.into(), dir_out.clone(), self.cfg.follow_symlinks).await?;
             self.progress.set_message("finished copying directory");
-            Ok(TrunkLinkPipelineOutput::CopyDir(CopyDirOutput(self.id)))
+            Ok(TrunkLinkPipelineOutput::CopyDir(CopyDirOutput(self.id, dir_out)))
         })
     }
 }
 
-/// The output of a CopyDir build pipeline.
-pub struct CopyDirOutput(usize);
+/// The output of a CopyDir build pipeline, holding the pipeline ID & the path of the copied dir.
+pub struct CopyDirOutput(usize, PathBuf);
 
 impl CopyDirOutput {
+    /// The path of the copied directory in the dist dir.
+    pub fn dir_path(&self) -> &Path {
+        &self.1
+    }
+
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
         dom.select(&super::trunk_id_selector(self.0)).remove();
         Ok(())
@@ -1,6 +1,6 @@
 //! Copy-file asset pipeline.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
@@ -38,21 +38,36 @@ impl CopyFile {
         Ok(Self { id, cfg, progress, asset })
     }
 
+    /// The path of the source asset consumed by this pipeline.
+    pub fn input_path(&self) -> &Path {
+        &self.asset.path
+    }
+
+    /// The names of the files this pipeline intends to emit to the dist dir.
+    pub fn planned_outputs(&self) -> Vec<String> {
+        vec![self.asset.file_name.to_string_lossy().to_string()]
+    }
+
     /// Spawn the pipeline for this asset type.
     pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
         spawn(async move {
             self.progress.set_message("copying file");
-            let _ = self.asset.copy(&self.cfg.dist).await?;
+            let file_path = self.asset.copy(&self.cfg.dist, self.cfg.fs_retries).await?;
             self.progress.set_message("finished copying file");
-            Ok(TrunkLinkPipelineOutput::CopyFile(CopyFileOutput(self.id)))
+            Ok(TrunkLinkPipelineOutput::CopyFile(CopyFileOutput(self.id, file_path)))
         })
     }
 }
 
-/// The output of a CopyFile build pipeline.
-pub struct CopyFileOutput(usize);
+/// The output of a CopyFile build pipeline, holding the pipeline ID & the path of the copied file.
+pub struct CopyFileOutput(usize, PathBuf);
 
 impl CopyFileOutput {
+    /// The path of the copied file in the dist dir.
+    pub fn file_path(&self) -> &Path {
+        &self.1
+    }
+
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
         dom.select(&super::trunk_id_selector(self.0)).remove();
         Ok(())
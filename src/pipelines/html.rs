@@ -1,23 +1,40 @@
 //! Source HTML pipelines.
 
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, ensure, Context, Result};
 use async_std::fs;
 use async_std::task::{spawn_local, JoinHandle};
+use chrono::{SecondsFormat, TimeZone, Utc};
 use futures::channel::mpsc::Sender;
-use futures::stream::{FuturesUnordered, StreamExt};
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use html5ever::serialize::{AttrRef, HtmlSerializer, Serialize, SerializeOpts, Serializer, TraversalScope};
+use html5ever::QualName;
 use indicatif::ProgressBar;
-use nipper::Document;
+use nipper::{Document, SerializableNodeRef};
 
+use crate::common::write_with_retry;
 use crate::config::RtcBuild;
 use crate::pipelines::rust_app::RustApp;
-use crate::pipelines::{TrunkLink, TrunkLinkPipelineOutput, TRUNK_ID};
+use crate::pipelines::{escape_raw_text, AssetPlan, HashCache, TrunkLink, TrunkLinkPipelineOutput, TRUNK_ID};
 
 const PUBLIC_URL_MARKER_ATTR: &str = "data-trunk-public-url";
+const BASE_HREF_MARKER_ATTR: &str = "data-trunk-base-href";
 
-type AssetPipelineHandles = FuturesUnordered<JoinHandle<Result<TrunkLinkPipelineOutput>>>;
+type AssetPipelineHandles = LocalBoxStream<'static, Result<TrunkLinkPipelineOutput>>;
+
+/// The paths consumed & emitted by a run of the HTML pipeline.
+pub struct HtmlPipelineOutput {
+    /// The source HTML file & the sources of all assets referenced by it.
+    pub input_paths: Vec<PathBuf>,
+    /// All files & dirs emitted to the dist dir.
+    pub output_paths: Vec<PathBuf>,
+    /// The paths, relative to the dist dir & using `/` separators, of all content hashed files
+    /// emitted to the dist dir, with a `<hash>` placeholder standing in for the content hash.
+    pub hashed_patterns: Vec<String>,
+}
 
 /// An HTML assets build pipeline.
 ///
@@ -28,18 +45,24 @@ pub struct HtmlPipeline {
     cfg: Arc<RtcBuild>,
     /// The progress bar used by this pipeline.
     progress: ProgressBar,
-    /// The path to the source HTML document from which the output `index.html` will be built.
+    /// The path to the source HTML document from which the output HTML file will be built.
     target_html_path: PathBuf,
+    /// The file name of the output HTML file within the dist dir.
+    output_name: String,
     /// The parent directory of `target_html_path`.
     target_html_dir: Arc<PathBuf>,
     /// An optional channel to be used to communicate ignore paths to the watcher.
     ignore_chan: Option<Sender<PathBuf>>,
+    /// The cache of hashed asset outputs, which persists across the builds of this pipeline.
+    hash_cache: Arc<HashCache>,
 }
 
 impl HtmlPipeline {
-    /// Create a new instance.
-    pub fn new(cfg: Arc<RtcBuild>, progress: ProgressBar, ignore_chan: Option<Sender<PathBuf>>) -> Result<Self> {
-        let target_html_path = cfg.target.canonicalize().context("failed to get canonical path of target HTML file")?;
+    /// Create a new instance, building the given source HTML file into the given output file name.
+    pub fn new(
+        cfg: Arc<RtcBuild>, target: &Path, output_name: String, progress: ProgressBar, ignore_chan: Option<Sender<PathBuf>>,
+    ) -> Result<Self> {
+        let target_html_path = target.canonicalize().context("failed to get canonical path of target HTML file")?;
         let target_html_dir = Arc::new(
             target_html_path
                 .parent()
@@ -51,24 +74,79 @@ impl HtmlPipeline {
             cfg,
             progress,
             target_html_path,
+            output_name,
             target_html_dir,
             ignore_chan,
+            hash_cache: Arc::new(HashCache::default()),
         })
     }
 
     /// Spawn a new pipeline.
-    pub fn spawn(self: Arc<Self>) -> JoinHandle<Result<()>> {
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<Result<HtmlPipelineOutput>> {
         spawn_local(self.build())
     }
 
-    /// Perform the build routine of this pipeline.
-    async fn build(self: Arc<Self>) -> Result<()> {
-        self.progress.set_message("spawning asset pipelines");
+    /// Describe the assets declared in the source HTML & what their pipelines intend to emit,
+    /// without running any of them.
+    pub async fn plan(&self) -> Result<Vec<AssetPlan>> {
+        let raw_html = fs::read_to_string(&self.target_html_path).await?;
+        let mut target_html = Document::from(&raw_html);
+        let assets = self.collect_assets(&mut target_html).await?;
+        let mut plans = vec![AssetPlan {
+            asset_type: "html",
+            input: self.target_html_path.clone(),
+            outputs: vec![self.output_name.clone()],
+        }];
+        plans.extend(assets.iter().map(|asset| asset.plan()));
+        Ok(plans)
+    }
 
+    /// Perform the build routine of this pipeline.
+    async fn build(self: Arc<Self>) -> Result<HtmlPipelineOutput> {
         // Open the source HTML file for processing.
         let raw_html = fs::read_to_string(&self.target_html_path).await?;
         let mut target_html = Document::from(&raw_html);
+        let assets = self.collect_assets(&mut target_html).await?;
+
+        // Spawn all asset pipelines, running no more than the configured number of them at once.
+        let mut input_paths = vec![self.target_html_path.clone()];
+        input_paths.extend(assets.iter().map(|asset| asset.input_path().to_path_buf()));
+        let hashed_patterns = assets
+            .iter()
+            .flat_map(|asset| asset.plan().outputs)
+            .filter(|output| output.contains("<hash>"))
+            .collect();
+        let pipelines: AssetPipelineHandles = stream::iter(assets)
+            .map(|asset| asset.spawn())
+            .buffer_unordered(self.cfg.jobs)
+            .boxed_local();
+
+        // Finalize asset pipelines.
+        let (mut output_paths, app_version) = self.finalize_asset_pipelines(&mut target_html, pipelines).await?;
+        self.finalize_nomodule_fallback(&mut target_html).await?;
+        self.finalize_html(&mut target_html, app_version.as_deref());
 
+        // Assemble a new output index.html file.
+        let output_html = if self.cfg.release && self.cfg.minify_html {
+            minify_html(&target_html)?
+        } else {
+            target_html.html().to_string() // TODO: prettify this output.
+        };
+        let output_path = self.cfg.dist.join(&self.output_name);
+        write_with_retry(&output_path, output_html.as_bytes(), self.cfg.fs_retries)
+            .await
+            .context("error writing finalized HTML output")?;
+        output_paths.push(output_path);
+        Ok(HtmlPipelineOutput {
+            input_paths,
+            output_paths,
+            hashed_patterns,
+        })
+    }
+
+    /// Build an asset for each `<link data-trunk .../>` element of the given document, along with
+    /// the default Rust app asset if the document does not declare one.
+    async fn collect_assets(&self, target_html: &mut Document) -> Result<Vec<TrunkLink>> {
         // Iterator over all `link[data-trunk]` elements, assigning IDs & building pipelines.
         let mut assets = vec![];
         for (id, mut link) in target_html.select(r#"link[data-trunk]"#).iter().enumerate() {
@@ -78,6 +156,7 @@ impl HtmlPipeline {
                 self.progress.clone(),
                 self.target_html_dir.clone(),
                 self.ignore_chan.clone(),
+                self.hash_cache.clone(),
                 link,
                 id,
             )
@@ -88,6 +167,11 @@ impl HtmlPipeline {
         // Ensure we have a Rust app pipeline to spawn.
         let rust_app_nodes = target_html.select(r#"link[data-trunk][rel="rust"]"#).length();
         ensure!(rust_app_nodes <= 1, r#"only one <link data-trunk rel="rust" .../> link may be specified"#);
+        ensure!(
+            rust_app_nodes == 1 || !self.cfg.require_rust_link,
+            r#"source HTML {:?} has no <link data-trunk rel="rust" .../> link, which is required by the `require_rust_link` option"#,
+            &self.target_html_path
+        );
         if rust_app_nodes == 0 {
             let app = RustApp::new_default(
                 self.cfg.clone(),
@@ -98,37 +182,308 @@ impl HtmlPipeline {
             .await?;
             assets.push(TrunkLink::RustApp(app));
         }
-
-        // Spawn all asset pipelines.
-        let mut pipelines: AssetPipelineHandles = FuturesUnordered::new();
-        pipelines.extend(assets.into_iter().map(|asset| asset.spawn()));
-
-        // Finalize asset pipelines.
-        self.finalize_asset_pipelines(&mut target_html, pipelines).await?;
-        self.finalize_html(&mut target_html);
-
-        // Assemble a new output index.html file.
-        let output_html = target_html.html(); // TODO: prettify this output.
-        fs::write(self.cfg.dist.join("index.html"), output_html.as_bytes())
-            .await
-            .context("error writing finalized HTML output")?;
-        Ok(())
+        Ok(assets)
     }
 
-    /// Finalize asset pipelines & prep the DOM for final output.
-    async fn finalize_asset_pipelines(&self, target_html: &mut Document, mut pipelines: AssetPipelineHandles) -> Result<()> {
+    /// Finalize asset pipelines & prep the DOM for final output, returning the paths of all
+    /// files & dirs emitted by the asset pipelines, along with the version of the Rust app.
+    async fn finalize_asset_pipelines(
+        &self, target_html: &mut Document, mut pipelines: AssetPipelineHandles,
+    ) -> Result<(Vec<PathBuf>, Option<String>)> {
+        let mut output_paths = vec![];
+        let mut app_version = None;
         while let Some(asset_res) = pipelines.next().await {
             let asset = asset_res?;
+            output_paths.extend(asset.output_paths());
+            if let TrunkLinkPipelineOutput::RustApp(app) = &asset {
+                app_version = Some(app.version.clone());
+            }
             asset.finalize(target_html).await?;
         }
+        Ok((output_paths, app_version))
+    }
+
+    /// Inject the configured `<script nomodule>` fallback, if any, into the document head.
+    ///
+    /// The fallback is either the content of the configured script file, which is resolved relative
+    /// to the source HTML & must exist, or the configured inline script content.
+    async fn finalize_nomodule_fallback(&self, target_html: &mut Document) -> Result<()> {
+        let content = match (&self.cfg.nomodule_fallback_path, &self.cfg.nomodule_fallback_inline) {
+            (Some(path), _) => {
+                let path = self.target_html_dir.join(path);
+                fs::read_to_string(&path)
+                    .await
+                    .with_context(|| format!("error reading nomodule fallback script {:?}", &path))?
+            }
+            (None, Some(content)) => content.clone(),
+            (None, None) => return Ok(()),
+        };
+        target_html
+            .select("html head")
+            .append_html(format!("<script nomodule>{}</script>", escape_raw_text(&content, "script")));
         Ok(())
     }
 
-    /// Prepare the document for final output.
-    fn finalize_html(&self, target_html: &mut Document) {
-        // Write public_url to base element.
+    /// Prepare the document for final output, stamping it with the given app version when the
+    /// `stamp` option is enabled.
+    fn finalize_html(&self, target_html: &mut Document, app_version: Option<&str>) {
+        // Write public_url to base element, or the relative base when using relative URLs.
         let mut base_elements = target_html.select(&format!("html head base[{}]", PUBLIC_URL_MARKER_ATTR));
         base_elements.remove_attr(PUBLIC_URL_MARKER_ATTR);
-        base_elements.set_attr("href", &self.cfg.public_url);
+        base_elements.set_attr("href", self.cfg.asset_base());
+
+        // Inject the configured base href, unless the document already declares a base element.
+        if let Some(base_href) = &self.cfg.base_href {
+            if target_html.select("html head base").length() == 0 {
+                target_html
+                    .select("html head")
+                    .append_html(format!("<base {}/>", BASE_HREF_MARKER_ATTR));
+                let mut base = target_html.select(&format!("html head base[{}]", BASE_HREF_MARKER_ATTR));
+                base.remove_attr(BASE_HREF_MARKER_ATTR);
+                base.set_attr("href", base_href);
+            }
+        }
+
+        if self.cfg.stamp {
+            target_html.select("html head").append_html(format!(
+                r#"<meta name="trunk-build" content="{} {}"/>"#,
+                build_timestamp(),
+                app_version.unwrap_or_default()
+            ));
+        }
+    }
+}
+
+/// The build timestamp in ISO 8601 format.
+///
+/// For reproducible builds, the `SOURCE_DATE_EPOCH` env var is honored when set.
+fn build_timestamp() -> String {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<i64>().ok())
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        .unwrap_or_else(Utc::now)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Elements whose contents are significant verbatim, and are therefore never minified.
+const RAW_TEXT_ELEMENTS: &[&str] = &["pre", "script", "style", "textarea"];
+
+/// Minify the given document by stripping comments & collapsing runs of whitespace in text to a
+/// single space.
+///
+/// This is deliberately conservative: whitespace between inline elements may be significant, so
+/// it is collapsed rather than removed, and the text within raw text elements such as `<pre>` &
+/// `<script>` is left untouched. As the document is serialized from its DOM, attribute values &
+/// the contents of raw text elements are never mistaken for markup.
+fn minify_html(doc: &Document) -> Result<String> {
+    let mut out = vec![];
+    let opts = SerializeOpts {
+        traversal_scope: TraversalScope::IncludeNode,
+        ..Default::default()
+    };
+    let mut serializer = MinifySerializer {
+        inner: HtmlSerializer::new(&mut out, opts),
+        raw: vec![],
+    };
+    SerializableNodeRef::from(doc.root())
+        .serialize(&mut serializer, TraversalScope::IncludeNode)
+        .context("error minifying HTML output")?;
+    String::from_utf8(out).context("error minifying HTML output")
+}
+
+/// A serializer which minifies the HTML it writes via the wrapped serializer.
+struct MinifySerializer<S: Serializer> {
+    inner: S,
+    /// Whether each of the currently open elements is, or is within, a raw text element.
+    raw: Vec<bool>,
+}
+
+impl<S: Serializer> Serializer for MinifySerializer<S> {
+    fn start_elem<'a, AttrIter>(&mut self, name: QualName, attrs: AttrIter) -> io::Result<()>
+    where
+        AttrIter: Iterator<Item = AttrRef<'a>>,
+    {
+        let raw = self.raw.last().copied().unwrap_or(false) || RAW_TEXT_ELEMENTS.contains(&name.local.as_ref());
+        self.raw.push(raw);
+        self.inner.start_elem(name, attrs)
+    }
+
+    fn end_elem(&mut self, name: QualName) -> io::Result<()> {
+        self.raw.pop();
+        self.inner.end_elem(name)
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        if self.raw.last().copied().unwrap_or(false) {
+            return self.inner.write_text(text);
+        }
+        let mut collapsed = String::with_capacity(text.len());
+        for (idx, word) in text.split(|c: char| c.is_ascii_whitespace()).enumerate() {
+            if idx > 0 && !collapsed.ends_with(' ') {
+                collapsed.push(' ');
+            }
+            collapsed.push_str(word);
+        }
+        self.inner.write_text(&collapsed)
+    }
+
+    fn write_comment(&mut self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_doctype(&mut self, name: &str) -> io::Result<()> {
+        self.inner.write_doctype(name)
+    }
+
+    fn write_processing_instruction(&mut self, target: &str, data: &str) -> io::Result<()> {
+        self.inner.write_processing_instruction(target, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigOpts, ConfigOptsBuild};
+    use crate::test_util::TestDir;
+
+    /// Create a fresh dir holding a source HTML file & the given files.
+    fn test_target(name: &str, files: &[(&str, &str)]) -> TestDir {
+        let dir = TestDir::new(name, files);
+        dir.write("index.html", "<html><head></head><body></body></html>");
+        dir
+    }
+
+    /// Build the pipeline of the source HTML file of the given dir with the given build options.
+    async fn test_pipeline(dir: &Path, opts: ConfigOptsBuild) -> HtmlPipeline {
+        let target = dir.join("index.html");
+        let opts = ConfigOptsBuild {
+            target: Some(target.clone()),
+            ..opts
+        };
+        let cfg = ConfigOpts::rtc_build(opts, Some(dir.join("Trunk.toml")), None, false).await.unwrap();
+        HtmlPipeline::new(cfg, &target, "index.html".into(), ProgressBar::hidden(), None).unwrap()
+    }
+
+    #[async_std::test]
+    async fn plan_lists_assets_with_their_outputs() {
+        let dir = TestDir::new("plan", &[
+            ("Cargo.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2018\"\n"),
+            ("src/main.rs", "fn main() {}\n"),
+            ("app.css", "body {}"),
+            ("robots.txt", ""),
+        ]);
+        dir.write(
+            "index.html",
+            r#"<html><head><link data-trunk rel="css" href="app.css"/><link data-trunk rel="copy-file" href="robots.txt"/></head></html>"#,
+        );
+        let plans = test_pipeline(&dir, Default::default()).await.plan().await.unwrap();
+        let summary = plans
+            .iter()
+            .map(|plan| (plan.asset_type, plan.input.strip_prefix(&dir).unwrap().to_path_buf(), plan.outputs.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            ("html", PathBuf::from("index.html"), vec![String::from("index.html")]),
+            ("css", PathBuf::from("app.css"), vec![String::from("app-<hash>.css")]),
+            ("copy-file", PathBuf::from("robots.txt"), vec![String::from("robots.txt")]),
+            ("rust", PathBuf::from("Cargo.toml"), vec![String::from("index-<hash>.js"), String::from("index-<hash>_bg.wasm")]),
+        ]);
+        assert_eq!(plans[1].to_string(), format!("css        {} -> app-<hash>.css", dir.join("app.css").display()));
+        let json = serde_json::to_value(&plans[1]).unwrap();
+        assert_eq!(json["asset_type"], "css");
+        assert_eq!(json["outputs"][0], "app-<hash>.css");
+    }
+
+    #[async_std::test]
+    async fn require_rust_link_fails_without_a_rust_link() {
+        let dir = test_target("require-rust-link", &[
+            ("Cargo.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2018\"\n"),
+            ("src/main.rs", "fn main() {}\n"),
+        ]);
+        let opts = ConfigOptsBuild {
+            require_rust_link: true,
+            ..Default::default()
+        };
+        let err = test_pipeline(&dir, opts.clone()).await.plan().await.unwrap_err();
+        assert!(err.to_string().contains("which is required by the `require_rust_link` option"), "{}", err);
+        // Without the option, the Cargo project next to the source HTML is built by default.
+        assert!(test_pipeline(&dir, Default::default()).await.plan().await.is_ok());
+
+        dir.write("index.html", r#"<html><head><link data-trunk rel="rust"/></head></html>"#);
+        assert!(test_pipeline(&dir, opts).await.plan().await.is_ok());
+    }
+
+    #[test]
+    fn minify_html_preserves_raw_text_elements() {
+        let doc = Document::from(concat!(
+            "<!DOCTYPE html><html><head>\n  <!-- a comment -->\n  <title>  The   title </title>\n",
+            "  <script>if (a < b && c > d) {\n    x();\n}</script>\n</head>\n<body>\n",
+            "  <div data-x=\"a > b\">  some\n   text  </div>\n",
+            "  <pre>  keep\n    this  </pre><preview>  not\n  raw  </preview>\n",
+            "  <textarea>  and\n  this </textarea>\n</body></html>",
+        ));
+        let html = minify_html(&doc).unwrap();
+        assert!(!html.contains("a comment"), "{}", html);
+        assert!(html.contains("<title> The title </title>"), "{}", html);
+        assert!(html.contains("<script>if (a < b && c > d) {\n    x();\n}</script>"), "{}", html);
+        assert!(html.contains(r#"<div data-x="a > b"> some text </div>"#), "{}", html);
+        assert!(html.contains("<pre>  keep\n    this  </pre>"), "{}", html);
+        assert!(html.contains("<preview> not raw </preview>"), "{}", html);
+        assert!(html.contains("<textarea>  and\n  this </textarea>"), "{}", html);
+    }
+
+    #[async_std::test]
+    async fn stamp_is_injected_once() {
+        let dir = test_target("stamp", &[]);
+        let opts = ConfigOptsBuild {
+            stamp: true,
+            ..Default::default()
+        };
+        let pipeline = test_pipeline(&dir, opts).await;
+        let mut doc = Document::from("<html><head></head><body></body></html>");
+        pipeline.finalize_html(&mut doc, Some("1.2.3"));
+        let stamps = doc.select(r#"html head meta[name="trunk-build"]"#);
+        assert_eq!(stamps.length(), 1);
+        assert!(stamps.attr("content").unwrap().ends_with(" 1.2.3"), "{}", doc.html());
+    }
+
+    #[async_std::test]
+    async fn nomodule_fallback_path_is_inlined_escaped() {
+        let dir = test_target("nomodule-path", &[("fallback.js", "document.write('</script><p>old browser</p>');")]);
+        let opts = ConfigOptsBuild {
+            nomodule_fallback_path: Some("fallback.js".into()),
+            ..Default::default()
+        };
+        let pipeline = test_pipeline(&dir, opts).await;
+        let mut doc = Document::from("<html><head></head><body></body></html>");
+        pipeline.finalize_nomodule_fallback(&mut doc).await.unwrap();
+        let html = doc.html().to_string();
+        assert!(html.contains(r#"<script nomodule="">document.write('<\/script><p>old browser</p>');</script>"#), "{}", html);
+    }
+
+    #[async_std::test]
+    async fn nomodule_fallback_missing_path_is_an_error() {
+        let dir = test_target("nomodule-missing", &[]);
+        let opts = ConfigOptsBuild {
+            nomodule_fallback_path: Some("missing.js".into()),
+            ..Default::default()
+        };
+        let pipeline = test_pipeline(&dir, opts).await;
+        let mut doc = Document::from("<html><head></head><body></body></html>");
+        let err = pipeline.finalize_nomodule_fallback(&mut doc).await.unwrap_err();
+        assert!(format!("{:?}", err).contains("error reading nomodule fallback script"), "{:?}", err);
+    }
+
+    #[async_std::test]
+    async fn nomodule_fallback_inline_is_escaped() {
+        let dir = test_target("nomodule-inline", &[]);
+        let opts = ConfigOptsBuild {
+            nomodule_fallback_inline: Some("alert('</SCRIPT>')".into()),
+            ..Default::default()
+        };
+        let pipeline = test_pipeline(&dir, opts).await;
+        let mut doc = Document::from("<html><head></head><body></body></html>");
+        pipeline.finalize_nomodule_fallback(&mut doc).await.unwrap();
+        assert!(doc.html().contains(r#"<script nomodule="">alert('<\/SCRIPT>')</script>"#), "{}", doc.html());
     }
 }
@@ -1,6 +1,6 @@
 //! Icon asset pipeline.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
@@ -9,7 +9,7 @@ use indicatif::ProgressBar;
 use nipper::{Document, Selection};
 
 use super::ATTR_HREF;
-use super::{AssetFile, HashedFileOutput, TrunkLinkPipelineOutput};
+use super::{write_unhashed_alias, AssetFile, HashCache, HashedFileOutput, TrunkLinkPipelineOutput};
 use crate::config::RtcBuild;
 
 /// An Icon asset pipeline.
@@ -22,12 +22,16 @@ pub struct Icon {
     progress: ProgressBar,
     /// The asset file being processed.
     asset: AssetFile,
+    /// The cache of hashed outputs shared across builds.
+    hash_cache: Arc<HashCache>,
 }
 
 impl Icon {
     pub const TYPE_ICON: &'static str = "icon";
 
-    pub async fn new(cfg: Arc<RtcBuild>, progress: ProgressBar, html_dir: Arc<PathBuf>, el: Selection<'_>, id: usize) -> Result<Self> {
+    pub async fn new(
+        cfg: Arc<RtcBuild>, progress: ProgressBar, html_dir: Arc<PathBuf>, hash_cache: Arc<HashCache>, el: Selection<'_>, id: usize,
+    ) -> Result<Self> {
         // Build the path to the target asset.
         let href_attr = el
             .attr(ATTR_HREF)
@@ -35,14 +39,35 @@ impl Icon {
         let mut path = PathBuf::new();
         path.extend(href_attr.as_ref().split('/'));
         let asset = AssetFile::new(&html_dir, path).await?;
-        Ok(Self { id, cfg, progress, asset })
+        Ok(Self {
+            id,
+            cfg,
+            progress,
+            asset,
+            hash_cache,
+        })
+    }
+
+    /// The path of the source asset consumed by this pipeline.
+    pub fn input_path(&self) -> &Path {
+        &self.asset.path
+    }
+
+    /// The names of the files this pipeline intends to emit to the dist dir, with a `<hash>`
+    /// placeholder standing in for the content hash.
+    pub fn planned_outputs(&self) -> Vec<String> {
+        vec![format!("{}{}-<hash>.{}", self.cfg.assets_prefix(), self.asset.file_stem.to_string_lossy(), &self.asset.ext)]
     }
 
     /// Spawn the pipeline for this asset type.
     pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
         spawn(async move {
             self.progress.set_message("copying & hashing icon");
-            let hashed_file_output = self.asset.copy_with_hash(&self.cfg.dist).await?;
+            let hashed_file_output = self
+                .asset
+                .copy_with_hash(&self.cfg.assets_out_dir(), self.cfg.hash_algo, self.cfg.fs_retries, &self.hash_cache)
+                .await?;
+            write_unhashed_alias(&self.cfg, &hashed_file_output.file_path, &self.asset.file_name.to_string_lossy()).await?;
             self.progress.set_message("finished copying & hashing icon");
             Ok(TrunkLinkPipelineOutput::Icon(IconOutput {
                 cfg: self.cfg.clone(),
@@ -66,9 +91,8 @@ pub struct IconOutput {
 impl IconOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
         dom.select(&super::trunk_id_selector(self.id)).replace_with_html(format!(
-            r#"<link rel="icon" href="{base}{file}"/>"#,
-            base = &self.cfg.public_url,
-            file = self.file.file_name
+            r#"<link rel="icon" href="{url}"/>"#,
+            url = self.cfg.asset_url(&self.file.file_name)
         ));
         Ok(())
     }
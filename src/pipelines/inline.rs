@@ -0,0 +1,213 @@
+//! Inline asset pipeline.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use async_std::fs;
+use async_std::task::{spawn, JoinHandle};
+use indicatif::ProgressBar;
+use nipper::{Document, Selection};
+
+use super::ATTR_HREF;
+use super::{escape_raw_text, write_unhashed_alias, AssetFile, HashCache, HashedFileOutput, TrunkLinkPipelineOutput};
+use crate::config::RtcBuild;
+
+/// An Inline asset pipeline.
+///
+/// Small CSS, JS & SVG assets are inlined directly into the output HTML. Assets larger than the
+/// configured `inline_max_size` are instead emitted as hashed files & linked as usual.
+pub struct Inline {
+    /// The ID of this pipeline's source HTML element.
+    id: usize,
+    /// Runtime build config.
+    cfg: Arc<RtcBuild>,
+    /// The progress bar to use for this pipeline.
+    progress: ProgressBar,
+    /// The asset file being processed.
+    asset: AssetFile,
+    /// The cache of hashed outputs shared across builds.
+    hash_cache: Arc<HashCache>,
+}
+
+impl Inline {
+    pub const TYPE_INLINE: &'static str = "inline";
+
+    pub async fn new(
+        cfg: Arc<RtcBuild>, progress: ProgressBar, html_dir: Arc<PathBuf>, hash_cache: Arc<HashCache>, el: Selection<'_>, id: usize,
+    ) -> Result<Self> {
+        // Build the path to the target asset.
+        let href_attr = el
+            .attr(ATTR_HREF)
+            .ok_or_else(|| anyhow!("required attr `href` missing for <link data-trunk .../> element: {}", el.html()))?;
+        let mut path = PathBuf::new();
+        path.extend(href_attr.as_ref().split('/'));
+        let asset = AssetFile::new(&html_dir, path).await?;
+        ContentType::from_ext(&asset.ext)?;
+        Ok(Self {
+            id,
+            cfg,
+            progress,
+            asset,
+            hash_cache,
+        })
+    }
+
+    /// The path of the source asset consumed by this pipeline.
+    pub fn input_path(&self) -> &Path {
+        &self.asset.path
+    }
+
+    /// The names of the files this pipeline intends to emit to the dist dir, with a `<hash>`
+    /// placeholder standing in for the content hash. This is empty when the asset will be inlined.
+    pub fn planned_outputs(&self) -> Vec<String> {
+        match self.cfg.inline_max_size {
+            Some(max) if self.asset.size > max => vec![format!(
+                "{}{}-<hash>.{}",
+                self.cfg.assets_prefix(),
+                self.asset.file_stem.to_string_lossy(),
+                &self.asset.ext
+            )],
+            _ => vec![],
+        }
+    }
+
+    /// Spawn the pipeline for this asset type.
+    pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
+        spawn(async move {
+            let content_type = ContentType::from_ext(&self.asset.ext)?;
+            let within_limit = self.cfg.inline_max_size.map(|max| self.asset.size <= max).unwrap_or(true);
+            let content = if within_limit {
+                self.progress.set_message("inlining asset");
+                let content = fs::read_to_string(&self.asset.path)
+                    .await
+                    .with_context(|| format!("error reading file for inlining {:?}", &self.asset.path))?;
+                InlineContent::Inlined(content)
+            } else {
+                self.progress.set_message("copying & hashing oversized inline asset");
+                let hashed_file_output = self
+                    .asset
+                    .copy_with_hash(&self.cfg.assets_out_dir(), self.cfg.hash_algo, self.cfg.fs_retries, &self.hash_cache)
+                    .await?;
+                write_unhashed_alias(&self.cfg, &hashed_file_output.file_path, &self.asset.file_name.to_string_lossy()).await?;
+                InlineContent::Hashed(hashed_file_output)
+            };
+            self.progress.set_message("finished processing inline asset");
+            Ok(TrunkLinkPipelineOutput::Inline(InlineOutput {
+                cfg: self.cfg.clone(),
+                id: self.id,
+                content_type,
+                content,
+            }))
+        })
+    }
+}
+
+/// The types of content which may be inlined.
+pub enum ContentType {
+    Css,
+    Js,
+    Svg,
+}
+
+impl ContentType {
+    /// Determine the content type of an asset from its file extension.
+    fn from_ext(ext: &str) -> Result<Self> {
+        Ok(match ext {
+            "css" => Self::Css,
+            "js" => Self::Js,
+            "svg" => Self::Svg,
+            _ => bail!(
+                r#"unsupported file extension `{}` for <link data-trunk rel="inline" .../>; only css, js & svg files may be inlined"#,
+                ext
+            ),
+        })
+    }
+}
+
+/// The content produced for an inline asset.
+pub enum InlineContent {
+    /// The asset's content, to be inlined directly into the HTML.
+    Inlined(String),
+    /// The asset exceeded the inline size limit, and was emitted as a hashed file instead.
+    Hashed(HashedFileOutput),
+}
+
+/// The output of an Inline build pipeline.
+pub struct InlineOutput {
+    /// The runtime build config.
+    pub cfg: Arc<RtcBuild>,
+    /// The ID of this pipeline.
+    pub id: usize,
+    /// The type of the content being inlined.
+    pub content_type: ContentType,
+    /// The finalized content of the asset.
+    pub content: InlineContent,
+}
+
+impl InlineOutput {
+    /// Replace this asset's link with the inlined content, or with a link to the hashed file.
+    ///
+    /// Any closing `</style` or `</script` tag within inlined CSS or JS is escaped, so that it can
+    /// not end the element early.
+    pub async fn finalize(self, dom: &mut Document) -> Result<()> {
+        let html = match (self.content_type, self.content) {
+            (ContentType::Css, InlineContent::Inlined(content)) => format!("<style>{}</style>", escape_raw_text(&content, "style")),
+            (ContentType::Js, InlineContent::Inlined(content)) => format!("<script>{}</script>", escape_raw_text(&content, "script")),
+            (ContentType::Svg, InlineContent::Inlined(content)) => content,
+            (ContentType::Css, InlineContent::Hashed(file)) => {
+                format!(r#"<link rel="stylesheet" href="{}"/>"#, self.cfg.asset_url(&file.file_name))
+            }
+            (ContentType::Js, InlineContent::Hashed(file)) => {
+                format!(r#"<script src="{}"></script>"#, self.cfg.asset_url(&file.file_name))
+            }
+            (ContentType::Svg, InlineContent::Hashed(file)) => {
+                format!(r#"<img src="{}"/>"#, self.cfg.asset_url(&file.file_name))
+            }
+        };
+        dom.select(&super::trunk_id_selector(self.id)).replace_with_html(html);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigOpts, ConfigOptsBuild};
+    use crate::test_util::TestDir;
+
+    /// Finalize the given inlined content into a document holding the link of asset ID 0.
+    async fn finalize_inlined(content_type: ContentType, content: &str) -> String {
+        let dir = TestDir::new("inline", &[("index.html", "<html></html>")]);
+        let opts = ConfigOptsBuild {
+            target: Some(dir.join("index.html")),
+            ..Default::default()
+        };
+        let cfg = ConfigOpts::rtc_build(opts, Some(dir.join("Trunk.toml")), None, false).await.unwrap();
+        let output = InlineOutput {
+            cfg,
+            id: 0,
+            content_type,
+            content: InlineContent::Inlined(content.into()),
+        };
+        let mut dom = Document::from(r#"<html><head><link data-trunk rel="inline" data-trunk-id="0"/></head><body></body></html>"#);
+        output.finalize(&mut dom).await.unwrap();
+        dom.html().to_string()
+    }
+
+    #[async_std::test]
+    async fn inlined_css_is_escaped_within_style() {
+        let html = finalize_inlined(ContentType::Css, r#"a::after { content: "</style><script>alert(1)</script>"; }"#).await;
+        assert!(
+            html.contains(r#"<style>a::after { content: "<\/style><script>alert(1)</script>"; }</style>"#),
+            "{}",
+            html
+        );
+    }
+
+    #[async_std::test]
+    async fn inlined_js_is_escaped_within_script() {
+        let html = finalize_inlined(ContentType::Js, "console.log('</Script>');").await;
+        assert!(html.contains(r"<script>console.log('<\/Script>');</script>"), "{}", html);
+    }
+}
@@ -3,31 +3,40 @@ mod copyfile;
 mod css;
 mod html;
 mod icon;
+mod inline;
 mod rust_app;
 mod rust_worker;
 mod sass;
 
+use std::collections::HashMap;
 use std::ffi::OsString;
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 use anyhow::{anyhow, bail, ensure, Context, Result};
 use async_std::fs;
+use async_std::path::PathBuf as AsyncPathBuf;
 use async_std::task::JoinHandle;
 use futures::channel::mpsc::Sender;
+use futures::stream::StreamExt;
 use indicatif::ProgressBar;
 use nipper::{Document, Selection};
+use serde::Serialize;
 
+use crate::common::{copy_with_retry, write_with_retry, HashAlgo};
 use crate::config::RtcBuild;
 use crate::pipelines::copydir::{CopyDir, CopyDirOutput};
 use crate::pipelines::copyfile::{CopyFile, CopyFileOutput};
 use crate::pipelines::css::{Css, CssOutput};
 use crate::pipelines::icon::{Icon, IconOutput};
+use crate::pipelines::inline::{Inline, InlineContent, InlineOutput};
 use crate::pipelines::rust_app::{RustApp, RustAppOutput};
 use crate::pipelines::rust_worker::{RustWorker, RustWorkerOutput};
 use crate::pipelines::sass::{Sass, SassOutput};
 
-pub use html::HtmlPipeline;
+pub use html::{HtmlPipeline, HtmlPipelineOutput};
 
 const ATTR_HREF: &str = "href";
 const ATTR_REL: &str = "rel";
@@ -44,6 +53,7 @@ pub enum TrunkLink {
     Css(Css),
     Sass(Sass),
     Icon(Icon),
+    Inline(Inline),
     CopyFile(CopyFile),
     CopyDir(CopyDir),
     RustApp(RustApp),
@@ -53,15 +63,17 @@ pub enum TrunkLink {
 impl TrunkLink {
     /// Construct a new instance.
     pub async fn from_html(
-        cfg: Arc<RtcBuild>, progress: ProgressBar, html_dir: Arc<PathBuf>, ignore_chan: Option<Sender<PathBuf>>, el: Selection<'_>, id: usize,
+        cfg: Arc<RtcBuild>, progress: ProgressBar, html_dir: Arc<PathBuf>, ignore_chan: Option<Sender<PathBuf>>, hash_cache: Arc<HashCache>,
+        el: Selection<'_>, id: usize,
     ) -> Result<Self> {
         let rel = el
             .attr(ATTR_REL)
             .ok_or_else(|| anyhow!("all <link data-trunk .../> elements must have a `rel` attribute indicating the asset type"))?;
         Ok(match rel.as_ref() {
             Sass::TYPE_SASS | Sass::TYPE_SCSS => Self::Sass(Sass::new(cfg.clone(), progress, html_dir, el, id).await?),
-            Icon::TYPE_ICON => Self::Icon(Icon::new(cfg.clone(), progress, html_dir, el, id).await?),
-            Css::TYPE_CSS => Self::Css(Css::new(cfg.clone(), progress, html_dir, el, id).await?),
+            Icon::TYPE_ICON => Self::Icon(Icon::new(cfg.clone(), progress, html_dir, hash_cache, el, id).await?),
+            Inline::TYPE_INLINE => Self::Inline(Inline::new(cfg.clone(), progress, html_dir, hash_cache, el, id).await?),
+            Css::TYPE_CSS => Self::Css(Css::new(cfg.clone(), progress, html_dir, hash_cache, el, id).await?),
             CopyFile::TYPE_COPY_FILE => Self::CopyFile(CopyFile::new(cfg.clone(), progress, html_dir, el, id).await?),
             CopyDir::TYPE_COPY_DIR => Self::CopyDir(CopyDir::new(cfg.clone(), progress, html_dir, el, id).await?),
             RustApp::TYPE_RUST_APP => Self::RustApp(RustApp::new(cfg.clone(), progress, html_dir, ignore_chan, el, id).await?),
@@ -73,12 +85,60 @@ impl TrunkLink {
         })
     }
 
+    /// The path of the source file or dir consumed by this asset's pipeline.
+    pub fn input_path(&self) -> &Path {
+        match self {
+            TrunkLink::Css(inner) => inner.input_path(),
+            TrunkLink::Sass(inner) => inner.input_path(),
+            TrunkLink::Icon(inner) => inner.input_path(),
+            TrunkLink::Inline(inner) => inner.input_path(),
+            TrunkLink::CopyFile(inner) => inner.input_path(),
+            TrunkLink::CopyDir(inner) => inner.input_path(),
+            TrunkLink::RustApp(inner) => inner.input_path(),
+            TrunkLink::RustWorker(inner) => inner.input_path(),
+        }
+    }
+
+    /// The asset type of this link, as declared by its `rel` attribute.
+    pub fn asset_type(&self) -> &'static str {
+        match self {
+            TrunkLink::Css(_) => Css::TYPE_CSS,
+            TrunkLink::Sass(_) => Sass::TYPE_SASS,
+            TrunkLink::Icon(_) => Icon::TYPE_ICON,
+            TrunkLink::Inline(_) => Inline::TYPE_INLINE,
+            TrunkLink::CopyFile(_) => CopyFile::TYPE_COPY_FILE,
+            TrunkLink::CopyDir(_) => CopyDir::TYPE_COPY_DIR,
+            TrunkLink::RustApp(_) => RustApp::TYPE_RUST_APP,
+            TrunkLink::RustWorker(_) => RustWorker::TYPE_RUST_WORKER,
+        }
+    }
+
+    /// Describe what this asset's pipeline intends to consume & emit, without running it.
+    pub fn plan(&self) -> AssetPlan {
+        let outputs = match self {
+            TrunkLink::Css(inner) => inner.planned_outputs(),
+            TrunkLink::Sass(inner) => inner.planned_outputs(),
+            TrunkLink::Icon(inner) => inner.planned_outputs(),
+            TrunkLink::Inline(inner) => inner.planned_outputs(),
+            TrunkLink::CopyFile(inner) => inner.planned_outputs(),
+            TrunkLink::CopyDir(inner) => inner.planned_outputs(),
+            TrunkLink::RustApp(inner) => inner.planned_outputs(),
+            TrunkLink::RustWorker(inner) => inner.planned_outputs(),
+        };
+        AssetPlan {
+            asset_type: self.asset_type(),
+            input: self.input_path().to_path_buf(),
+            outputs,
+        }
+    }
+
     /// Spawn the build pipeline for this asset.
     pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
         match self {
             TrunkLink::Css(inner) => inner.spawn(),
             TrunkLink::Sass(inner) => inner.spawn(),
             TrunkLink::Icon(inner) => inner.spawn(),
+            TrunkLink::Inline(inner) => inner.spawn(),
             TrunkLink::CopyFile(inner) => inner.spawn(),
             TrunkLink::CopyDir(inner) => inner.spawn(),
             TrunkLink::RustApp(inner) => inner.spawn(),
@@ -87,11 +147,35 @@ impl TrunkLink {
     }
 }
 
+/// A description of what an asset pipeline intends to consume & emit.
+#[derive(Debug, Serialize)]
+pub struct AssetPlan {
+    /// The asset type, as declared by the `rel` attribute of the asset's link.
+    pub asset_type: &'static str,
+    /// The path of the source file or dir consumed by the pipeline.
+    pub input: PathBuf,
+    /// The names of the files & dirs the pipeline intends to emit to the dist dir. Content hashes
+    /// are only known after building, and are represented by a `<hash>` placeholder.
+    pub outputs: Vec<String>,
+}
+
+impl fmt::Display for AssetPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outputs = if self.outputs.is_empty() {
+            String::from("(inlined into index.html)")
+        } else {
+            self.outputs.join(", ")
+        };
+        write!(f, "{:<10} {} -> {}", self.asset_type, self.input.display(), outputs)
+    }
+}
+
 /// The output of a `<trunk-link/>` asset pipeline.
 pub enum TrunkLinkPipelineOutput {
     Css(CssOutput),
     Sass(SassOutput),
     Icon(IconOutput),
+    Inline(InlineOutput),
     CopyFile(CopyFileOutput),
     CopyDir(CopyDirOutput),
     RustApp(RustAppOutput),
@@ -105,12 +189,30 @@ impl TrunkLinkPipelineOutput {
             TrunkLinkPipelineOutput::Css(out) => out.finalize(dom).await,
             TrunkLinkPipelineOutput::Sass(out) => out.finalize(dom).await,
             TrunkLinkPipelineOutput::Icon(out) => out.finalize(dom).await,
+            TrunkLinkPipelineOutput::Inline(out) => out.finalize(dom).await,
             TrunkLinkPipelineOutput::CopyFile(out) => out.finalize(dom).await,
             TrunkLinkPipelineOutput::CopyDir(out) => out.finalize(dom).await,
             TrunkLinkPipelineOutput::RustApp(out) => out.finalize(dom).await,
             TrunkLinkPipelineOutput::RustWorker(out) => out.finalize(dom).await,
         }
     }
+
+    /// The paths of all files & dirs which this pipeline emitted to the dist dir.
+    pub fn output_paths(&self) -> Vec<PathBuf> {
+        match self {
+            TrunkLinkPipelineOutput::Css(out) => vec![out.file.file_path.clone()],
+            TrunkLinkPipelineOutput::Sass(out) => vec![out.file.file_path.clone()],
+            TrunkLinkPipelineOutput::Icon(out) => vec![out.file.file_path.clone()],
+            TrunkLinkPipelineOutput::Inline(out) => match &out.content {
+                InlineContent::Hashed(file) => vec![file.file_path.clone()],
+                InlineContent::Inlined(_) => vec![],
+            },
+            TrunkLinkPipelineOutput::CopyFile(out) => vec![out.file_path().to_path_buf()],
+            TrunkLinkPipelineOutput::CopyDir(out) => vec![out.dir_path().to_path_buf()],
+            TrunkLinkPipelineOutput::RustApp(out) => vec![out.cfg.dist.join(&out.js_output), out.cfg.dist.join(&out.wasm_output)],
+            TrunkLinkPipelineOutput::RustWorker(out) => vec![out.cfg.dist.join(&out.js_output), out.cfg.dist.join(&out.wasm_output)],
+        }
+    }
 }
 
 /// An asset file to be processed by some build pipeline.
@@ -123,6 +225,8 @@ pub struct AssetFile {
     pub file_stem: OsString,
     /// The extension of the file.
     pub ext: String,
+    /// The size of the file in bytes.
+    pub size: u64,
 }
 
 impl AssetFile {
@@ -159,40 +263,144 @@ impl AssetFile {
             Some(ext) => ext.to_string_lossy().to_lowercase(),
             None => bail!("asset has no file extension {:?}", &path),
         };
+        let size = fs::metadata(&path)
+            .await
+            .with_context(|| format!("error reading file metadata {:?}", &path))?
+            .len();
         Ok(Self {
             path: path.into(),
             file_name,
             file_stem,
             ext,
+            size,
         })
     }
 
-    /// Copy this asset to the target dir.
-    pub async fn copy(&self, to_dir: &Path) -> Result<PathBuf> {
+    /// Copy this asset to the target dir, retrying transient write failures up to `retries` times.
+    pub async fn copy(&self, to_dir: &Path, retries: u32) -> Result<PathBuf> {
         let bytes = fs::read(&self.path)
             .await
             .with_context(|| format!("error reading file for copying {:?}", &self.path))?;
 
         let file_path = to_dir.join(&self.file_name);
-        fs::write(&file_path, bytes)
+        write_with_retry(&file_path, &bytes, retries)
             .await
             .with_context(|| format!("error copying file {:?} to {:?}", &self.path, &file_path))?;
         Ok(file_path)
     }
 
-    /// Copy this asset to the target dir after hashing its contents & updating the filename with the hash.
-    pub async fn copy_with_hash(&self, to_dir: &Path) -> Result<HashedFileOutput> {
+    /// Copy this asset to the target dir after hashing its contents & updating the filename with the hash,
+    /// retrying transient write failures up to `retries` times.
+    ///
+    /// When the given cache shows that the source is unchanged since a previous build & that its
+    /// hashed output is still in place, the cached output is reused without reading, hashing or
+    /// writing anything.
+    pub async fn copy_with_hash(&self, to_dir: &Path, hash_algo: HashAlgo, retries: u32, cache: &HashCache) -> Result<HashedFileOutput> {
+        let modified = fs::metadata(&self.path).await.and_then(|meta| meta.modified()).ok();
+        if let Some(output) = cache.get(&self.path, modified, self.size, to_dir) {
+            return Ok(output);
+        }
         let bytes = fs::read(&self.path)
             .await
             .with_context(|| format!("error reading file for copying {:?}", &self.path))?;
-        let hash = seahash::hash(bytes.as_ref());
-        let file_name = format!("{}-{:x}.{}", &self.file_stem.to_string_lossy(), hash, &self.ext);
+        let hash = hash_algo.hash(bytes.as_ref());
+        let file_name = format!("{}-{}.{}", &self.file_stem.to_string_lossy(), hash, &self.ext);
 
         let file_path = to_dir.join(&file_name);
-        fs::write(&file_path, bytes)
+        write_with_retry(&file_path, &bytes, retries)
             .await
             .with_context(|| format!("error copying file {:?} to {:?}", &self.path, &file_path))?;
-        Ok(HashedFileOutput { hash, file_path, file_name })
+        let output = HashedFileOutput { hash, file_path, file_name };
+        if let Some(modified) = modified {
+            cache.insert(self.path.clone(), CachedHash {
+                modified,
+                size: self.size,
+                hash: output.hash.clone(),
+                output: Some((output.file_path.clone(), output.file_name.clone())),
+            });
+        }
+        Ok(output)
+    }
+}
+
+/// A cache of the content hashes of files, keyed by their paths, which lets watch rebuilds skip
+/// re-reading, re-hashing & re-writing assets whose sources are unchanged, and lets the server
+/// derive content based entity tags without reading each file on every request.
+///
+/// Entries are validated against the modification time & size of their file, along with the
+/// existence of their hashed output file if any, so that edited sources & cleaned dist dirs are
+/// rebuilt.
+#[derive(Default)]
+pub struct HashCache(Mutex<HashMap<PathBuf, CachedHash>>);
+
+/// A cached content hash of a file, along with the state of the file when it was hashed.
+struct CachedHash {
+    /// The modification time of the file when it was hashed.
+    modified: SystemTime,
+    /// The size in bytes of the file when it was hashed.
+    size: u64,
+    /// The hex-encoded hash of the file's content.
+    hash: String,
+    /// The path to & name of the hashed output file written for the file, if any.
+    output: Option<(PathBuf, String)>,
+}
+
+impl HashCache {
+    /// Hash the content of the file at the given path with the given algorithm, reusing the
+    /// cached hash while the file's modification time & size are unchanged.
+    pub async fn hash_file(&self, path: &Path, hash_algo: HashAlgo) -> Result<String> {
+        let meta = fs::metadata(path).await.with_context(|| format!("error reading metadata of {:?}", path))?;
+        let modified = meta.modified().ok();
+        if let Some(hash) = self.entry(path, modified, meta.len()).map(|entry| entry.hash) {
+            return Ok(hash);
+        }
+        let bytes = fs::read(path).await.with_context(|| format!("error reading file for hashing {:?}", path))?;
+        let hash = hash_algo.hash(bytes.as_ref());
+        if let Some(modified) = modified {
+            self.insert(path.to_path_buf(), CachedHash {
+                modified,
+                size: meta.len(),
+                hash: hash.clone(),
+                output: None,
+            });
+        }
+        Ok(hash)
+    }
+
+    /// Get the cached output for the given source, if it is still valid & was written to the given dir.
+    fn get(&self, path: &Path, modified: Option<SystemTime>, size: u64, to_dir: &Path) -> Option<HashedFileOutput> {
+        let entry = self.entry(path, modified, size)?;
+        let (file_path, file_name) = entry.output?;
+        if file_path.parent() != Some(to_dir) || !file_path.exists() {
+            return None;
+        }
+        Some(HashedFileOutput {
+            hash: entry.hash,
+            file_path,
+            file_name,
+        })
+    }
+
+    /// Get a copy of the entry for the given file, if its modification time & size are unchanged.
+    fn entry(&self, path: &Path, modified: Option<SystemTime>, size: u64) -> Option<CachedHash> {
+        let cache = self.0.lock().ok()?;
+        let entry = cache.get(path)?;
+        if Some(entry.modified) != modified || entry.size != size {
+            return None;
+        }
+        Some(CachedHash {
+            modified: entry.modified,
+            size: entry.size,
+            hash: entry.hash.clone(),
+            output: entry.output.clone(),
+        })
+    }
+
+    /// Cache the given entry for the given file.
+    fn insert(&self, path: PathBuf, entry: CachedHash) {
+        if let Ok(mut cache) = self.0.lock() {
+            cache.insert(path, entry);
+        }
     }
 }
 
@@ -202,17 +410,169 @@ impl AssetFile {
 /// the same contents, and the filename of the new file includes the hexadecimal representation of
 /// the hash before the file extension, as so: `{file_stem}-{hash}.{ext}`.
 pub struct HashedFileOutput {
-    /// The hash of the output file.
+    /// The hex-encoded hash of the output file.
     #[allow(dead_code)]
-    hash: u64,
+    hash: String,
     /// The canonical path to the output file.
-    #[allow(dead_code)]
     file_path: PathBuf,
     /// The output file's name.
     file_name: String,
 }
 
+/// Write a copy of the given hashed output file alongside it under the given unhashed name, when
+/// the `emit_unhashed_aliases` build option is enabled.
+///
+/// Aliases let consumers reference stable file names, at the cost of those names not being safe
+/// to cache long term, as their contents change from build to build.
+pub(crate) async fn write_unhashed_alias(cfg: &RtcBuild, hashed_path: &Path, unhashed_name: &str) -> Result<()> {
+    if !cfg.emit_unhashed_aliases {
+        return Ok(());
+    }
+    let alias_path = hashed_path.with_file_name(unhashed_name);
+    copy_with_retry(hashed_path, &alias_path, cfg.fs_retries)
+        .await
+        .with_context(|| format!("error writing unhashed alias {:?} of {:?}", &alias_path, hashed_path))?;
+    Ok(())
+}
+
+/// Recursively scan the given dist dir, building an `AssetFile` for each file found.
+///
+/// Each returned asset's `path` is canonical and lives within the dist dir, so its path relative
+/// to the dist dir may be obtained by stripping the canonical dist dir prefix. Files without an
+/// extension are skipped, as they can not be represented as an `AssetFile`. Assets are returned
+/// sorted by path.
+pub async fn scan_dist(dist: &Path) -> Result<Vec<AssetFile>> {
+    let dist: PathBuf = fs::canonicalize(dist)
+        .await
+        .with_context(|| format!("error getting canonical path for dist dir {:?}", dist))?
+        .into();
+    let mut assets = vec![];
+    let mut dirs = vec![AsyncPathBuf::from(dist.clone())];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(&dir)
+            .await
+            .with_context(|| format!("error reading dist dir {:?}", &dir))?;
+        while let Some(entry) = entries.next().await {
+            let path = entry.with_context(|| format!("error reading dist dir entry in {:?}", &dir))?.path();
+            if path.is_dir().await {
+                dirs.push(path);
+            } else if path.extension().is_some() {
+                let rel_path = path.strip_prefix(&dist).map(|rel| rel.to_path_buf())?;
+                assets.push(AssetFile::new(&dist, rel_path.into()).await?);
+            }
+        }
+    }
+    assets.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(assets)
+}
+
+/// Escape all closing tags of the given raw text element, such as `</script`, within the given
+/// content, which would otherwise end the element early once inlined into the HTML.
+///
+/// The escaped `<\/` is equivalent to `</` within the JS & CSS strings, comments & regexes in which
+/// such a sequence may validly occur.
+pub(crate) fn escape_raw_text(content: &str, element: &str) -> String {
+    let close = format!("</{}", element.to_ascii_lowercase());
+    // NOTE: ASCII lowercasing preserves byte offsets, so matches index into the original content.
+    let lowercase = content.to_ascii_lowercase();
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for (idx, _) in lowercase.match_indices(&close) {
+        out.push_str(&content[last..idx]);
+        out.push_str("<\\/");
+        last = idx + 2;
+    }
+    out.push_str(&content[last..]);
+    out
+}
+
 /// Create the CSS selector for selecting a trunk link by ID.
 pub(self) fn trunk_id_selector(id: usize) -> String {
     format!(r#"link[{}="{}"]"#, TRUNK_ID, id)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigOpts, ConfigOptsBuild};
+    use crate::test_util::TestDir;
+
+    #[async_std::test]
+    async fn scan_dist_finds_nested_assets_in_path_order() {
+        let files = &[("index.html", ""), ("assets/img/logo.png", ""), ("assets/app.css", ""), ("LICENSE", "")];
+        let dist = TestDir::new("scan-dist", files);
+
+        let assets = scan_dist(&dist).await.unwrap();
+        let paths = assets
+            .iter()
+            .map(|asset| asset.path.strip_prefix(&dist).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<_>>();
+        // Files without an extension are skipped.
+        assert_eq!(paths, vec!["assets/app.css", "assets/img/logo.png", "index.html"]);
+    }
+
+    /// Copy each of the given assets of the given dir into the given dist dir with hashing,
+    /// returning the output paths.
+    async fn hashed_copies(dir: &Path, names: &[&str], dist: &Path, cache: &HashCache) -> Vec<PathBuf> {
+        let mut outputs = vec![];
+        for name in names {
+            let asset = AssetFile::new(dir, name.into()).await.unwrap();
+            outputs.push(asset.copy_with_hash(dist, HashAlgo::default(), 0, cache).await.unwrap().file_path);
+        }
+        outputs
+    }
+
+    #[async_std::test]
+    async fn hashed_copies_are_named_with_the_selected_hash() {
+        let dir = TestDir::new("hash-algo", &[("app.css", "body {}")]);
+        let dist = TestDir::new("hash-algo-dist", &[]);
+        let asset = AssetFile::new(&dir, "app.css".into()).await.unwrap();
+        for algo in &[HashAlgo::Seahash, HashAlgo::Sha256, HashAlgo::Sha384] {
+            let output = asset.copy_with_hash(&dist, *algo, 0, &HashCache::default()).await.unwrap();
+            assert_eq!(output.hash, algo.hash(b"body {}"));
+            assert_eq!(output.file_name, format!("app-{}.css", output.hash));
+            assert!(output.file_path.is_file());
+        }
+    }
+
+    #[async_std::test]
+    async fn unhashed_aliases_are_copies_of_the_hashed_outputs() {
+        let dir = TestDir::new("unhashed-alias", &[("index.html", ""), ("app.css", "body {}")]);
+        let dist = dir.create_dir("dist");
+        let asset = AssetFile::new(&dir, "app.css".into()).await.unwrap();
+        let output = asset.copy_with_hash(&dist, HashAlgo::default(), 0, &HashCache::default()).await.unwrap();
+        for emit_unhashed_aliases in &[false, true] {
+            let opts = ConfigOptsBuild {
+                target: Some(dir.join("index.html")),
+                emit_unhashed_aliases: *emit_unhashed_aliases,
+                ..Default::default()
+            };
+            let cfg = ConfigOpts::rtc_build(opts, Some(dir.join("Trunk.toml")), None, false).await.unwrap();
+            write_unhashed_alias(&cfg, &output.file_path, "app.css").await.unwrap();
+            assert_eq!(dist.join("app.css").is_file(), *emit_unhashed_aliases);
+        }
+        assert_eq!(std::fs::read(dist.join("app.css")).unwrap(), std::fs::read(&output.file_path).unwrap());
+    }
+
+    #[async_std::test]
+    async fn hash_cache_only_rehashes_changed_assets() {
+        let dir = TestDir::new("hash-cache", &[("app.css", "body {}"), ("theme.css", "a {}")]);
+        let dist = dir.create_dir("dist");
+        let cache = HashCache::default();
+        let names = &["app.css", "theme.css"];
+        let first = hashed_copies(&dir, names, &dist, &cache).await;
+
+        // Backdate the outputs, so that any rewrite of them would be visible.
+        let epoch = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        for output in &first {
+            std::fs::File::options().write(true).open(output).unwrap().set_modified(epoch).unwrap();
+        }
+        dir.write("theme.css", "a { color: red; }");
+        let second = hashed_copies(&dir, names, &dist, &cache).await;
+
+        assert_eq!(second[0], first[0]);
+        assert_eq!(std::fs::metadata(&second[0]).unwrap().modified().unwrap(), epoch, "the unchanged asset was rewritten");
+        assert_ne!(second[1], first[1]);
+        assert_eq!(std::fs::read_to_string(&second[1]).unwrap(), "a { color: red; }");
+    }
+}
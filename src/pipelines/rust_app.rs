@@ -1,5 +1,6 @@
 //! Rust application pipeline.
 
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -12,10 +13,10 @@ use futures::channel::mpsc::Sender;
 use indicatif::ProgressBar;
 use nipper::{Document, Selection};
 
-use super::TrunkLinkPipelineOutput;
+use super::{write_unhashed_alias, TrunkLinkPipelineOutput};
 use super::{ATTR_HREF, SNIPPETS_DIR};
-use crate::common::copy_dir_recursive;
-use crate::config::{CargoMetadata, RtcBuild};
+use crate::common::{copy_dir_recursive, copy_with_retry};
+use crate::config::{dist_path_prefix, CargoMetadata, RtcBuild};
 
 /// A Rust application pipeline.
 pub struct RustApp {
@@ -83,6 +84,24 @@ impl RustApp {
         })
     }
 
+    /// The path of the Cargo manifest of the crate built by this pipeline.
+    pub fn input_path(&self) -> &std::path::Path {
+        std::path::Path::new(&self.manifest.manifest_path)
+    }
+
+    /// The names of the files this pipeline intends to emit to the dist dir, with a `<hash>`
+    /// placeholder standing in for the content hash.
+    pub fn planned_outputs(&self) -> Vec<String> {
+        let prefix = self.bindgen_out_prefix();
+        vec![format!("{}index-<hash>.js", prefix), format!("{}index-<hash>_bg.wasm", prefix)]
+    }
+
+    /// The path prefix, relative to the dist dir & using `/` separators, of the files emitted
+    /// by wasm-bindgen, which is empty unless a `bindgen_out_dir` or `assets_dir` is configured.
+    fn bindgen_out_prefix(&self) -> String {
+        dist_path_prefix(self.cfg.rust_app_out_dir())
+    }
+
     /// Spawn a new pipeline.
     pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
         spawn(self.build())
@@ -101,6 +120,7 @@ impl RustApp {
         }
 
         // Spawn the cargo build process.
+        let verbosity = self.cargo_verbosity();
         let mut args = vec![
             "build",
             "--target=wasm32-unknown-unknown",
@@ -114,8 +134,14 @@ impl RustApp {
             args.push("--bin");
             args.push(bin);
         }
-        let build_output = Command::new("cargo")
+        if let Some(verbosity) = &verbosity {
+            args.push(verbosity);
+        }
+        args.extend(self.cfg.cargo_args.iter().map(String::as_str));
+        let envs = self.cargo_envs();
+        let build_output = Command::new(&self.cfg.cargo_command)
             .args(args.as_slice())
+            .envs(&envs)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -132,8 +158,9 @@ impl RustApp {
         // Perform a final cargo invocation on success to get artifact names.
         self.progress.set_message("fetching artifacts");
         args.push("--message-format=json");
-        let artifacts_out = Command::new("cargo")
+        let artifacts_out = Command::new(&self.cfg.cargo_command)
             .args(args.as_slice())
+            .envs(&envs)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .spawn()
@@ -168,10 +195,55 @@ impl RustApp {
         // Hash the built wasm app, then use that as the out-name param.
         self.progress.set_message("processing WASM");
         let wasm_bytes = async_std::fs::read(&wasm).await.context("error reading wasm file for hash generation")?;
-        let hashed_name = format!("index-{:x}", seahash::hash(&wasm_bytes));
+        let hashed_name = format!("index-{}", self.cfg.hash_algo.hash(&wasm_bytes));
         Ok((wasm, hashed_name))
     }
 
+    /// The verbosity flag to pass to cargo invocations, if any.
+    fn cargo_verbosity(&self) -> Option<String> {
+        match (self.cfg.cargo_quiet, self.cfg.cargo_verbose) {
+            (true, _) => Some(String::from("--quiet")),
+            (false, 0) => None,
+            (false, level) => Some(format!("-{}", "v".repeat(level as usize))),
+        }
+    }
+
+    /// The env vars to set for cargo invocations.
+    ///
+    /// `RUSTFLAGS` is composed in this order: the `RUSTFLAGS` of `[build.env]`, else of Trunk's
+    /// own env; then the configured `rustflags`; then `-D warnings` with `deny_warnings`; then
+    /// `-C panic=abort` with `panic_abort` for release builds. It is only set for the cargo
+    /// subprocess, never for Trunk's own env.
+    ///
+    /// NOTE WELL: as `RUSTFLAGS` is part of cargo's fingerprint, changing these flags rebuilds the
+    /// entire dependency graph.
+    fn cargo_envs(&self) -> HashMap<String, String> {
+        let mut envs = self.cfg.env.clone();
+        let mut flags = self.cfg.rustflags.clone();
+        if self.cfg.deny_warnings {
+            flags.extend(vec![String::from("-D"), String::from("warnings")]);
+        }
+        if self.cfg.release && self.cfg.panic_abort {
+            flags.extend(vec![String::from("-C"), String::from("panic=abort")]);
+        }
+        if flags.is_empty() {
+            return envs;
+        }
+        let mut rustflags = envs
+            .get("RUSTFLAGS")
+            .cloned()
+            .or_else(|| std::env::var("RUSTFLAGS").ok())
+            .unwrap_or_default();
+        for flag in flags {
+            if !rustflags.is_empty() {
+                rustflags.push(' ');
+            }
+            rustflags.push_str(&flag);
+        }
+        envs.insert("RUSTFLAGS".into(), rustflags);
+        envs
+    }
+
     async fn wasm_bindgen_build(&self, wasm: PathBuf, hashed_name: String) -> Result<RustAppOutput> {
         self.progress.set_message("calling wasm-bindgen");
 
@@ -182,11 +254,19 @@ impl RustApp {
             .await
             .context("error creating wasm-bindgen output dir")?;
 
+        if let Some(expected) = &self.cfg.wasm_bindgen_version {
+            check_wasm_bindgen_version(expected).await?;
+        }
+
         // Build up args for calling wasm-bindgen.
         let arg_out_path = format!("--out-dir={}", bindgen_out.display());
         let arg_out_name = format!("--out-name={}", &hashed_name);
         let target_wasm = wasm.to_string_lossy().to_string();
-        let args = vec!["--target=web", &arg_out_path, &arg_out_name, "--no-typescript", &target_wasm];
+        let mut args = vec!["--target=web", &arg_out_path, &arg_out_name, "--no-typescript"];
+        if self.cfg.source_maps {
+            args.push("--keep-debug");
+        }
+        args.push(&target_wasm);
 
         // Invoke wasm-bindgen.
         let build_output = Command::new("wasm-bindgen")
@@ -204,34 +284,82 @@ impl RustApp {
             String::from_utf8_lossy(&build_output.stderr),
         );
 
-        // Copy the generated WASM & JS loader to the dist dir.
-        self.progress.set_message("copying generated artifacts");
         let hashed_js_name = format!("{}.js", &hashed_name);
         let hashed_wasm_name = format!("{}_bg.wasm", &hashed_name);
         let js_loader_path = bindgen_out.join(&hashed_js_name);
-        let js_loader_path_dist = self.cfg.dist.join(&hashed_js_name);
         let wasm_path = bindgen_out.join(&hashed_wasm_name);
-        let wasm_path_dist = self.cfg.dist.join(&hashed_wasm_name);
-        fs::copy(js_loader_path, js_loader_path_dist)
+        self.wasm_opt_build(&wasm_path).await?;
+
+        // Copy the generated WASM & JS loader to the dist dir, or to the configured dir within it.
+        self.progress.set_message("copying generated artifacts");
+        let dist_out = match self.cfg.rust_app_out_dir() {
+            Some(dir) => self.cfg.dist.join(dir),
+            None => self.cfg.dist.clone(),
+        };
+        fs::create_dir_all(dist_out.as_path())
+            .await
+            .context("error creating wasm-bindgen output dir within dist dir")?;
+        let js_loader_path_dist = dist_out.join(&hashed_js_name);
+        let wasm_path_dist = dist_out.join(&hashed_wasm_name);
+        copy_with_retry(&js_loader_path, &js_loader_path_dist, self.cfg.fs_retries)
             .await
             .context("error copying JS loader file to dist dir")?;
-        fs::copy(wasm_path, wasm_path_dist).await.context("error copying wasm file to dist dir")?;
+        copy_with_retry(&wasm_path, &wasm_path_dist, self.cfg.fs_retries)
+            .await
+            .context("error copying wasm file to dist dir")?;
+        // The JS loader resolves the WASM file relative to itself, so the aliases load together.
+        write_unhashed_alias(&self.cfg, &js_loader_path_dist, "index.js").await?;
+        write_unhashed_alias(&self.cfg, &wasm_path_dist, "index_bg.wasm").await?;
 
-        // Check for any snippets, and copy them over.
+        // Check for any snippets, and copy them over alongside the JS loader, which imports them
+        // by relative path.
         let snippets_dir = bindgen_out.join(SNIPPETS_DIR);
         if Path::new(&snippets_dir).exists().await {
-            copy_dir_recursive(bindgen_out.join(SNIPPETS_DIR), self.cfg.dist.join(SNIPPETS_DIR))
+            copy_dir_recursive(bindgen_out.join(SNIPPETS_DIR), dist_out.join(SNIPPETS_DIR), true)
                 .await
                 .context("error copying snippets dir")?;
         }
 
+        let prefix = self.bindgen_out_prefix();
         Ok(RustAppOutput {
             id: self.id,
             cfg: self.cfg.clone(),
-            js_output: hashed_js_name,
-            wasm_output: hashed_wasm_name,
+            js_output: format!("{}{}", prefix, hashed_js_name),
+            wasm_output: format!("{}{}", prefix, hashed_wasm_name),
+            version: self.manifest.package.version.to_string(),
         })
     }
+
+    /// Optimize the given wasm file in place via wasm-opt, unless disabled.
+    async fn wasm_opt_build(&self, wasm: &std::path::Path) -> Result<()> {
+        let args = match wasm_opt_args(self.cfg.wasm_opt.as_deref(), wasm) {
+            Some(args) => args,
+            None => return Ok(()),
+        };
+        self.progress.set_message("calling wasm-opt");
+        let output = Command::new("wasm-opt")
+            .args(args.as_slice())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("error spawning wasm-opt call; ensure wasm-opt is installed & available on your PATH, or set `wasm_opt` to `off`")?
+            .output()
+            .await
+            .context("error during wasm-opt call")?;
+        ensure!(
+            output.status.success(),
+            "wasm-opt call returned a bad status {}",
+            String::from_utf8_lossy(&output.stderr),
+        );
+        Ok(())
+    }
+}
+
+/// The args of the wasm-opt call optimizing the given wasm file in place at the given level, or
+/// `None` when wasm-opt is disabled.
+fn wasm_opt_args(level: Option<&str>, wasm: &std::path::Path) -> Option<Vec<String>> {
+    let wasm = wasm.to_string_lossy().to_string();
+    level.map(|level| vec![format!("-O{}", level), format!("--output={}", wasm), wasm])
 }
 
 /// The output of a cargo build pipeline.
@@ -240,24 +368,256 @@ pub struct RustAppOutput {
     pub cfg: Arc<RtcBuild>,
     /// The ID of this pipeline.
     pub id: Option<usize>,
-    /// The filename of the generated JS loader file written to the dist dir.
+    /// The path, relative to the dist dir, of the generated JS loader file written to the dist dir.
     pub js_output: String,
-    /// The filename of the generated WASM file written to the dist dir.
+    /// The path, relative to the dist dir, of the generated WASM file written to the dist dir.
     pub wasm_output: String,
+    /// The version of the app's crate, which the HTML pipeline stamps into the output HTML.
+    pub version: String,
 }
 
 impl RustAppOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
-        let script = format!(
-            r#"<script type="module">import init from '{base}{js}';init('{base}{wasm}');</script>"#,
-            base = self.cfg.public_url,
-            js = &self.js_output,
-            wasm = &self.wasm_output,
-        );
+        let script = match &self.cfg.pattern_script {
+            Some(pattern) => self.render_pattern(pattern),
+            None => format!(
+                r#"<script type="module">import init from '{base}{js}';init('{base}{wasm}');</script>"#,
+                base = self.cfg.asset_base(),
+                js = &self.js_output,
+                wasm = &self.wasm_output,
+            ),
+        };
         match self.id {
             Some(id) => dom.select(&super::trunk_id_selector(id)).replace_with_html(script),
             None => dom.select("html head").append_html(script),
         }
+        if let Some(pattern) = &self.cfg.pattern_preload {
+            dom.select("html head").append_html(self.render_pattern(pattern));
+        }
         Ok(())
     }
+
+    /// Render the given `pattern_script` or `pattern_preload` template, replacing `{base}`, `{js}`
+    /// & `{wasm}` with the asset base URL & the paths of the JS loader & WASM file.
+    fn render_pattern(&self, pattern: &str) -> String {
+        pattern
+            .replace("{base}", self.cfg.asset_base())
+            .replace("{js}", &self.js_output)
+            .replace("{wasm}", &self.wasm_output)
+    }
+}
+
+/// Check that the wasm-bindgen found on the `PATH` reports the given version.
+///
+/// The wasm-bindgen CLI must match the version of the `wasm-bindgen` crate used by the app, so
+/// pinning it keeps builds from drifting along with whatever version happens to be installed.
+async fn check_wasm_bindgen_version(expected: &str) -> Result<()> {
+    let output = Command::new("wasm-bindgen")
+        .arg("--version")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .context("error spawning wasm-bindgen to check its version")?;
+    ensure!(
+        output.status.success(),
+        "wasm-bindgen version check returned a bad status {}",
+        String::from_utf8_lossy(&output.stderr),
+    );
+    ensure_wasm_bindgen_version(&String::from_utf8_lossy(&output.stdout), expected)
+}
+
+/// Check that the given output of `wasm-bindgen --version` reports the given version.
+fn ensure_wasm_bindgen_version(stdout: &str, expected: &str) -> Result<()> {
+    // The output is of the form `wasm-bindgen 0.2.68`.
+    let found = stdout.split_whitespace().nth(1).unwrap_or_else(|| stdout.trim());
+    ensure!(
+        found == expected.trim_start_matches('v'),
+        "wasm-bindgen version mismatch: expected {} (from `wasm_bindgen_version`), but found {} on the PATH",
+        expected,
+        found,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigOpts, ConfigOptsBuild};
+    use crate::test_util::TestDir;
+
+    /// Create the default Rust app pipeline of a minimal crate, built with the given options.
+    async fn test_rust_app(name: &str, opts: ConfigOptsBuild) -> (TestDir, RustApp) {
+        let dir = TestDir::new(name, &[
+            ("Cargo.toml", "[package]\nname = \"app\"\nversion = \"0.1.0\"\nedition = \"2018\"\n"),
+            ("src/main.rs", "fn main() {}\n"),
+            ("index.html", "<html></html>"),
+        ]);
+        let opts = ConfigOptsBuild {
+            target: Some(dir.join("index.html")),
+            ..opts
+        };
+        let cfg = ConfigOpts::rtc_build(opts, Some(dir.join("Trunk.toml")), None, false).await.unwrap();
+        let app = RustApp::new_default(cfg, ProgressBar::hidden(), Arc::new(dir.to_path_buf()), None)
+            .await
+            .unwrap();
+        (dir, app)
+    }
+
+    #[async_std::test]
+    async fn cargo_verbosity_is_passed_through() {
+        for (quiet, verbose, expected) in &[(false, None, None), (true, None, Some("--quiet")), (false, Some(1), Some("-v")), (false, Some(3), Some("-vvv"))] {
+            let opts = ConfigOptsBuild {
+                cargo_quiet: *quiet,
+                cargo_verbose: *verbose,
+                ..Default::default()
+            };
+            let (_dir, app) = test_rust_app("cargo-verbosity", opts).await;
+            assert_eq!(app.cargo_verbosity().as_deref(), *expected);
+        }
+    }
+
+    /// The `RUSTFLAGS` passed to cargo by the given pipeline, if any.
+    fn rustflags(app: &RustApp) -> Option<String> {
+        app.cargo_envs().get("RUSTFLAGS").cloned()
+    }
+
+    /// Build options whose `[build.env]` sets the given `RUSTFLAGS`, which then take precedence
+    /// over any `RUSTFLAGS` in the env of the tests.
+    fn with_env_rustflags(rustflags: &str, opts: ConfigOptsBuild) -> ConfigOptsBuild {
+        ConfigOptsBuild {
+            env: Some(vec![(String::from("RUSTFLAGS"), rustflags.to_string())].into_iter().collect()),
+            ..opts
+        }
+    }
+
+    #[async_std::test]
+    async fn panic_abort_only_applies_to_release_builds() {
+        for (release, expected) in &[(true, "--cfg env -C panic=abort"), (false, "--cfg env")] {
+            let opts = ConfigOptsBuild {
+                release: *release,
+                panic_abort: true,
+                ..Default::default()
+            };
+            let (_dir, app) = test_rust_app("panic-abort", with_env_rustflags("--cfg env", opts)).await;
+            assert_eq!(rustflags(&app).as_deref(), Some(*expected));
+        }
+    }
+
+    #[async_std::test]
+    async fn rustflags_are_appended_to_the_env_rustflags() {
+        let opts = ConfigOptsBuild {
+            rustflags: Some(vec!["-C".into(), "target-cpu=mvp".into()]),
+            ..Default::default()
+        };
+        let (_dir, app) = test_rust_app("rustflags", with_env_rustflags("--cfg env", opts.clone())).await;
+        assert_eq!(rustflags(&app).as_deref(), Some("--cfg env -C target-cpu=mvp"));
+        let (_dir, app) = test_rust_app("rustflags", with_env_rustflags("", opts)).await;
+        assert_eq!(rustflags(&app).as_deref(), Some("-C target-cpu=mvp"));
+        // Trunk's own env is left untouched.
+        assert_ne!(std::env::var("RUSTFLAGS").ok().as_deref(), Some("-C target-cpu=mvp"));
+    }
+
+    #[async_std::test]
+    async fn deny_warnings_follows_the_rustflags() {
+        let opts = ConfigOptsBuild {
+            release: true,
+            rustflags: Some(vec!["-C".into(), "target-cpu=mvp".into()]),
+            deny_warnings: true,
+            panic_abort: true,
+            ..Default::default()
+        };
+        let (_dir, app) = test_rust_app("deny-warnings", with_env_rustflags("--cfg env", opts)).await;
+        assert_eq!(rustflags(&app).as_deref(), Some("--cfg env -C target-cpu=mvp -D warnings -C panic=abort"));
+    }
+
+    /// The HTML of an empty document, once finalized with the output the given pipeline would
+    /// produce for a build hashed as `abc`.
+    async fn finalized_html(app: &RustApp) -> String {
+        let prefix = app.bindgen_out_prefix();
+        let output = RustAppOutput {
+            cfg: app.cfg.clone(),
+            id: None,
+            js_output: format!("{}index-abc.js", prefix),
+            wasm_output: format!("{}index-abc_bg.wasm", prefix),
+            version: String::from("0.1.0"),
+        };
+        let mut dom = Document::from("<html><head></head><body></body></html>");
+        output.finalize(&mut dom).await.unwrap();
+        dom.select("head").html().to_string()
+    }
+
+    #[async_std::test]
+    async fn bindgen_out_dir_is_prefixed_to_the_outputs() {
+        let opts = ConfigOptsBuild {
+            public_url: Some(String::from("/app/")),
+            bindgen_out_dir: Some(PathBuf::from("pkg")),
+            ..Default::default()
+        };
+        let (_dir, app) = test_rust_app("bindgen-out-dir", opts).await;
+        assert_eq!(app.planned_outputs(), vec!["pkg/index-<hash>.js", "pkg/index-<hash>_bg.wasm"]);
+        assert_eq!(
+            finalized_html(&app).await,
+            r#"<head><script type="module">import init from '/app/pkg/index-abc.js';init('/app/pkg/index-abc_bg.wasm');</script></head>"#
+        );
+    }
+
+    #[async_std::test]
+    async fn relative_urls_override_the_public_url() {
+        let opts = ConfigOptsBuild {
+            public_url: Some(String::from("/app/")),
+            relative_urls: true,
+            ..Default::default()
+        };
+        let (_dir, app) = test_rust_app("relative-urls", opts).await;
+        let html = finalized_html(&app).await;
+        assert!(html.contains("import init from './index-abc.js';init('./index-abc_bg.wasm');"), "{}", html);
+        assert!(!html.contains("'/"), "{}", html);
+    }
+
+    #[async_std::test]
+    async fn patterns_control_the_injected_tags() {
+        let opts = ConfigOptsBuild {
+            public_url: Some(String::from("/app/")),
+            pattern_script: Some(String::from(r#"<script defer src="{base}{js}" data-wasm="{base}{wasm}"></script>"#)),
+            pattern_preload: Some(String::from(r#"<link rel="preload" href="{base}{wasm}" as="fetch" crossorigin>"#)),
+            ..Default::default()
+        };
+        let (_dir, app) = test_rust_app("patterns", opts).await;
+        assert_eq!(
+            finalized_html(&app).await,
+            concat!(
+                r#"<head><script defer="" src="/app/index-abc.js" data-wasm="/app/index-abc_bg.wasm"></script>"#,
+                r#"<link rel="preload" href="/app/index-abc_bg.wasm" as="fetch" crossorigin=""></head>"#,
+            )
+        );
+    }
+
+    #[test]
+    fn wasm_bindgen_version_mismatch_names_both_versions() {
+        ensure_wasm_bindgen_version("wasm-bindgen 0.2.68\n", "0.2.68").unwrap();
+        ensure_wasm_bindgen_version("wasm-bindgen 0.2.68\n", "v0.2.68").unwrap();
+        let err = ensure_wasm_bindgen_version("wasm-bindgen 0.2.69\n", "0.2.68").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "wasm-bindgen version mismatch: expected 0.2.68 (from `wasm_bindgen_version`), but found 0.2.69 on the PATH"
+        );
+    }
+
+    #[test]
+    fn wasm_opt_args_pass_the_level() {
+        let wasm = std::path::Path::new("/target/wasm-bindgen/release/index-abc_bg.wasm");
+        assert_eq!(wasm_opt_args(Some("z"), wasm).unwrap(), vec![
+            String::from("-Oz"),
+            String::from("--output=/target/wasm-bindgen/release/index-abc_bg.wasm"),
+            String::from("/target/wasm-bindgen/release/index-abc_bg.wasm"),
+        ]);
+        assert_eq!(wasm_opt_args(Some("3"), wasm).unwrap()[0], "-O3");
+    }
+
+    #[test]
+    fn wasm_opt_off_skips_wasm_opt() {
+        let wasm = std::path::Path::new("index_bg.wasm");
+        assert!(wasm_opt_args(None, wasm).is_none());
+    }
 }
@@ -2,7 +2,7 @@
 
 #![allow(dead_code, unused_variables)] // TODO: remove this when this pipeline type is implemented.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{bail, Result};
@@ -37,6 +37,16 @@ impl RustWorker {
         bail!(r#"the rust web worker asset type `<link data-trunk rel="rust-worker" .../>` is not yet supported"#)
     }
 
+    /// The path of the Cargo manifest of the crate built by this pipeline.
+    pub fn input_path(&self) -> &Path {
+        Path::new(&self.manifest.manifest_path)
+    }
+
+    /// The names of the files this pipeline intends to emit to the dist dir.
+    pub fn planned_outputs(&self) -> Vec<String> {
+        vec![]
+    }
+
     /// Spawn a new pipeline.
     pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
         unimplemented!()
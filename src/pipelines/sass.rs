@@ -1,16 +1,16 @@
 //! Sass/Scss asset pipeline.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Context, Result};
-use async_std::fs;
 use async_std::task::{spawn, spawn_blocking, JoinHandle};
 use indicatif::ProgressBar;
 use nipper::{Document, Selection};
 
 use super::ATTR_HREF;
-use super::{AssetFile, HashedFileOutput, TrunkLinkPipelineOutput};
+use super::{write_unhashed_alias, AssetFile, HashedFileOutput, TrunkLinkPipelineOutput};
+use crate::common::write_with_retry;
 use crate::config::RtcBuild;
 
 /// A sass/scss asset pipeline.
@@ -40,6 +40,17 @@ impl Sass {
         Ok(Self { id, cfg, progress, asset })
     }
 
+    /// The path of the source asset consumed by this pipeline.
+    pub fn input_path(&self) -> &Path {
+        &self.asset.path
+    }
+
+    /// The names of the files this pipeline intends to emit to the dist dir, with a `<hash>`
+    /// placeholder standing in for the content hash.
+    pub fn planned_outputs(&self) -> Vec<String> {
+        vec![format!("{}{}-<hash>.css", self.cfg.assets_prefix(), self.asset.file_stem.to_string_lossy())]
+    }
+
     /// Spawn the pipeline for this asset type.
     pub fn spawn(self) -> JoinHandle<Result<TrunkLinkPipelineOutput>> {
         spawn(async move {
@@ -55,10 +66,13 @@ impl Sass {
             })?;
 
             // Hash the contents to generate a file name, and then write the contents to the dist dir.
-            let hash = seahash::hash(css.as_bytes());
-            let file_name = format!("{}-{:x}.css", &self.asset.file_stem.to_string_lossy(), hash);
-            let file_path = self.cfg.dist.join(&file_name);
-            fs::write(&file_path, css).await.context("error writing SASS pipeline output")?;
+            let hash = self.cfg.hash_algo.hash(css.as_bytes());
+            let file_name = format!("{}-{}.css", &self.asset.file_stem.to_string_lossy(), hash);
+            let file_path = self.cfg.assets_out_dir().join(&file_name);
+            write_with_retry(&file_path, css.as_bytes(), self.cfg.fs_retries)
+                .await
+                .context("error writing SASS pipeline output")?;
+            write_unhashed_alias(&self.cfg, &file_path, &format!("{}.css", &self.asset.file_stem.to_string_lossy())).await?;
             Ok(TrunkLinkPipelineOutput::Sass(SassOutput {
                 cfg: self.cfg.clone(),
                 id: self.id,
@@ -81,9 +95,8 @@ pub struct SassOutput {
 impl SassOutput {
     pub async fn finalize(self, dom: &mut Document) -> Result<()> {
         dom.select(&super::trunk_id_selector(self.id)).replace_with_html(format!(
-            r#"<link rel="stylesheet" href="{base}{file}"/>"#,
-            base = &self.cfg.public_url,
-            file = self.file.file_name
+            r#"<link rel="stylesheet" href="{url}"/>"#,
+            url = self.cfg.asset_url(&self.file.file_name)
         ));
         Ok(())
     }
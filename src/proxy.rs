@@ -1,6 +1,14 @@
-use http_types::Url;
-use tide::{Request, Result};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
+use async_std::io::{BufReader, Read};
+use http_types::{Body, StatusCode, Url};
+use indicatif::ProgressBar;
+use regex::Regex;
+use tide::{Request, Response, Result};
+
+use crate::config::ConfigOptsProxy;
 use crate::serve::State;
 
 /// A handler used for proxying HTTP requests to a backend.
@@ -10,12 +18,33 @@ pub struct ProxyHandlerHttp {
     /// An optional rewrite path to be used as the listening URI prefix, but which will be
     /// stripped before being sent to the proxy backend.
     rewrite: Option<String>,
+    /// An optional maximum size in bytes of proxied request & response bodies.
+    max_body_bytes: Option<usize>,
+    /// An optional header name & value which requests must carry to be handled by this proxy.
+    match_header: Option<(String, String)>,
+    /// An optional rewrite rule for the backend path of proxied requests.
+    path_rewrite: Option<PathRewrite>,
+    /// An optional prefix stripped from the full request path, which then forms the backend path.
+    strip_prefix: Option<String>,
+    /// The progress bar used for reporting on proxied requests.
+    progress: ProgressBar,
 }
 
 impl ProxyHandlerHttp {
     /// Create a new instance.
-    pub fn new(backend: Url, rewrite: Option<String>) -> Self {
-        Self { backend, rewrite }
+    pub fn new(
+        backend: Url, rewrite: Option<String>, max_body_bytes: Option<usize>, match_header: Option<(String, String)>,
+        path_rewrite: Option<PathRewrite>, strip_prefix: Option<String>, progress: ProgressBar,
+    ) -> Self {
+        Self {
+            backend,
+            rewrite,
+            max_body_bytes,
+            match_header,
+            path_rewrite,
+            strip_prefix,
+            progress,
+        }
     }
 
     /// The path on which this proxy handler is to listen.
@@ -23,18 +52,36 @@ impl ProxyHandlerHttp {
         self.rewrite.as_ref().map(AsRef::as_ref).unwrap_or_else(|| self.backend.path())
     }
 
+    /// Whether this proxy handler only matches requests carrying a specific header value.
+    pub fn is_header_matched(&self) -> bool {
+        self.match_header.is_some()
+    }
+
+    /// Check whether the given request is to be handled by this proxy handler.
+    pub fn matches(&self, req: &Request<State>) -> bool {
+        match &self.match_header {
+            Some((name, val)) => req
+                .header(name.as_str())
+                .map(|vals| vals.iter().any(|hval| hval.as_str() == val))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
     /// Proxy the given request to the target backend.
     pub async fn proxy_request(&self, mut req: Request<State>) -> Result {
-        // Build a new request to be sent to the proxy backend.
-        let req_url = req.url();
-        let req_path = req_url.path();
-        let mut url = self.backend.clone();
-        if let Ok(mut segments) = url.path_segments_mut() {
-            segments.pop_if_empty().extend(req_path.trim_start_matches('/').split('/'));
+        // Reject request bodies which declare a size over the limit.
+        if self.exceeds_limit(req.len()) {
+            self.progress
+                .println(format!("proxied request to {} rejected: body exceeds the size limit", &self.backend));
+            return Ok(Response::new(StatusCode::PayloadTooLarge));
         }
-        url.set_query(req_url.query());
-        let mut request = surf::RequestBuilder::new(req.method(), url).body(req.take_body());
-        for (hname, hval) in req.iter() {
+
+        // Build a new request to be sent to the proxy backend.
+        let url = self.backend_url(req.url());
+        let req_body = self.limit_body(req.take_body());
+        let mut request = surf::RequestBuilder::new(req.method(), url).body(req_body);
+        for (hname, hval) in req.iter().filter(|(hname, _)| !is_hop_by_hop(hname.as_str())) {
             request = request.header(hname, hval);
         }
         // Ensure the host header is set to target the backend itself.
@@ -42,12 +89,301 @@ impl ProxyHandlerHttp {
             request = request.header("host", host);
         }
 
-        // Send the request & unpack the response.
+        // Send the request & unpack the response, rejecting response bodies which declare a size
+        // over the limit.
         let mut res = request.send().await?;
-        let mut response = tide::Response::builder(res.status()).body(res.take_body());
-        for (hname, hval) in res.iter() {
+        if self.exceeds_limit(res.len()) {
+            self.progress
+                .println(format!("proxied response from {} rejected: body exceeds the size limit", &self.backend));
+            return Ok(Response::new(StatusCode::BadGateway));
+        }
+        // The body is relayed as a stream as it arrives from the backend, and the framing of the
+        // response is left to the server: bodies of unknown length, such as `text/event-stream`
+        // responses, are sent chunked, with each chunk written as soon as it is received.
+        let res_body = self.limit_body(res.take_body());
+        let mut response = tide::Response::builder(res.status()).body(res_body);
+        for (hname, hval) in res.iter().filter(|(hname, _)| !is_hop_by_hop(hname.as_str())) {
             response = response.header(hname, hval);
         }
-        Ok(response.build())
+        let mut response = response.build();
+        response.insert_ext(ProxiedResponse);
+        Ok(response)
+    }
+
+    /// The backend URL to which the given request URL is proxied, whose path has already had the
+    /// listening prefix stripped by the router.
+    ///
+    /// Any strip prefix is removed from the full request path before any rewrite is applied, so
+    /// that a path template then renders the stripped path, and a regex is applied to it.
+    fn backend_url(&self, req_url: &Url) -> Url {
+        let req_path = req_url.path();
+        let mut url = self.backend.clone();
+        let full_path = format!("{}/{}", self.path().trim_end_matches('/'), req_path.trim_start_matches('/'));
+        let stripped_path = self.strip_prefix.as_ref().map(|prefix| {
+            let rest = full_path.strip_prefix(prefix.trim_end_matches('/')).unwrap_or(&full_path);
+            format!("/{}", rest.trim_start_matches('/'))
+        });
+        match (&self.path_rewrite, &stripped_path) {
+            (Some(PathRewrite::Template(template)), _) => {
+                let path = stripped_path.as_deref().unwrap_or(req_path);
+                url.set_path(&render_path_template(template, path));
+            }
+            (Some(PathRewrite::Regex(regex, replacement)), _) => {
+                let path = stripped_path.as_ref().unwrap_or(&full_path);
+                url.set_path(&regex.replace(path, replacement.as_str()));
+            }
+            (None, Some(stripped_path)) => url.set_path(stripped_path),
+            (None, None) => {
+                if let Ok(mut segments) = url.path_segments_mut() {
+                    segments.pop_if_empty().extend(req_path.trim_start_matches('/').split('/'));
+                }
+            }
+        }
+        url.set_query(req_url.query());
+        url
+    }
+
+    /// Check whether the given declared body size exceeds the configured limit, if any.
+    fn exceeds_limit(&self, len: Option<usize>) -> bool {
+        matches!((len, self.max_body_bytes), (Some(len), Some(max)) if len > max)
+    }
+
+    /// Wrap the given body such that it is aborted if it exceeds the configured limit while
+    /// streaming. Bodies are still streamed through unbuffered.
+    fn limit_body(&self, body: Body) -> Body {
+        let max = match self.max_body_bytes {
+            Some(max) => max,
+            None => return body,
+        };
+        let mime = body.mime().clone();
+        let len = body.len();
+        let reader = LimitedReader {
+            inner: body,
+            remaining: max,
+            max,
+            progress: self.progress.clone(),
+        };
+        let mut body = Body::from_reader(BufReader::new(reader), len);
+        body.set_mime(mime);
+        body
+    }
+}
+
+/// A marker extension on responses relayed from a proxy backend, which distinguishes them from
+/// responses for static files.
+pub struct ProxiedResponse;
+
+/// A rule for rewriting the backend path of proxied requests, used in place of appending the
+/// request path to the backend URL's path.
+pub enum PathRewrite {
+    /// A template rendered from the request path following the listening prefix, or from the
+    /// stripped request path when a strip prefix is configured.
+    Template(String),
+    /// A regex applied to the full request path, whose first match is replaced with the given
+    /// replacement, per the semantics of `Regex::replace`.
+    Regex(Regex, String),
+}
+
+impl PathRewrite {
+    /// Build the path rewrite rule declared by the given proxy config, if any.
+    pub fn from_opts(opts: &ConfigOptsProxy) -> std::result::Result<Option<Self>, regex::Error> {
+        Ok(match (&opts.path_template, &opts.rewrite_regex, &opts.rewrite_replacement) {
+            (Some(template), _, _) => Some(Self::Template(template.clone())),
+            (None, Some(regex), Some(replacement)) => Some(Self::Regex(Regex::new(regex)?, replacement.clone())),
+            _ => None,
+        })
+    }
+}
+
+/// Render the given backend path template for the given request path, which has already had the
+/// listening prefix or strip prefix stripped.
+///
+/// `{path}` is replaced with the entire request path, and `{1}`, `{2}` etc with its individual
+/// segments, where out of range segments render as empty. Any other `{...}` is left as is.
+fn render_path_template(template: &str, req_path: &str) -> String {
+    let path = req_path.trim_start_matches('/');
+    let segments: Vec<&str> = path.split('/').collect();
+    let mut out = String::with_capacity(template.len() + path.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        let token = &rest[1..end];
+        match token {
+            "path" => out.push_str(path),
+            _ => match token.parse::<usize>() {
+                Ok(idx) if idx > 0 => out.push_str(segments.get(idx - 1).copied().unwrap_or("")),
+                _ => out.push_str(&rest[..=end]),
+            },
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Check whether the given header is a hop-by-hop header, which applies only to a single
+/// connection & must therefore not be forwarded by proxies (RFC 7230, section 6.1).
+///
+/// Forwarding `transfer-encoding` in particular would double up the framing which the server
+/// applies to streamed bodies itself.
+fn is_hop_by_hop(name: &str) -> bool {
+    const HOP_BY_HOP: &[&str] = &[
+        "connection",
+        "keep-alive",
+        "proxy-authenticate",
+        "proxy-authorization",
+        "te",
+        "trailer",
+        "transfer-encoding",
+        "upgrade",
+    ];
+    HOP_BY_HOP.iter().any(|hop| name.eq_ignore_ascii_case(hop))
+}
+
+/// A reader which fails once more than a maximum number of bytes have been read through it.
+struct LimitedReader {
+    /// The body being read.
+    inner: Body,
+    /// The number of bytes which may still be read.
+    remaining: usize,
+    /// The maximum number of bytes which may be read in total.
+    max: usize,
+    /// The progress bar used for reporting an aborted transfer.
+    progress: ProgressBar,
+}
+
+impl Read for LimitedReader {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let read = match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(read)) => read,
+            other => return other,
+        };
+        if read > self.remaining {
+            let msg = format!("proxied body exceeded the size limit of {} bytes; transfer aborted", self.max);
+            self.progress.println(&msg);
+            return Poll::Ready(Err(io::Error::other(msg)));
+        }
+        self.remaining -= read;
+        Poll::Ready(Ok(read))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_handler(backend: &str, rewrite: Option<&str>, path_rewrite: Option<PathRewrite>, strip_prefix: Option<&str>) -> ProxyHandlerHttp {
+        ProxyHandlerHttp::new(
+            backend.parse().unwrap(),
+            rewrite.map(String::from),
+            None,
+            None,
+            path_rewrite,
+            strip_prefix.map(String::from),
+            ProgressBar::hidden(),
+        )
+    }
+
+    fn backend_url(handler: &ProxyHandlerHttp, req_path: &str) -> String {
+        let req_url: Url = format!("http://localhost{}", req_path).parse().unwrap();
+        handler.backend_url(&req_url).to_string()
+    }
+
+    /// Read the given body through a `LimitedReader` with the given limit.
+    async fn read_limited(body: &[u8], max: usize) -> io::Result<Vec<u8>> {
+        let mut reader = LimitedReader {
+            inner: Body::from_reader(BufReader::new(async_std::io::Cursor::new(body.to_vec())), None),
+            remaining: max,
+            max,
+            progress: ProgressBar::hidden(),
+        };
+        let mut out = vec![];
+        async_std::io::ReadExt::read_to_end(&mut reader, &mut out).await?;
+        Ok(out)
+    }
+
+    #[async_std::test]
+    async fn limited_reader_fails_once_over_the_limit() {
+        assert_eq!(read_limited(b"0123456789", 10).await.unwrap(), b"0123456789");
+        let err = read_limited(b"0123456789a", 10).await.unwrap_err();
+        assert!(err.to_string().contains("size limit of 10 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn hop_by_hop_headers_are_recognized() {
+        for name in &["Connection", "keep-alive", "TE", "Transfer-Encoding", "upgrade", "Proxy-Authorization"] {
+            assert!(is_hop_by_hop(name), "{}", name);
+        }
+        for name in &["content-type", "content-length", "authorization", "x-forwarded-for", "tea"] {
+            assert!(!is_hop_by_hop(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn render_path_template_substitutes_path_and_segments() {
+        assert_eq!(render_path_template("/v2/{path}", "/users/1"), "/v2/users/1");
+        assert_eq!(render_path_template("/{2}/by-id/{1}", "/users/1"), "/1/by-id/users");
+        assert_eq!(render_path_template("/{3}/{0}/{x}", "/users/1"), "//{0}/{x}");
+        assert_eq!(render_path_template("/unclosed/{path", "/users"), "/unclosed/{path");
+    }
+
+    #[test]
+    fn request_path_is_appended_to_the_backend_path() {
+        let handler = test_handler("http://localhost:9000/api/", None, None, None);
+        assert_eq!(backend_url(&handler, "/users/1?page=2"), "http://localhost:9000/api/users/1?page=2");
+    }
+
+    #[test]
+    fn strip_prefix_is_removed_from_the_full_path() {
+        let handler = test_handler("http://localhost:9000/api/", None, None, Some("/api"));
+        assert_eq!(backend_url(&handler, "/users/1"), "http://localhost:9000/users/1");
+        let handler = test_handler("http://localhost:9000/", Some("/auth/"), None, Some("/auth/"));
+        assert_eq!(backend_url(&handler, "/login"), "http://localhost:9000/login");
+    }
+
+    #[test]
+    fn strip_prefix_is_applied_before_a_path_template() {
+        let template = Some(PathRewrite::Template("/v2/{path}".into()));
+        let handler = test_handler("http://localhost:9000/", Some("/api/v1/"), template, Some("/api"));
+        assert_eq!(backend_url(&handler, "/users/1"), "http://localhost:9000/v2/v1/users/1");
+
+        let template = Some(PathRewrite::Template("/v2/{path}".into()));
+        let handler = test_handler("http://localhost:9000/", Some("/api/v1/"), template, None);
+        assert_eq!(backend_url(&handler, "/users/1"), "http://localhost:9000/v2/users/1");
+    }
+
+    /// The path rewrite rule of a proxy to a local backend declared with the given extra config.
+    fn path_rewrite(config: &str) -> std::result::Result<Option<PathRewrite>, regex::Error> {
+        let opts: ConfigOptsProxy = toml::from_str(&format!("backend = \"http://localhost:9000/\"\n{}", config)).unwrap();
+        PathRewrite::from_opts(&opts)
+    }
+
+    #[test]
+    fn path_rewrite_is_built_from_the_proxy_config() {
+        assert!(path_rewrite("").unwrap().is_none());
+        // A regex without a replacement is not a rewrite rule.
+        assert!(path_rewrite("rewrite_regex = \"^/v1\"").unwrap().is_none());
+        assert!(matches!(
+            path_rewrite("rewrite_regex = \"^/v1\"\nrewrite_replacement = \"/v2\"").unwrap(),
+            Some(PathRewrite::Regex(regex, replacement)) if regex.as_str() == "^/v1" && replacement == "/v2"
+        ));
+        // A template takes precedence over a regex.
+        assert!(matches!(
+            path_rewrite("path_template = \"/v2/{path}\"\nrewrite_regex = \"^/v1\"\nrewrite_replacement = \"/v2\"").unwrap(),
+            Some(PathRewrite::Template(template)) if template == "/v2/{path}"
+        ));
+        assert!(path_rewrite("rewrite_regex = \"(\"\nrewrite_replacement = \"\"").is_err());
+    }
+
+    #[test]
+    fn strip_prefix_is_applied_before_a_rewrite_regex() {
+        let regex = Some(PathRewrite::Regex(Regex::new("^/v1/(.*)").unwrap(), "/api/$1".into()));
+        let handler = test_handler("http://localhost:9000/", Some("/proxy/v1/"), regex, Some("/proxy"));
+        assert_eq!(backend_url(&handler, "/users"), "http://localhost:9000/api/users");
     }
 }
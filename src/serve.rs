@@ -1,97 +1,385 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use async_std::fs;
+use async_std::path::PathBuf as AsyncPathBuf;
 use async_std::task::{spawn, spawn_local, JoinHandle};
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt};
+use futures::stream::StreamExt;
 use indicatif::ProgressBar;
-use tide::http::mime;
-use tide::{Middleware, Next, Request, Response, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use sha2::{Digest, Sha256};
+use socket2::{Domain, Protocol, Socket, Type};
+use tide::http::conditional::{ETag, IfModifiedSince, IfNoneMatch, LastModified};
+use tide::http::{headers, mime, Method};
+use tide::{Body, Endpoint, Middleware, Next, Request, Response, StatusCode};
 
-use crate::common::SERVER;
-use crate::config::RtcServe;
-use crate::proxy::ProxyHandlerHttp;
-use crate::watch::WatchSystem;
+use crate::backend::BackendProcess;
+use crate::build::BuildSystem;
+use crate::common::{HashAlgo, SERVER};
+use crate::config::{DownloadRule, RtcServe};
+use crate::pipelines::HashCache;
+use crate::proxy::{PathRewrite, ProxiedResponse, ProxyHandlerHttp};
+use crate::watch::{LastGoodDist, WatchSystem};
 
 /// A system encapsulating a build & watch system, responsible for serving generated content.
 pub struct ServeSystem {
     cfg: Arc<RtcServe>,
-    watch: WatchSystem,
+    /// The watch system, which is absent when serving a prebuilt serve dir or when not watching.
+    watch: Option<WatchSystem>,
+    /// The build system used for the one initial build when not watching.
+    build: Option<BuildSystem>,
     http_addr: String,
+    /// The TCP listener bound for the server, which is absent when serving on a Unix socket.
+    listener: Option<TcpListener>,
     progress: ProgressBar,
+    /// A snapshot of the last successful build's dist dir, served while rebuilds are failing.
+    last_good: Option<Arc<LastGoodDist>>,
 }
 
 impl ServeSystem {
     /// Construct a new instance.
     pub async fn new(cfg: Arc<RtcServe>, progress: ProgressBar) -> Result<Self> {
-        let watch = WatchSystem::new(cfg.watch.clone(), progress.clone()).await?;
-        let http_addr = format!("http://127.0.0.1:{}{}", cfg.port, &cfg.watch.build.public_url);
+        for warning in cfg.warnings.iter() {
+            progress.println(format!("warning: {}", warning));
+        }
+        let (mut watch, build) = match (&cfg.watch, cfg.no_watch) {
+            (None, _) => (None, None),
+            (Some(watch), true) => (None, Some(BuildSystem::new(watch.build.clone(), progress.clone(), None).await?)),
+            (Some(watch), false) => (Some(WatchSystem::new(watch.clone(), progress.clone()).await?), None),
+        };
+        let last_good = match &mut watch {
+            Some(watch) if cfg.serve_last_good => Some(watch.enable_last_good().await?),
+            _ => None,
+        };
+        // Bind the listener up front, so that the actual port is known when configured with port 0.
+        let (http_addr, listener) = match &cfg.unix_socket {
+            Some(unix_socket) => (format!("http+unix://{}", unix_socket.display()), None),
+            None => {
+                let addr = SocketAddr::new(cfg.address, cfg.port);
+                let listener = bind_tcp(addr, cfg.backlog).with_context(|| format!("error binding to {}", addr))?;
+                let port = listener.local_addr().context("error getting bound address of server")?.port();
+                (
+                    format!("http://{}{}", SocketAddr::new(url_host(cfg.address), port), &cfg.public_url),
+                    Some(listener),
+                )
+            }
+        };
         Ok(Self {
             cfg,
             watch,
+            build,
             http_addr,
+            listener,
             progress,
+            last_good,
         })
     }
 
+    /// The address to which the server is bound, which carries the actual port when configured
+    /// with port 0. This is `None` when serving on a Unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref().and_then(|listener| listener.local_addr().ok())
+    }
+
     /// Run the serve system.
     pub async fn run(mut self) -> Result<()> {
-        // Spawn the watcher & the server.
-        self.watch.build().await;
-        let watch_handle = spawn_local(self.watch.run());
-        let server_handle = Self::spawn_server(self.cfg.clone(), self.http_addr.clone(), self.progress.clone())?;
-
-        // Open the browser.
-        if self.cfg.open {
-            if let Err(err) = open::that(self.http_addr) {
+        // Run the initial build, bailing if it fails unless configured to serve regardless.
+        let build_res = match (&mut self.watch, &mut self.build) {
+            (Some(watch), _) => Some(watch.build().await),
+            (None, Some(build)) => {
+                let res = build.build().await;
+                if let Err(err) = &res {
+                    // NOTE WELL: we use debug formatting here to ensure the error chain is displayed.
+                    self.progress.println(format!("{:?}", err));
+                }
+                Some(res)
+            }
+            (None, None) => {
+                self.progress
+                    .println(format!("{} serving {:?} without building\n", SERVER, self.cfg.serve_root()));
+                None
+            }
+        };
+        if matches!(build_res, Some(Err(_))) && !self.cfg.serve_on_build_error {
+            bail!("initial build failed; set `serve.serve_on_build_error` to keep serving on a failed initial build");
+        }
+
+        // Spawn the backend process, the watcher & the server.
+        let backend_handle = self
+            .cfg
+            .backend_command
+            .clone()
+            .map(|backend| BackendProcess::new(backend, self.progress.clone()).spawn());
+        let watch_handle = self.watch.take().map(|watch| spawn_local(watch.run()));
+        // The socket is removed once the guard is dropped, as this returns.
+        let _unix_socket_guard = self.cfg.unix_socket.as_deref().map(prepare_unix_socket).transpose()?;
+        let server_handle = Self::spawn_server(
+            self.cfg.clone(),
+            self.http_addr.clone(),
+            self.listener.take(),
+            self.progress.clone(),
+            self.last_good.clone(),
+        )?;
+
+        // Open the browser, which is only possible when serving over TCP.
+        if self.cfg.open && self.cfg.unix_socket.is_none() {
+            if let Err(err) = open_browser(&self.open_url(), self.cfg.browser.as_deref()) {
                 self.progress.println(format!("error opening browser: {}", err));
             }
         }
 
-        server_handle.await;
-        watch_handle.await;
+        // Serve until the server stops or Trunk is interrupted, then shut down the backend process.
+        let shutdown = shutdown_signal()?;
+        futures::select! {
+            _ = server_handle.fuse() => (),
+            _ = shutdown.fuse() => self.progress.println(format!("{} shutting down", SERVER)),
+        }
+        drop(watch_handle);
+        if let Some(backend_handle) = backend_handle {
+            backend_handle.shutdown().await;
+        }
+        if let Some(last_good) = &self.last_good {
+            last_good.remove().await?;
+        }
         Ok(())
     }
 
-    fn spawn_server(cfg: Arc<RtcServe>, http_addr: String, progress: ProgressBar) -> Result<JoinHandle<()>> {
+    /// The URL to open in the browser, which is the configured `open_path` relative to the served
+    /// public URL, if any.
+    fn open_url(&self) -> String {
+        match &self.cfg.open_path {
+            Some(path) => format!("{}/{}", self.http_addr.trim_end_matches('/'), path.trim_start_matches('/')),
+            None => self.http_addr.clone(),
+        }
+    }
+
+    fn spawn_server(
+        cfg: Arc<RtcServe>, http_addr: String, listener: Option<TcpListener>, progress: ProgressBar,
+        last_good: Option<Arc<LastGoodDist>>,
+    ) -> Result<JoinHandle<()>> {
+        tide::log::with_level(tide::log::LevelFilter::Error);
+        let app = Self::build_app(&cfg, &progress, last_good)?;
+
+        // Listen and serve.
+        progress.println(format!("{} server running at {}\n", SERVER, &http_addr));
+        Ok(spawn(async move {
+            let res = match listener {
+                Some(listener) => app.listen(listener).await,
+                None => app.listen(http_addr).await,
+            };
+            if let Err(err) = res {
+                progress.println(err.to_string());
+            }
+        }))
+    }
+
+    /// Build the server app, with its middleware, static files & proxies, per the given config.
+    fn build_app(cfg: &RtcServe, progress: &ProgressBar, last_good: Option<Arc<LastGoodDist>>) -> Result<tide::Server<State>> {
         // Prep state.
-        let listen_addr = format!("0.0.0.0:{}", cfg.port);
-        let index = Arc::new(cfg.watch.build.dist.join("index.html"));
+        let index = Arc::new(cfg.serve_root().join("index.html"));
+        let dist = Arc::new(cfg.serve_root().to_path_buf());
+        let public_url = Arc::new(cfg.public_url.clone());
 
         // Build app.
-        tide::log::with_level(tide::log::LevelFilter::Error);
-        let mut app = tide::with_state(State { index });
-        app.with(IndexHtmlMiddleware)
-            .at(&cfg.watch.build.public_url)
-            .serve_dir(cfg.watch.build.dist.to_string_lossy().as_ref())?;
+        let mut app = tide::with_state(State { index, dist, public_url });
+        if let Some(allowed_hosts) = &cfg.allowed_hosts {
+            app.with(AllowedHostsMiddleware(allowed_hosts.clone()));
+        }
+        if let Some(credentials) = &cfg.basic_auth {
+            app.with(BasicAuthMiddleware::new(credentials));
+        }
+        if let Some(last_good) = last_good {
+            app.with(LastGoodDistMiddleware(last_good));
+        }
+        app.with(WellKnownMiddleware)
+            .with(IndexHtmlMiddleware {
+                proxy_passthrough_404: cfg.proxy_passthrough_404,
+            })
+            .with(ConditionalRequestMiddleware::default());
+        if !cfg.downloads.is_empty() {
+            app.with(DownloadMiddleware(cfg.downloads.clone()));
+        }
+        if cfg.directory_listing {
+            app.with(DirectoryListingMiddleware);
+        }
+        app.at(&cfg.public_url)
+            .serve_dir(cfg.serve_root().to_string_lossy().as_ref())?;
 
         // Build proxies.
         if let Some(backend) = &cfg.proxy_backend {
-            let handler = Arc::new(ProxyHandlerHttp::new(backend.clone(), cfg.proxy_rewrite.clone()));
+            let handler = Arc::new(ProxyHandlerHttp::new(
+                backend.clone(),
+                cfg.proxy_rewrite.clone(),
+                cfg.proxy_max_body_bytes,
+                None,
+                None,
+                None,
+                progress.clone(),
+            ));
             progress.println(format!("{} proxying {} -> {}\n", SERVER, handler.path(), &backend));
-            app.at(handler.path()).strip_prefix().all(move |req| {
+            let path = handler.path().to_string();
+            route_all_methods(&mut app, &path, move |req| {
                 let handler = handler.clone();
                 async move { handler.proxy_request(req).await }
             });
         } else if let Some(proxies) = &cfg.proxies {
+            // Group proxies by path, as header-matched proxies may share a path.
+            let mut routes: Vec<(String, Vec<Arc<ProxyHandlerHttp>>)> = vec![];
             for proxy in proxies.iter() {
-                let handler = Arc::new(ProxyHandlerHttp::new(proxy.backend.clone(), proxy.rewrite.clone()));
-                progress.println(format!("{} proxying {} -> {}\n", SERVER, handler.path(), &proxy.backend));
-                app.at(handler.path()).strip_prefix().all(move |req| {
-                    let handler = handler.clone();
-                    async move { handler.proxy_request(req).await }
+                let handler = Arc::new(ProxyHandlerHttp::new(
+                    proxy.backend.clone(),
+                    proxy.rewrite.clone(),
+                    proxy.max_body_bytes,
+                    proxy.match_header.clone(),
+                    PathRewrite::from_opts(proxy)?,
+                    proxy.strip_prefix.clone(),
+                    progress.clone(),
+                ));
+                match &proxy.match_header {
+                    Some((name, val)) => progress.println(format!(
+                        "{} proxying {} -> {} (when `{}: {}`)\n",
+                        SERVER,
+                        handler.path(),
+                        &proxy.backend,
+                        name,
+                        val
+                    )),
+                    None => progress.println(format!("{} proxying {} -> {}\n", SERVER, handler.path(), &proxy.backend)),
+                }
+                match routes.iter_mut().find(|(path, _)| path == handler.path()) {
+                    Some((_, handlers)) => handlers.push(handler),
+                    None => routes.push((handler.path().to_string(), vec![handler])),
+                }
+            }
+            for (path, mut handlers) in routes {
+                // Header-matched proxies are evaluated before those matching on path alone.
+                handlers.sort_by_key(|handler| !handler.is_header_matched());
+                let handlers = Arc::new(handlers);
+                route_all_methods(&mut app, &path, move |req: Request<State>| {
+                    let handlers = handlers.clone();
+                    async move {
+                        match handlers.iter().find(|handler| handler.matches(&req)) {
+                            Some(handler) => handler.proxy_request(req).await,
+                            None => Ok(Response::new(StatusCode::NotFound)),
+                        }
+                    }
                 });
             }
         }
+        Ok(app)
+    }
+}
 
-        // Listen and serve.
-        progress.println(format!("{} server running at {}\n", SERVER, &http_addr));
-        Ok(spawn(async move {
-            if let Err(err) = app.listen(listen_addr).await {
-                progress.println(err.to_string());
-            }
-        }))
+/// Route requests of any method under the given path prefix to the given endpoint.
+///
+/// The endpoint is registered for each method individually, rather than via `Route::all`, as tide
+/// only falls back to the routes of all methods when no route of the request's method matches, so
+/// the `GET` route of the static files would otherwise shadow proxies under the public URL.
+fn route_all_methods(app: &mut tide::Server<State>, path: &str, endpoint: impl Endpoint<State> + Clone) {
+    const METHODS: &[Method] = &[
+        Method::Get,
+        Method::Head,
+        Method::Post,
+        Method::Put,
+        Method::Delete,
+        Method::Connect,
+        Method::Options,
+        Method::Trace,
+        Method::Patch,
+    ];
+    let mut route = app.at(path);
+    route.strip_prefix();
+    for method in METHODS {
+        route.method(*method, endpoint.clone());
+    }
+}
+
+/// A future which resolves once Trunk is interrupted or terminated, so that the serve system may
+/// shut down gracefully.
+#[cfg(unix)]
+fn shutdown_signal() -> Result<BoxFuture<'static, ()>> {
+    use signal_hook::iterator::Signals;
+
+    let signals = Signals::new([signal_hook::SIGINT, signal_hook::SIGTERM]).context("error registering signal handlers")?;
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            let _ = tx.send(());
+        }
+    });
+    Ok(rx.map(|_| ()).boxed())
+}
+
+/// Signals are only handled on unix platforms, elsewhere Trunk is simply terminated.
+#[cfg(not(unix))]
+fn shutdown_signal() -> Result<BoxFuture<'static, ()>> {
+    Ok(futures::future::pending().boxed())
+}
+
+/// Bind a TCP listener to the given address, with the given listen backlog.
+///
+/// This mirrors `std::net::TcpListener::bind`, which always uses a backlog of 128. Listeners
+/// bound to the unspecified IPv6 address `::` are dual-stack, accepting IPv4 connections as well,
+/// regardless of the platform's default.
+fn bind_tcp(addr: SocketAddr, backlog: u32) -> std::io::Result<TcpListener> {
+    let domain = if addr.is_ipv6() { Domain::ipv6() } else { Domain::ipv4() };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    socket.bind(&addr.into())?;
+    // NOTE: the backlog is validated to fit into an `i32` by the runtime config.
+    socket.listen(backlog as i32)?;
+    Ok(socket.into_tcp_listener())
+}
+
+/// The host by which to reach a server bound to the given address, which is the loopback address
+/// of the same IP version when bound to all interfaces.
+fn url_host(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        address => address,
+    }
+}
+
+/// A guard which removes the Unix domain socket at the given path when dropped.
+struct UnixSocketGuard(PathBuf);
+
+impl Drop for UnixSocketGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Prepare the given path to be bound as a Unix domain socket, returning a guard which removes
+/// the socket once the server shuts down.
+///
+/// Any socket left behind by a previous run, such as one which was killed, is removed first.
+#[cfg(unix)]
+fn prepare_unix_socket(unix_socket: &Path) -> Result<UnixSocketGuard> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(meta) = std::fs::symlink_metadata(unix_socket) {
+        if !meta.file_type().is_socket() {
+            bail!("refusing to replace {:?} with a unix socket, as it is not a socket", unix_socket);
+        }
+        std::fs::remove_file(unix_socket).context("error removing stale unix socket")?;
     }
+    Ok(UnixSocketGuard(unix_socket.to_path_buf()))
+}
+
+/// Unix domain sockets are only supported on unix platforms, which is enforced by the runtime config.
+#[cfg(not(unix))]
+fn prepare_unix_socket(unix_socket: &Path) -> Result<UnixSocketGuard> {
+    Ok(UnixSocketGuard(unix_socket.to_path_buf()))
 }
 
 /// Server state.
@@ -99,6 +387,39 @@ impl ServeSystem {
 pub struct State {
     /// The path to the index.html file.
     pub index: Arc<PathBuf>,
+    /// The dist dir from which all static content is served.
+    pub dist: Arc<PathBuf>,
+    /// The public URL on which the dist dir is served.
+    pub public_url: Arc<String>,
+}
+
+impl State {
+    /// Resolve the canonical path within the dist dir targeted by the given percent-encoded
+    /// request path.
+    ///
+    /// Returns `None` if the request path is not rooted at the public URL, if it holds `.` or `..`
+    /// segments or encoded separators, if the target does not exist, or if the target would
+    /// resolve to a location outside of the dist dir, such as via a symlink.
+    pub async fn resolve_dist_path(&self, req_path: &str) -> Option<AsyncPathBuf> {
+        let rel_path = req_path.strip_prefix(self.public_url.as_str())?;
+        let mut segments = vec![];
+        for segment in rel_path.split('/').filter(|segment| !segment.is_empty()) {
+            // Segments are decoded one by one, so that encoded separators can not introduce new segments.
+            let segment = percent_decode_str(segment).decode_utf8().ok()?;
+            if segment == "." || segment == ".." || segment.contains(&['/', '\\'][..]) {
+                return None;
+            }
+            segments.push(segment.into_owned());
+        }
+        let dist = fs::canonicalize(self.dist.as_path()).await.ok()?;
+        let mut path = dist.clone();
+        path.extend(segments);
+        let path = fs::canonicalize(&path).await.ok()?;
+        if !path.starts_with(&dist) {
+            return None;
+        }
+        Some(path)
+    }
 }
 
 async fn load_index_html(index: &Path) -> tide::Result<Vec<u8>> {
@@ -106,19 +427,838 @@ async fn load_index_html(index: &Path) -> tide::Result<Vec<u8>> {
 }
 
 /// Middleware for accessing the index.html from any request which needs it.
-struct IndexHtmlMiddleware;
+struct IndexHtmlMiddleware {
+    /// Relay `404` responses from proxy backends verbatim, rather than falling back to the index.html.
+    proxy_passthrough_404: bool,
+}
 
 #[tide::utils::async_trait]
 impl Middleware<State> for IndexHtmlMiddleware {
     async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
         let index = req.state().index.clone();
         let res = next.run(req).await;
+        if self.proxy_passthrough_404 && res.ext::<ProxiedResponse>().is_some() {
+            return Ok(res);
+        }
         Ok(match res.status() {
-            StatusCode::NotFound => Response::builder(StatusCode::Ok)
-                .content_type(mime::HTML)
-                .body(load_index_html(&index).await?)
-                .build(),
+            StatusCode::NotFound => {
+                let mut res = Response::builder(StatusCode::Ok)
+                    .content_type(mime::HTML)
+                    .body(load_index_html(&index).await?)
+                    .build();
+                res.insert_ext(IndexFallback);
+                res
+            }
             _ => res,
         })
     }
 }
+
+/// A marker extension on responses serving the index.html in place of a `404`.
+struct IndexFallback;
+
+/// Middleware requiring HTTP basic auth credentials on all requests, including proxied requests.
+struct BasicAuthMiddleware {
+    /// The SHA-256 digest of the expected value of the `Authorization` header.
+    expected: Vec<u8>,
+}
+
+impl BasicAuthMiddleware {
+    /// Create a new instance requiring the given `user:pass` credentials.
+    fn new(credentials: &str) -> Self {
+        Self {
+            expected: Sha256::digest(format!("Basic {}", base64::encode(credentials)).as_bytes()).to_vec(),
+        }
+    }
+
+    /// Check whether the given `Authorization` header value carries the expected credentials.
+    ///
+    /// The digests of both values are compared in constant time, so that the time taken reveals
+    /// nothing about how much of the credentials is correct, nor about their length.
+    fn is_authorized(&self, value: &str) -> bool {
+        let digest = Sha256::digest(value.as_bytes());
+        digest.iter().zip(self.expected.iter()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for BasicAuthMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let authorized = req
+            .header(headers::AUTHORIZATION)
+            .map(|vals| vals.iter().any(|val| self.is_authorized(val.as_str())))
+            .unwrap_or(false);
+        if authorized {
+            return Ok(next.run(req).await);
+        }
+        Ok(Response::builder(StatusCode::Unauthorized)
+            .header(headers::WWW_AUTHENTICATE, r#"Basic realm="trunk", charset="UTF-8""#)
+            .build())
+    }
+}
+
+/// Middleware rejecting requests whose `Host` header does not name an allowed host, which guards
+/// against DNS rebinding attacks when serving on all interfaces.
+struct AllowedHostsMiddleware(Vec<String>);
+
+impl AllowedHostsMiddleware {
+    /// Check whether the given `Host` header value, which may carry a port, names an allowed host.
+    fn is_allowed(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        // Strip the port, taking care not to split IPv6 literals such as `[::1]:8080`.
+        let host = match host.rfind(':') {
+            Some(idx) if !host[idx..].contains(']') => &host[..idx],
+            _ => host.as_str(),
+        };
+        if matches!(host, "localhost" | "127.0.0.1" | "[::1]") {
+            return true;
+        }
+        self.0.iter().any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host.len() > domain.len() && host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'),
+            None => allowed == host,
+        })
+    }
+}
+
+#[tide::utils::async_trait]
+impl Middleware<State> for AllowedHostsMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let allowed = req.header(headers::HOST).map(|vals| self.is_allowed(vals.last().as_str())).unwrap_or(false);
+        if allowed {
+            return Ok(next.run(req).await);
+        }
+        Ok(Response::builder(StatusCode::Forbidden).body("host not allowed").build())
+    }
+}
+
+/// Open the given URL in the given browser executable, else in the system default browser.
+fn open_browser(url: &str, browser: Option<&str>) -> std::io::Result<()> {
+    match browser {
+        Some(browser) => browser_command(url, browser).spawn().map(|_| ()),
+        None => open::that(url).map(|_| ()),
+    }
+}
+
+/// The command opening the given URL in the given browser executable, to which the URL is passed
+/// as the only argument.
+fn browser_command(url: &str, browser: &str) -> std::process::Command {
+    let mut cmd = std::process::Command::new(browser);
+    cmd.arg(url);
+    cmd
+}
+
+/// Middleware serving the snapshot of the last successful build while rebuilds are failing.
+///
+/// Static files, including the index.html of dirs, are served from the snapshot. All other
+/// requests are handled as usual, so `404`s & proxied responses are unaffected, except that the
+/// index.html served in place of a `404` is also taken from the snapshot.
+struct LastGoodDistMiddleware(Arc<LastGoodDist>);
+
+#[tide::utils::async_trait]
+impl Middleware<State> for LastGoodDistMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !self.0.is_failing() {
+            return Ok(next.run(req).await);
+        }
+        let rel_path = match req.url().path().strip_prefix(req.state().public_url.as_str()) {
+            Some(rel_path) if !rel_path.split('/').any(|segment| segment == "..") => rel_path.to_string(),
+            _ => return Ok(next.run(req).await),
+        };
+        let mut path = self.0.dir().to_path_buf();
+        path.extend(rel_path.split('/').filter(|segment| !segment.is_empty()));
+        if path.is_dir() {
+            path.push("index.html");
+        }
+        if path.is_file() {
+            return Ok(Response::builder(StatusCode::Ok).body(Body::from_file(&path).await?).build());
+        }
+        let res = next.run(req).await;
+        if res.ext::<IndexFallback>().is_none() {
+            return Ok(res);
+        }
+        Ok(Response::builder(StatusCode::Ok)
+            .content_type(mime::HTML)
+            .body(Body::from_file(self.0.dir().join("index.html")).await?)
+            .build())
+    }
+}
+
+/// Middleware for serving `.well-known` files verbatim.
+///
+/// Requests under the `.well-known` dir never fall back to the index.html, and files without an
+/// extension (such as `apple-app-site-association`) are served as JSON, as is expected of them.
+struct WellKnownMiddleware;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for WellKnownMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let is_well_known = req
+            .url()
+            .path()
+            .strip_prefix(req.state().public_url.as_str())
+            .map(|rel_path| rel_path.starts_with(".well-known/"))
+            .unwrap_or(false);
+        if !is_well_known {
+            return Ok(next.run(req).await);
+        }
+        let file: PathBuf = match req.state().resolve_dist_path(req.url().path()).await {
+            Some(file) if file.is_file().await => file.into(),
+            _ => return Ok(Response::new(StatusCode::NotFound)),
+        };
+        let mut res = next.run(req).await;
+        if res.status() == StatusCode::Ok && file.extension().is_none() {
+            res.set_content_type(mime::JSON);
+        }
+        Ok(res)
+    }
+}
+
+/// Middleware for rendering an HTML listing of dist directories which have no index.html.
+struct DirectoryListingMiddleware;
+
+#[tide::utils::async_trait]
+impl Middleware<State> for DirectoryListingMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let req_path = req.url().path().to_string();
+        // Only directories which do not have an index.html file of their own are eligible.
+        let dir: PathBuf = match req.state().resolve_dist_path(&req_path).await {
+            Some(dir) if dir.is_dir().await && !dir.join("index.html").is_file().await => dir.into(),
+            _ => return Ok(next.run(req).await),
+        };
+        Ok(Response::builder(StatusCode::Ok)
+            .content_type(mime::HTML)
+            .body(render_directory_listing(&dir, &req_path).await?)
+            .build())
+    }
+}
+
+/// Middleware adding a `Content-Disposition: attachment` header to successful responses for paths
+/// matching a download rule, so that browsers download the files instead of displaying them.
+struct DownloadMiddleware(Vec<DownloadRule>);
+
+#[tide::utils::async_trait]
+impl Middleware<State> for DownloadMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let rule = req
+            .url()
+            .path()
+            .strip_prefix(req.state().public_url.as_str())
+            .map(|rel_path| rel_path.trim_start_matches('/'))
+            .and_then(|rel_path| self.0.iter().find(|rule| rule.pattern.is_match(rel_path)))
+            .cloned();
+        let mut res = next.run(req).await;
+        let rule = match rule {
+            Some(rule) if res.status().is_success() && res.ext::<ProxiedResponse>().is_none() => rule,
+            _ => return Ok(res),
+        };
+        let disposition = match &rule.filename {
+            Some(filename) => format!(r#"attachment; filename="{}""#, filename.replace('\\', "\\\\").replace('"', "\\\"")),
+            None => "attachment".to_string(),
+        };
+        res.insert_header("Content-Disposition", disposition);
+        Ok(res)
+    }
+}
+
+/// Middleware adding `ETag` & `Last-Modified` headers to static responses, and answering
+/// matching conditional requests with `304 Not Modified`.
+///
+/// The entity tag is a hash of the file's content, so that it is unchanged when a rebuild rewrites
+/// a file with identical content. Hashes are cached until the file's metadata changes, so each
+/// version of a file is only read once for hashing.
+#[derive(Default)]
+struct ConditionalRequestMiddleware(HashCache);
+
+#[tide::utils::async_trait]
+impl Middleware<State> for ConditionalRequestMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        // Only static files served from the dist dir are eligible.
+        let file = match req.state().resolve_dist_path(req.url().path()).await {
+            Some(file) if file.is_file().await => file,
+            _ => return Ok(next.run(req).await),
+        };
+        let if_none_match = IfNoneMatch::from_headers(&req).ok().flatten();
+        let if_modified_since = IfModifiedSince::from_headers(&req).ok().flatten();
+        let mut res = next.run(req).await;
+        if res.status() != StatusCode::Ok {
+            return Ok(res);
+        }
+
+        // Build validators separately from the response, so that the body is streamed through as is.
+        let file: PathBuf = file.into();
+        let (hash, meta) = match (self.0.hash_file(&file, HashAlgo::default()).await, fs::metadata(&file).await) {
+            (Ok(hash), Ok(meta)) => (hash, meta),
+            _ => return Ok(res),
+        };
+        let etag = ETag::new(hash);
+        let last_modified = meta.modified().ok().map(LastModified::new);
+
+        // Per RFC 7232, `If-None-Match` takes precedence over `If-Modified-Since` when present.
+        let not_modified = match (&if_none_match, &if_modified_since, &last_modified) {
+            (Some(tags), _, _) => tags.wildcard() || tags.iter().any(|tag| etag_matches(tag, &etag)),
+            (None, Some(since), Some(modified)) => unix_secs(modified.modified()) <= unix_secs(since.modified()),
+            _ => false,
+        };
+        if not_modified {
+            res.set_status(StatusCode::NotModified);
+            res.set_body(Body::empty());
+        }
+        etag.apply(&mut res);
+        if let Some(last_modified) = last_modified {
+            last_modified.apply(&mut res);
+        }
+        Ok(res)
+    }
+}
+
+/// Compare the given entity tags using the weak comparison function, as required for `If-None-Match`.
+fn etag_matches(a: &ETag, b: &ETag) -> bool {
+    let opaque_tag = |tag: &ETag| match tag {
+        ETag::Strong(val) | ETag::Weak(val) => val.clone(),
+    };
+    opaque_tag(a) == opaque_tag(b)
+}
+
+/// The number of whole seconds since the Unix epoch, which is the precision of HTTP dates.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|dur| dur.as_secs()).unwrap_or_default()
+}
+
+/// The characters which are percent-encoded within a path segment of a link.
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Render an HTML listing of the contents of the given directory.
+async fn render_directory_listing(dir: &Path, req_path: &str) -> tide::Result<String> {
+    let mut rows = vec![];
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let meta = entry.metadata().await?;
+        let mut name = entry.file_name().to_string_lossy().to_string();
+        let size = if meta.is_dir() {
+            name.push('/');
+            String::from("-")
+        } else {
+            meta.len().to_string()
+        };
+        rows.push((name, size));
+    }
+    rows.sort();
+
+    let base = req_path.trim_end_matches('/');
+    let rows = rows
+        .iter()
+        .map(|(name, size)| {
+            // Dir names carry a trailing `/`, which is kept as is in the link.
+            let segment = name.trim_end_matches('/');
+            format!(
+                r#"<tr><td><a href="{base}/{href}{slash}">{name}</a></td><td>{size}</td></tr>"#,
+                base = escape_html(base),
+                href = escape_html(&utf8_percent_encode(segment, PATH_SEGMENT).to_string()),
+                slash = &name[segment.len()..],
+                name = escape_html(name),
+                size = size,
+            )
+        })
+        .collect::<String>();
+    Ok(format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"/><title>Index of {path}</title></head><body><h1>Index of {path}</h1><table><tr><th>Name</th><th>Size (bytes)</th></tr>{rows}</table></body></html>"#,
+        path = escape_html(req_path),
+        rows = rows,
+    ))
+}
+
+/// Escape the given value for safe inclusion in HTML content & attributes.
+fn escape_html(val: &str) -> String {
+    val.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ConfigOpts, ConfigOptsBuild, ConfigOptsServe, ConfigOptsWatch};
+    use crate::test_util::TestDir;
+    use regex::Regex;
+    use tide::http::{Request as HttpRequest, Response as HttpResponse, Url};
+
+    /// Build a server of static files from the given dist dir, to which middleware may be added.
+    fn test_app(dist: &Path) -> tide::Server<State> {
+        let mut app = tide::with_state(State {
+            index: Arc::new(dist.join("index.html")),
+            dist: Arc::new(dist.to_path_buf()),
+            public_url: Arc::new("/".into()),
+        });
+        app.at("/").serve_dir(dist.to_string_lossy().as_ref()).unwrap();
+        app
+    }
+
+    /// Serve a backend app on a free local port, answering `/api/*` with the given name & the
+    /// request path, and with the responses of various special paths, returning its URL.
+    fn spawn_backend(name: &'static str) -> String {
+        let mut backend = tide::new();
+        backend.at("/api/*").all(move |req: tide::Request<()>| async move { Ok(format!("{}:{}", name, req.url().path())) });
+        backend
+            .at("/api/missing")
+            .get(|_| async { Ok(Response::builder(StatusCode::NotFound).body("backend 404").build()) });
+        backend.at("/api/big").get(|_| async { Ok("x".repeat(100)) });
+        backend.at("/api/stream").get(|_| async {
+            let body = Body::from_reader(futures::io::Cursor::new(vec![b'x'; 100]), None);
+            Ok(Response::builder(StatusCode::Ok).body(body).build())
+        });
+        backend.at("/api/hop").get(|_| async {
+            Ok(Response::builder(StatusCode::Ok)
+                .header("keep-alive", "timeout=5")
+                .header("x-custom", "kept")
+                .body("hop")
+                .build())
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        async_std::task::spawn(backend.listen(listener));
+        url
+    }
+
+    /// Build the server app for a dist dir holding an index.html, with the given `Trunk.toml`
+    /// content & serve options.
+    async fn test_serve_app(name: &str, config: &str, serve_opts: ConfigOptsServe) -> (TestDir, tide::Server<State>) {
+        let dist = TestDir::new(name, &[("index.html", "index"), ("Trunk.toml", config)]);
+        let serve_opts = ConfigOptsServe {
+            serve_dir: Some(dist.to_path_buf()),
+            ..serve_opts
+        };
+        let cfg = ConfigOpts::rtc_serve(Default::default(), Default::default(), serve_opts, Some(dist.join("Trunk.toml")), None, false)
+            .await
+            .unwrap();
+        let app = ServeSystem::build_app(&cfg, &ProgressBar::hidden(), None).unwrap();
+        (dist, app)
+    }
+
+    /// Send a `GET` for the given path with the given headers.
+    async fn get(app: &tide::Server<State>, path: &str, headers: &[(&str, &str)]) -> HttpResponse {
+        let mut req = HttpRequest::new(Method::Get, Url::parse("http://localhost").unwrap().join(path).unwrap());
+        req.insert_header(headers::HOST, "localhost");
+        for (name, val) in headers {
+            req.insert_header(*name, *val);
+        }
+        app.respond(req).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn conditional_request_etag_matches_with_304() {
+        let dist = TestDir::new("etag", &[("app.js", "console.log(1);")]);
+        let mut app = test_app(&dist);
+        app.with(ConditionalRequestMiddleware::default());
+
+        let mut res = get(&app, "/app.js", &[]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let etag = res.header(headers::ETAG).expect("expected an etag").last().as_str().to_string();
+        assert!(res.header(headers::LAST_MODIFIED).is_some());
+        assert_eq!(res.body_string().await.unwrap(), "console.log(1);");
+
+        let mut res = get(&app, "/app.js", &[("If-None-Match", &etag)]).await;
+        assert_eq!(res.status(), StatusCode::NotModified);
+        assert_eq!(res.body_string().await.unwrap(), "");
+
+        let mut res = get(&app, "/app.js", &[("If-None-Match", "\"stale\"")]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "console.log(1);");
+    }
+
+    #[async_std::test]
+    async fn directory_listing_enumerates_files_with_sizes() {
+        let dist = TestDir::new("listing", &[("index.html", "index"), ("assets/app.css", "body {}"), ("assets/a b#1?.txt", "12")]);
+        dist.create_dir("assets/img");
+        let mut app = test_app(&dist);
+        app.with(DirectoryListingMiddleware);
+
+        let mut res = get(&app, "/assets/", &[]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        let html = res.body_string().await.unwrap();
+        assert!(html.contains(r#"<a href="/assets/app.css">app.css</a></td><td>7</td>"#), "{}", html);
+        assert!(html.contains(r#"<a href="/assets/a%20b%231%3F.txt">a b#1?.txt</a></td><td>2</td>"#), "{}", html);
+        assert!(html.contains(r#"<a href="/assets/img/">img/</a></td><td>-</td>"#), "{}", html);
+        // Dirs with an index.html are not listed.
+        assert!(!get(&app, "/", &[]).await.body_string().await.unwrap().contains("Index of"));
+    }
+
+    #[async_std::test]
+    async fn directory_listing_is_off_by_default() {
+        let dist = TestDir::new("listing-default", &[("assets/app.css", "body {}")]);
+        let serve_opts = ConfigOptsServe {
+            serve_dir: Some(dist.to_path_buf()),
+            ..Default::default()
+        };
+        let cfg = ConfigOpts::rtc_serve(Default::default(), Default::default(), serve_opts, Some(dist.join("Trunk.toml")), None, false)
+            .await
+            .unwrap();
+        assert!(!cfg.directory_listing);
+        let app = ServeSystem::build_app(&cfg, &ProgressBar::hidden(), None).unwrap();
+        let mut res = get(&app, "/assets/", &[]).await;
+        assert!(!res.body_string().await.unwrap().contains("app.css"));
+    }
+
+    #[async_std::test]
+    async fn directory_listing_never_lists_outside_dist() {
+        let dist = TestDir::new("listing-escape", &[("assets/a b#1?.txt", "")]);
+        let state = State {
+            index: Arc::new(dist.join("index.html")),
+            dist: Arc::new(dist.to_path_buf()),
+            public_url: Arc::new("/".into()),
+        };
+        for path in &["/..", "/assets/../..", "/%2e%2e/", "/assets/%2E%2E/%2e%2e", "/assets/..%2f..%2f", "/assets/%2e%2e%5c"] {
+            assert!(state.resolve_dist_path(path).await.is_none(), "{}", path);
+        }
+        let file = state.resolve_dist_path("/assets/a%20b%231%3F.txt").await.unwrap();
+        assert_eq!(file, AsyncPathBuf::from(dist.join("assets/a b#1?.txt")));
+
+        let mut app = test_app(&dist);
+        app.with(DirectoryListingMiddleware);
+        let outside = TestDir::new("listing-outside", &[("secret.txt", "secret")]);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&*outside, dist.join("link")).unwrap();
+        #[cfg(not(unix))]
+        drop(outside);
+        for path in &["/assets/..%2f..%2f", "/%2e%2e/", "/link/"] {
+            let mut res = get(&app, path, &[]).await;
+            assert!(!res.body_string().await.unwrap().contains("secret"), "{}", path);
+        }
+    }
+
+    #[async_std::test]
+    async fn well_known_files_are_served_verbatim() {
+        let dist = TestDir::new("well-known", &[
+            ("index.html", "index"),
+            (".well-known/apple-app-site-association", "{}"),
+            (".well-known/security.txt", "Contact: mailto:security@example.com"),
+        ]);
+        let mut app = test_app(&dist);
+        app.with(WellKnownMiddleware).with(IndexHtmlMiddleware {
+            proxy_passthrough_404: true,
+        });
+        let content_type = |res: &HttpResponse| res.content_type().map(|mime| mime.essence().to_string());
+
+        let mut res = get(&app, "/.well-known/apple-app-site-association", &[]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(content_type(&res).as_deref(), Some("application/json"));
+        assert_eq!(res.body_string().await.unwrap(), "{}");
+        // Files with an extension keep the content type derived from it.
+        let res = get(&app, "/.well-known/security.txt", &[]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_ne!(content_type(&res).as_deref(), Some("application/json"));
+        // Missing files are not answered with the index.html.
+        let mut res = get(&app, "/.well-known/missing", &[]).await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+        assert_ne!(res.body_string().await.unwrap(), "index");
+    }
+
+    #[async_std::test]
+    async fn header_matched_proxies_take_precedence() {
+        let (default, staging) = (spawn_backend("default"), spawn_backend("staging"));
+        let config = format!(
+            "[[proxy]]\nbackend = \"{}/api/\"\n\n[[proxy]]\nbackend = \"{}/api/\"\nmatch_header = [\"x-env\", \"staging\"]\n",
+            default, staging
+        );
+        let (_dist, app) = test_serve_app("proxy-match-header", &config, Default::default()).await;
+        for (headers, expected) in &[
+            (vec![("x-env", "staging")], "staging:/api/users"),
+            (vec![("x-env", "prod")], "default:/api/users"),
+            (vec![], "default:/api/users"),
+        ] {
+            let mut res = get(&app, "/api/users", headers).await;
+            assert_eq!(res.body_string().await.unwrap(), *expected, "{:?}", headers);
+        }
+    }
+
+    #[async_std::test]
+    async fn proxied_404s_are_relayed_unless_disabled() {
+        let backend = spawn_backend("backend");
+        for (passthrough, status, body) in &[(None, StatusCode::NotFound, "backend 404"), (Some(false), StatusCode::Ok, "index")] {
+            let serve_opts = ConfigOptsServe {
+                proxy_backend: Some(format!("{}/api/", backend).parse().unwrap()),
+                proxy_passthrough_404: *passthrough,
+                ..Default::default()
+            };
+            let (_dist, app) = test_serve_app("proxy-404", "", serve_opts).await;
+            let mut res = get(&app, "/api/missing", &[]).await;
+            assert_eq!(res.status(), *status, "{:?}", passthrough);
+            assert_eq!(res.body_string().await.unwrap(), *body, "{:?}", passthrough);
+            // Missing static files still fall back to the index.html.
+            assert_eq!(get(&app, "/some/route", &[]).await.body_string().await.unwrap(), "index");
+        }
+    }
+
+    #[async_std::test]
+    async fn proxied_bodies_over_the_size_limit_are_rejected() {
+        let serve_opts = ConfigOptsServe {
+            proxy_backend: Some(format!("{}/api/", spawn_backend("backend")).parse().unwrap()),
+            proxy_max_body_bytes: Some(50),
+            ..Default::default()
+        };
+        let (_dist, app) = test_serve_app("proxy-limit", "", serve_opts).await;
+        let mut res = get(&app, "/api/small", &[]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_eq!(res.body_string().await.unwrap(), "backend:/api/small");
+        // Responses which declare an oversized body are rejected up front, and others are aborted
+        // once the limit is exceeded, depending on whether the HTTP client reports the length.
+        for path in &["/api/big", "/api/stream"] {
+            let mut res = get(&app, path, &[]).await;
+            assert!(res.status() == StatusCode::BadGateway || res.body_string().await.is_err(), "{}", path);
+        }
+
+        let mut req = HttpRequest::new(Method::Post, Url::parse("http://localhost/api/upload").unwrap());
+        req.insert_header(headers::HOST, "localhost");
+        req.set_body("x".repeat(100));
+        let res: HttpResponse = app.respond(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PayloadTooLarge);
+    }
+
+    #[async_std::test]
+    async fn hop_by_hop_headers_are_not_relayed() {
+        let serve_opts = ConfigOptsServe {
+            proxy_backend: Some(format!("{}/api/", spawn_backend("backend")).parse().unwrap()),
+            ..Default::default()
+        };
+        let (_dist, app) = test_serve_app("proxy-hop-by-hop", "", serve_opts).await;
+        let mut res = get(&app, "/api/hop", &[]).await;
+        assert_eq!(res.header("x-custom").map(|val| val.last().as_str()), Some("kept"));
+        assert!(res.header("keep-alive").is_none());
+        assert_eq!(res.body_string().await.unwrap(), "hop");
+    }
+
+    #[async_std::test]
+    async fn downloads_have_a_content_disposition() {
+        let dist = TestDir::new("downloads", &[("files/app.zip", "zip"), ("files/notes.txt", "notes"), ("app.pdf", "pdf")]);
+        let mut app = test_app(&dist);
+        app.with(DownloadMiddleware(vec![
+            DownloadRule {
+                pattern: Regex::new("^files/.*\\.zip$").unwrap(),
+                filename: None,
+            },
+            DownloadRule {
+                pattern: Regex::new("^[^/]*\\.pdf$").unwrap(),
+                filename: Some("the \"manual\".pdf".into()),
+            },
+        ]));
+        let disposition = |res: &HttpResponse| res.header("Content-Disposition").map(|val| val.last().as_str().to_string());
+        assert_eq!(disposition(&get(&app, "/files/app.zip", &[]).await).as_deref(), Some("attachment"));
+        assert_eq!(
+            disposition(&get(&app, "/app.pdf", &[]).await).as_deref(),
+            Some(r#"attachment; filename="the \"manual\".pdf""#)
+        );
+        assert_eq!(disposition(&get(&app, "/files/notes.txt", &[]).await), None);
+        // Only successful responses are downloads.
+        let res = get(&app, "/files/missing.zip", &[]).await;
+        assert_eq!(res.status(), StatusCode::NotFound);
+        assert_eq!(disposition(&res), None);
+    }
+
+    #[async_std::test]
+    async fn last_good_dist_is_served_while_failing() {
+        let dist = TestDir::new("last-good", &[("index.html", "good index"), ("app.js", "good app")]);
+        std::fs::create_dir_all(dist.join(".well-known")).unwrap();
+        let state_dir = TestDir::new("last-good-state", &[]);
+        let last_good = Arc::new(LastGoodDist::new(&state_dir, &dist).await.unwrap());
+        last_good.record(&dist, true).await.unwrap();
+        std::fs::write(dist.join("index.html"), "broken index").unwrap();
+        std::fs::write(dist.join("app.js"), "broken app").unwrap();
+        last_good.record(&dist, false).await.unwrap();
+
+        let mut app = test_app(&dist);
+        app.with(LastGoodDistMiddleware(last_good.clone()))
+            .with(WellKnownMiddleware)
+            .with(IndexHtmlMiddleware {
+                proxy_passthrough_404: true,
+            });
+        assert_eq!(get(&app, "/app.js", &[]).await.body_string().await.unwrap(), "good app");
+        assert_eq!(get(&app, "/", &[]).await.body_string().await.unwrap(), "good index");
+        assert_eq!(get(&app, "/some/route", &[]).await.body_string().await.unwrap(), "good index");
+        assert_eq!(get(&app, "/.well-known/missing", &[]).await.status(), StatusCode::NotFound);
+
+        last_good.record(&dist, true).await.unwrap();
+        assert_eq!(get(&app, "/app.js", &[]).await.body_string().await.unwrap(), "broken app");
+        assert!(last_good.dir().starts_with(&*state_dir));
+        last_good.remove().await.unwrap();
+        assert!(!last_good.dir().exists());
+    }
+
+    #[test]
+    fn browser_override_is_used_to_open_the_url() {
+        let url = "http://127.0.0.1:8080/some/route";
+        let cmd = browser_command(url, "/opt/browsers/chrome-debug");
+        assert_eq!(cmd.get_program(), "/opt/browsers/chrome-debug");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), vec![url]);
+
+        let err = open_browser(url, Some("trunk-test-no-such-browser")).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[async_std::test]
+    async fn open_url_has_the_bound_port_and_open_path() {
+        let dist = TestDir::new("open-url", &[("index.html", "index")]);
+        let serve_opts = ConfigOptsServe {
+            address: Some("127.0.0.1".into()),
+            port: Some(0),
+            open_path: Some("/some/route".into()),
+            serve_dir: Some(dist.to_path_buf()),
+            ..Default::default()
+        };
+        let build_opts = ConfigOptsBuild {
+            public_url: Some("/app/".into()),
+            ..Default::default()
+        };
+        let cfg = ConfigOpts::rtc_serve(build_opts, Default::default(), serve_opts, Some(dist.join("Trunk.toml")), None, false)
+            .await
+            .unwrap();
+        let system = ServeSystem::new(cfg, ProgressBar::hidden()).await.unwrap();
+        let addr = system.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(system.open_url(), format!("http://127.0.0.1:{}/app/some/route", addr.port()));
+    }
+
+    #[async_std::test]
+    async fn no_watch_builds_without_a_watch_system() {
+        let project = TestDir::new("no-watch", &[("index.html", "<html></html>")]);
+        for no_watch in &[true, false] {
+            let serve_opts = ConfigOptsServe {
+                port: Some(0),
+                no_watch: *no_watch,
+                ..Default::default()
+            };
+            let build_opts = ConfigOptsBuild {
+                target: Some(project.join("index.html")),
+                dist: Some(project.join("dist")),
+                ..Default::default()
+            };
+            let watch_opts = ConfigOptsWatch {
+                watch_root: Some(project.to_path_buf()),
+                path_deps: Some(false),
+                ..Default::default()
+            };
+            let cfg = ConfigOpts::rtc_serve(build_opts, watch_opts, serve_opts, Some(project.join("Trunk.toml")), None, false)
+                .await
+                .unwrap();
+            let system = ServeSystem::new(cfg, ProgressBar::hidden()).await.unwrap();
+            assert_eq!(system.watch.is_none(), *no_watch);
+            assert_eq!(system.build.is_some(), *no_watch);
+        }
+    }
+
+    #[test]
+    fn unspecified_ipv6_listeners_are_dual_stack() {
+        let listener = match bind_tcp("[::]:0".parse().unwrap(), 128) {
+            Ok(listener) => listener,
+            // IPv6 may be unavailable in the test environment.
+            Err(err) if err.kind() == std::io::ErrorKind::AddrNotAvailable => return,
+            Err(err) => panic!("error binding dual-stack listener: {}", err),
+        };
+        let port = listener.local_addr().unwrap().port();
+        std::net::TcpStream::connect(("127.0.0.1", port)).expect("expected IPv4 connections to be accepted");
+        assert_eq!(url_host("::".parse().unwrap()), "::1".parse::<IpAddr>().unwrap());
+        assert_eq!(url_host("0.0.0.0".parse().unwrap()), "127.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(url_host("fe80::1".parse().unwrap()), "fe80::1".parse::<IpAddr>().unwrap());
+    }
+
+    #[async_std::test]
+    async fn allowed_hosts_rejects_other_hosts_with_403() {
+        let dist = TestDir::new("allowed-hosts", &[("index.html", "index")]);
+        let mut app = test_app(&dist);
+        app.with(AllowedHostsMiddleware(vec!["dev.example.com".into(), "*.example.org".into()]));
+
+        for host in &["localhost:8080", "127.0.0.1", "[::1]:8080", "DEV.example.com:8080", "app.example.org"] {
+            assert_eq!(get(&app, "/index.html", &[("host", host)]).await.status(), StatusCode::Ok, "{}", host);
+        }
+        for host in &["evil.com", "example.org", "dev.example.com.evil.com", "notexample.org"] {
+            assert_eq!(get(&app, "/index.html", &[("host", host)]).await.status(), StatusCode::Forbidden, "{}", host);
+        }
+    }
+
+    #[async_std::test]
+    async fn allowed_hosts_include_the_configured_address() {
+        let dist = TestDir::new("allowed-hosts-address", &[("index.html", "index")]);
+        for (address, host, allowed) in &[
+            ("192.168.1.5", "192.168.1.5:8080", true),
+            ("fe80::1", "[fe80::1]:8080", true),
+            ("0.0.0.0", "0.0.0.0:8080", false),
+            ("192.168.1.5", "192.168.1.6", false),
+        ] {
+            let serve_opts = ConfigOptsServe {
+                address: Some(address.to_string()),
+                allowed_hosts: Some(vec!["dev.example.com".into()]),
+                serve_dir: Some(dist.to_path_buf()),
+                ..Default::default()
+            };
+            let cfg = ConfigOpts::rtc_serve(Default::default(), Default::default(), serve_opts, Some(dist.join("Trunk.toml")), None, false)
+                .await
+                .unwrap();
+            let app = ServeSystem::build_app(&cfg, &ProgressBar::hidden(), None).unwrap();
+            let status = get(&app, "/index.html", &[("host", host)]).await.status();
+            assert_eq!(status == StatusCode::Ok, *allowed, "{} via {}", host, address);
+        }
+    }
+
+    #[async_std::test]
+    async fn basic_auth_requires_credentials() {
+        let dist = TestDir::new("basic-auth", &[("index.html", "<html></html>")]);
+        let mut app = test_app(&dist);
+        app.with(BasicAuthMiddleware::new("user:pass"));
+
+        let res = get(&app, "/index.html", &[]).await;
+        assert_eq!(res.status(), StatusCode::Unauthorized);
+        assert!(res.header(headers::WWW_AUTHENTICATE).is_some());
+        let wrong = format!("Basic {}", base64::encode("user:wrong"));
+        assert_eq!(get(&app, "/index.html", &[("Authorization", &wrong)]).await.status(), StatusCode::Unauthorized);
+        let right = format!("Basic {}", base64::encode("user:pass"));
+        assert_eq!(get(&app, "/index.html", &[("Authorization", &right)]).await.status(), StatusCode::Ok);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_is_replaced_when_stale_and_removed_on_drop() {
+        let dir = TestDir::new("unix-socket", &[("file.txt", "")]);
+        let socket = dir.join("trunk.sock");
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        assert!(socket.exists(), "a dropped listener leaves its socket behind");
+
+        let guard = prepare_unix_socket(&socket).unwrap();
+        assert!(!socket.exists(), "the stale socket should be removed");
+        std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        drop(guard);
+        assert!(!socket.exists(), "the socket should be removed once the guard is dropped");
+
+        assert!(prepare_unix_socket(&dir.join("file.txt")).is_err(), "non-socket files must not be replaced");
+    }
+
+    #[async_std::test]
+    async fn etag_is_kept_when_identical_content_is_rewritten() {
+        let dist = TestDir::new("etag-rewrite", &[("app.js", "console.log(1);")]);
+        let mut app = test_app(&dist);
+        app.with(ConditionalRequestMiddleware::default());
+        let etag = |res: &HttpResponse| res.header(headers::ETAG).unwrap().last().as_str().to_string();
+        let first = etag(&get(&app, "/app.js", &[]).await);
+
+        // Rewrite the same bytes with a later modification time, as a rebuild does.
+        let path = dist.write("app.js", "console.log(1);");
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        let res = get(&app, "/app.js", &[("If-None-Match", &first)]).await;
+        assert_eq!(res.status(), StatusCode::NotModified);
+        assert_eq!(etag(&res), first);
+
+        dist.write("app.js", "console.log(2);");
+        let res = get(&app, "/app.js", &[("If-None-Match", &first)]).await;
+        assert_eq!(res.status(), StatusCode::Ok);
+        assert_ne!(etag(&res), first);
+    }
+}
@@ -0,0 +1,67 @@
+//! Fixtures shared by the unit tests of the various modules.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A counter keeping the dirs of tests which use the same name unique within a test run.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh dir under the system temp dir, which is removed along with its contents on drop.
+///
+/// The path is canonical, so it may be compared against the canonical paths produced by the
+/// config layers & pipelines.
+pub struct TestDir(PathBuf);
+
+impl TestDir {
+    /// Create a fresh dir for the test of the given name, holding the given files.
+    ///
+    /// File paths are relative to the dir, and any missing parent dirs are created.
+    pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let dir = std::env::temp_dir().join(format!("trunk-test-{}-{}-{}", name, std::process::id(), id));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = Self(dir.canonicalize().unwrap());
+        for (path, content) in files {
+            dir.write(path, content);
+        }
+        dir
+    }
+
+    /// Write the given content to the file at the given path relative to this dir, creating any
+    /// missing parent dirs.
+    pub fn write(&self, path: &str, content: &str) -> PathBuf {
+        let path = self.0.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    /// Create the dir at the given path relative to this dir, along with any missing parents.
+    pub fn create_dir(&self, path: &str) -> PathBuf {
+        let path = self.0.join(path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -1,15 +1,20 @@
-use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Context, Result};
 use async_std::task::{spawn_blocking, JoinHandle};
-use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::channel::mpsc::{channel, Receiver};
+use futures::future::BoxFuture;
 use futures::prelude::*;
 use indicatif::ProgressBar;
-use notify::{watcher, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{DebouncedEvent, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
 
-use crate::build::BuildSystem;
-use crate::config::RtcWatch;
+use crate::build::{dist_state_file, BuildSystem};
+use crate::common::copy_dir_recursive;
+use crate::config::{CargoMetadata, RtcWatch};
 
 /// A watch system wrapping a build system and a watcher.
 pub struct WatchSystem {
@@ -19,19 +24,43 @@ pub struct WatchSystem {
     build: BuildSystem,
     /// The current vector of paths to be ignored.
     ignores: Vec<PathBuf>,
-    /// A channel of FS watch events.
-    watch_rx: Receiver<DebouncedEvent>,
     /// A channel of new paths to ignore from the build system.
     build_rx: Receiver<PathBuf>,
-    /// The watch system used for watching the filesystem.
-    _watcher: (JoinHandle<()>, RecommendedWatcher),
+    /// The watcher used for watching the filesystem.
+    watcher: Box<dyn FsWatcher>,
+    /// The dirs watched non-recursively, as they contain ignored paths.
+    partially_watched: Vec<PathBuf>,
+    /// The maximum number of entries to retain in `history`.
+    history_size: usize,
+    /// A ring buffer of the most recent rebuilds, oldest first.
+    history: VecDeque<BuildRecord>,
+    /// The base directory being watched for changes.
+    watch_root: PathBuf,
+    /// Whether changes reached through symlinks should trigger builds.
+    follow_symlinks: bool,
+    /// Whether changes to the temp & swap files of common editors should be ignored.
+    ignore_editor_temp: bool,
+    /// Whether changes to hidden files & dirs should be ignored.
+    ignore_hidden: bool,
+    /// The dist dir being built into.
+    dist: PathBuf,
+    /// An optional snapshot of the last successful build's dist dir, updated after each build.
+    last_good: Option<Arc<LastGoodDist>>,
 }
 
 impl WatchSystem {
-    /// Create a new instance.
+    /// Create a new instance, watching the filesystem via the `notify` crate.
     pub async fn new(cfg: Arc<RtcWatch>, progress: ProgressBar) -> Result<Self> {
+        let watcher: Box<dyn FsWatcher> = match cfg.poll {
+            Some(interval) => Box::new(NotifyWatcher::<PollWatcher>::new(interval)?),
+            None => Box::new(NotifyWatcher::<RecommendedWatcher>::new(Duration::from_secs(1))?),
+        };
+        Self::with_watcher(cfg, progress, watcher).await
+    }
+
+    /// Create a new instance, driven by the events of the given watcher.
+    pub async fn with_watcher(cfg: Arc<RtcWatch>, progress: ProgressBar, mut watcher: Box<dyn FsWatcher>) -> Result<Self> {
         // Create a channel for being able to listen for new paths to ignore while running.
-        let (watch_tx, watch_rx) = channel(1);
         let (build_tx, build_rx) = channel(1);
 
         // Process ignore list.
@@ -41,28 +70,71 @@ impl WatchSystem {
             Ok(acc)
         })?;
         ignores.append(&mut vec![cfg.build.dist.clone()]);
+        // In poll mode, every watched file is stat'ed on each poll, so skip the poll-specific
+        // paths as well, such as large build output dirs.
+        if cfg.poll.is_some() {
+            ignores.extend(cfg.poll_ignore.iter().cloned());
+        }
 
-        // Build the watcher.
-        let _watcher = build_watcher(watch_tx)?;
+        // Watch the watch root, skipping ignored paths.
+        let mut partially_watched = vec![];
+        watch_dir(watcher.as_mut(), &cfg.watch_root, &ignores, &mut partially_watched)?;
 
-        // Build dependencies.
+        // Build dependencies, ignoring the state written by the build system itself.
         let build = BuildSystem::new(cfg.build.clone(), progress.clone(), Some(build_tx)).await?;
+        ignores.push(build.state_dir().to_path_buf());
+
+        // Watch the sources of any local path dependencies not already covered by the watch root.
+        if cfg.path_deps {
+            for dir in path_dep_dirs(&build, &cfg.watch_root, &ignores).await? {
+                watcher
+                    .watch(&dir, RecursiveMode::Recursive)
+                    .with_context(|| format!("failed to watch path dependency {:?} for file system changes", &dir))?;
+            }
+        }
         Ok(Self {
             progress,
             build,
             ignores,
-            watch_rx,
             build_rx,
-            _watcher,
+            watcher,
+            partially_watched,
+            history_size: cfg.rebuild_history,
+            history: VecDeque::with_capacity(cfg.rebuild_history),
+            watch_root: cfg.watch_root.clone(),
+            follow_symlinks: cfg.follow_symlinks,
+            ignore_editor_temp: cfg.ignore_editor_temp,
+            ignore_hidden: cfg.ignore_hidden,
+            dist: cfg.build.dist.clone(),
+            last_good: None,
         })
     }
 
+    /// Keep a snapshot of the last successful build's dist dir up to date, returning it.
+    pub async fn enable_last_good(&mut self) -> Result<Arc<LastGoodDist>> {
+        let last_good = Arc::new(LastGoodDist::new(self.build.state_dir(), &self.dist).await?);
+        self.last_good = Some(last_good.clone());
+        Ok(last_good)
+    }
+
     /// Run a build.
-    pub async fn build(&mut self) {
-        if let Err(err) = self.build.build().await {
+    ///
+    /// Any build error is reported by this system before being returned, so callers need only
+    /// use the returned value to decide how to proceed.
+    pub async fn build(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let res = self.build.build().await;
+        self.record_build(start.elapsed(), res.is_ok());
+        if let Err(err) = &res {
             // NOTE WELL: we use debug formatting here to ensure the error chain is displayed.
             self.progress.println(format!("{:?}", err));
         }
+        if let Some(last_good) = &self.last_good {
+            if let Err(err) = last_good.record(&self.dist, res.is_ok()).await {
+                self.progress.println(format!("{:?}", err));
+            }
+        }
+        res
     }
 
     /// Run the watch system, responding to events and triggering builds.
@@ -72,7 +144,7 @@ impl WatchSystem {
                 ign_res = self.build_rx.next() => if let Some(ign) = ign_res {
                     self.update_ignore_list(ign);
                 },
-                ev_res = self.watch_rx.next() => if let Some(ev) = ev_res {
+                ev_res = self.watcher.next_event().fuse() => if let Some(ev) = ev_res {
                     self.handle_watch_event(ev).await;
                 },
             }
@@ -89,9 +161,49 @@ impl WatchSystem {
                 return; // Don't emit a notification if ignored.
             }
         }
-        if let Err(err) = self.build.build().await {
-            self.progress.println(format!("{}", err));
+        if self.ignore_editor_temp && is_editor_temp_file(&ev_path) {
+            return;
+        }
+        if self.ignore_hidden && is_hidden(&ev_path, &self.watch_root) {
+            return;
         }
+        if !self.follow_symlinks && is_via_symlink(&ev_path, &self.watch_root) {
+            return;
+        }
+        // Dirs created within a non-recursively watched dir are not covered by any watch yet.
+        let parent = ev_path.parent();
+        if ev_path.is_dir() && self.partially_watched.iter().any(|dir| Some(dir.as_path()) == parent) {
+            if let Err(err) = watch_dir(self.watcher.as_mut(), &ev_path, &self.ignores, &mut self.partially_watched) {
+                self.progress.println(format!("{:?}", err));
+            }
+        }
+        let _ = self.build().await;
+        self.report_history();
+    }
+
+    /// Record the outcome of a build in the rebuild history, evicting the oldest entry as needed.
+    fn record_build(&mut self, duration: Duration, success: bool) {
+        if self.history_size == 0 {
+            return;
+        }
+        if self.history.len() == self.history_size {
+            self.history.pop_front();
+        }
+        self.history.push_back(BuildRecord { duration, success });
+    }
+
+    /// Report the timings & outcomes of the retained rebuild history.
+    fn report_history(&self) {
+        if self.history.is_empty() {
+            return;
+        }
+        let entries = self
+            .history
+            .iter()
+            .map(|record| format!("{:.2}s {}", record.duration.as_secs_f64(), if record.success { "ok" } else { "error" }))
+            .collect::<Vec<_>>();
+        self.progress
+            .println(format!("rebuild history (last {}): {}", entries.len(), entries.join(", ")));
     }
 
     fn update_ignore_list(&mut self, path: PathBuf) {
@@ -101,16 +213,403 @@ impl WatchSystem {
     }
 }
 
-fn build_watcher(mut watch_tx: Sender<DebouncedEvent>) -> Result<(JoinHandle<()>, RecommendedWatcher)> {
-    let (tx, rx) = std::sync::mpsc::channel();
-    let mut watcher = watcher(tx, std::time::Duration::from_secs(1)).context("failed to build file system watcher")?;
-    watcher
-        .watch(".", RecursiveMode::Recursive)
-        .context("failed to watch CWD for file system changes")?;
-    let handle = spawn_blocking(move || loop {
-        if let Ok(event) = rx.recv() {
-            let _ = watch_tx.try_send(event);
-        }
-    });
-    Ok((handle, watcher))
+/// A snapshot of the dist dir as of the last successful build, which may be served in place of
+/// the dist dir while rebuilds are failing.
+///
+/// The snapshot lives in the build's state dir, which is private to the project & ignored by the
+/// watcher, so that updating it never triggers a rebuild.
+pub struct LastGoodDist {
+    /// The dir holding the snapshot.
+    dir: PathBuf,
+    /// Whether the most recent build failed after a snapshot was taken.
+    failing: AtomicBool,
+}
+
+impl LastGoodDist {
+    /// Create a new instance for the given dist dir, snapshotting it within the given state dir.
+    pub async fn new(state_dir: &Path, dist: &Path) -> Result<Self> {
+        let dir = state_dir.join(dist_state_file(dist, "last-good", "snapshot").await?);
+        Ok(Self {
+            dir,
+            failing: AtomicBool::new(false),
+        })
+    }
+
+    /// The dir holding the snapshot.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether the snapshot should currently be served in place of the dist dir.
+    pub fn is_failing(&self) -> bool {
+        self.failing.load(Ordering::SeqCst)
+    }
+
+    /// Record the outcome of a build, snapshotting the given dist dir after a successful build.
+    pub(crate) async fn record(&self, dist: &Path, success: bool) -> Result<()> {
+        if !success {
+            // Only fall back once there is a snapshot to fall back to.
+            self.failing.store(self.dir.exists(), Ordering::SeqCst);
+            return Ok(());
+        }
+        if self.dir.exists() {
+            async_std::fs::remove_dir_all(&self.dir)
+                .await
+                .with_context(|| format!("error removing stale dist snapshot {:?}", &self.dir))?;
+        }
+        copy_dir_recursive(dist.to_path_buf(), self.dir.clone(), true)
+            .await
+            .context("error snapshotting dist dir of successful build")?;
+        self.failing.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Remove the snapshot, once it is no longer going to be served.
+    pub(crate) async fn remove(&self) -> Result<()> {
+        self.failing.store(false, Ordering::SeqCst);
+        if self.dir.exists() {
+            async_std::fs::remove_dir_all(&self.dir)
+                .await
+                .with_context(|| format!("error removing dist snapshot {:?}", &self.dir))?;
+        }
+        Ok(())
+    }
+}
+
+/// A record of a single build run by the watch system.
+struct BuildRecord {
+    /// The wall-clock duration of the build.
+    duration: Duration,
+    /// Whether the build succeeded.
+    success: bool,
+}
+
+/// Check whether the given path is reached through a symlink below the given watch root.
+fn is_via_symlink(path: &Path, root: &Path) -> bool {
+    path.ancestors()
+        .take_while(|ancestor| *ancestor != root)
+        .any(|ancestor| ancestor.symlink_metadata().map(|meta| meta.file_type().is_symlink()).unwrap_or(false))
+}
+
+/// Check whether the given path is a temp or swap file written by a common editor.
+///
+/// This covers Vim swap & write-test files, Emacs lock & auto-save files, backup files ending
+/// in `~`, and JetBrains safe-write temp files.
+fn is_editor_temp_file(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    const SUFFIXES: &[&str] = &[".swp", ".swo", ".swx", "~", "___jb_tmp___", "___jb_old___"];
+    SUFFIXES.iter().any(|suffix| name.ends_with(suffix))
+        || name.starts_with(".#")
+        || (name.len() > 1 && name.starts_with('#') && name.ends_with('#'))
+        || name == "4913"
+}
+
+/// Check whether the given path is a hidden file or is within a hidden dir, where hidden names
+/// start with a `.`.
+///
+/// Only the components below the given watch root are considered, so that a project which itself
+/// lives within a hidden dir is still watched. Paths outside of the watch root, such as those of
+/// path dependencies, only have their file name checked.
+fn is_hidden(path: &Path, root: &Path) -> bool {
+    let is_hidden_name = |name: &std::ffi::OsStr| name.to_string_lossy().starts_with('.');
+    match path.strip_prefix(root) {
+        Ok(rel) => rel.iter().any(is_hidden_name),
+        Err(_) => path.file_name().map(is_hidden_name).unwrap_or(false),
+    }
+}
+
+/// Register the given dir with the watcher, without registering any of the given ignored paths.
+///
+/// Dirs which contain no ignored paths are watched recursively. Those which do are watched
+/// non-recursively & recorded as partially watched, with each of their subdirs registered in turn,
+/// so that the watcher never has to scan ignored trees.
+fn watch_dir(watcher: &mut dyn FsWatcher, dir: &Path, ignores: &[PathBuf], partially_watched: &mut Vec<PathBuf>) -> Result<()> {
+    if ignores.iter().any(|ignore| dir.starts_with(ignore)) {
+        return Ok(());
+    }
+    if !ignores.iter().any(|ignore| ignore.starts_with(dir)) {
+        return watcher.watch(dir, RecursiveMode::Recursive);
+    }
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    partially_watched.push(dir.to_path_buf());
+    let entries = std::fs::read_dir(dir).with_context(|| format!("error reading dir {:?} to watch for changes", dir))?;
+    for entry in entries {
+        let path = entry.with_context(|| format!("error reading dir {:?} to watch for changes", dir))?.path();
+        if path.is_dir() {
+            watch_dir(watcher, &path, ignores, partially_watched)?;
+        }
+    }
+    Ok(())
+}
+
+/// Find the source dirs of the Rust app's local path dependencies which are neither under the
+/// given watch root nor ignored.
+async fn path_dep_dirs(build: &BuildSystem, watch_root: &Path, ignores: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut dirs: Vec<PathBuf> = vec![];
+    for plan in build.plan().await?.into_iter().filter(|plan| plan.asset_type == "rust") {
+        let manifest = CargoMetadata::new(&plan.input).await?;
+        // Path dependencies & workspace members are the only packages without a source.
+        for package in manifest.metadata.packages.iter().filter(|package| package.source.is_none()) {
+            let dir = match package.manifest_path.parent().map(|dir| dir.join("src").canonicalize()) {
+                Some(Ok(dir)) => dir,
+                _ => continue,
+            };
+            if dir.starts_with(watch_root) || ignores.iter().any(|ignore| dir.starts_with(ignore)) || dirs.contains(&dir) {
+                continue;
+            }
+            dirs.push(dir);
+        }
+    }
+    Ok(dirs)
+}
+
+/// A source of file system change events which drives the watch system.
+///
+/// This abstracts over the `notify` crate, so that the watch system's event handling may also be
+/// driven by other sources of events, such as synthetic events fed in by hand.
+pub trait FsWatcher {
+    /// Watch the given path for changes, along with its descendants when recursive.
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()>;
+
+    /// Wait for the next change event, which is `None` once no more events will be produced.
+    fn next_event(&mut self) -> BoxFuture<'_, Option<DebouncedEvent>>;
+}
+
+/// The default `FsWatcher`, backed by a debounced watcher of the `notify` crate, which is either
+/// the platform's native watcher or a polling watcher.
+pub struct NotifyWatcher<W: Watcher> {
+    /// The underlying watcher, which stops watching when dropped.
+    watcher: W,
+    /// A channel of the events forwarded from the watcher's blocking channel.
+    events: Receiver<DebouncedEvent>,
+    /// The task forwarding events from the watcher's blocking channel.
+    _forwarder: JoinHandle<()>,
+}
+
+impl<W: Watcher> NotifyWatcher<W> {
+    /// Create a new instance, which is not yet watching any paths, debouncing events on the given
+    /// interval, which is also the interval between polls for a polling watcher.
+    pub fn new(delay: Duration) -> Result<Self> {
+        let (mut events_tx, events) = channel(1);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watcher = W::new(tx, delay).context("failed to build file system watcher")?;
+        let _forwarder = spawn_blocking(move || loop {
+            if let Ok(event) = rx.recv() {
+                let _ = events_tx.try_send(event);
+            }
+        });
+        Ok(Self {
+            watcher,
+            events,
+            _forwarder,
+        })
+    }
+}
+
+impl<W: Watcher> FsWatcher for NotifyWatcher<W> {
+    fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()> {
+        self.watcher
+            .watch(path, mode)
+            .with_context(|| format!("failed to watch {:?} for file system changes", path))
+    }
+
+    fn next_event(&mut self) -> BoxFuture<'_, Option<DebouncedEvent>> {
+        self.events.next().boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use futures::channel::mpsc::Sender;
+
+    use super::*;
+    use crate::config::{ConfigOpts, ConfigOptsBuild, ConfigOptsWatch};
+    use crate::test_util::TestDir;
+
+    /// The paths registered with a `MockWatcher`, along with their recursive modes.
+    type Watched = Arc<Mutex<Vec<(PathBuf, RecursiveMode)>>>;
+
+    /// An in-memory `FsWatcher`, which records the paths it is asked to watch & produces the
+    /// events sent to it by hand.
+    struct MockWatcher {
+        watched: Watched,
+        events: Receiver<DebouncedEvent>,
+    }
+
+    impl MockWatcher {
+        fn new() -> (Self, Watched, Sender<DebouncedEvent>) {
+            let watched = Watched::default();
+            let (events_tx, events) = channel(16);
+            let watcher = Self {
+                watched: watched.clone(),
+                events,
+            };
+            (watcher, watched, events_tx)
+        }
+    }
+
+    impl FsWatcher for MockWatcher {
+        fn watch(&mut self, path: &Path, mode: RecursiveMode) -> Result<()> {
+            self.watched.lock().unwrap().push((path.to_path_buf(), mode));
+            Ok(())
+        }
+
+        fn next_event(&mut self) -> BoxFuture<'_, Option<DebouncedEvent>> {
+            self.events.next().boxed()
+        }
+    }
+
+    /// Create a fresh project dir holding a source HTML file, a `src` dir & the given dirs.
+    fn test_project(name: &str, dirs: &[&str]) -> TestDir {
+        let root = TestDir::new(name, &[("index.html", "<html><head></head><body></body></html>")]);
+        for dir in Some(&"src").into_iter().chain(dirs) {
+            root.create_dir(dir);
+        }
+        root
+    }
+
+    /// Create a watch system for the given project dir, driven by a `MockWatcher`.
+    async fn test_watch_system(root: &Path, opts: ConfigOptsWatch) -> (WatchSystem, Watched, Sender<DebouncedEvent>) {
+        let build_opts = ConfigOptsBuild {
+            target: Some(root.join("index.html")),
+            dist: Some(root.join("dist")),
+            ..Default::default()
+        };
+        let opts = ConfigOptsWatch {
+            watch_root: Some(opts.watch_root.unwrap_or_else(|| root.to_path_buf())),
+            rebuild_history: Some(16),
+            path_deps: Some(false),
+            ..opts
+        };
+        let cfg = ConfigOpts::rtc_watch(build_opts, opts, Some(root.join("Trunk.toml")), None, false)
+            .await
+            .unwrap();
+        let (watcher, watched, events) = MockWatcher::new();
+        let system = WatchSystem::with_watcher(cfg, ProgressBar::hidden(), Box::new(watcher)).await.unwrap();
+        (system, watched, events)
+    }
+
+    /// Feed a write of each of the given paths through the watcher of the given watch system,
+    /// returning the number of builds triggered.
+    async fn builds_triggered(system: &mut WatchSystem, events: &mut Sender<DebouncedEvent>, paths: &[PathBuf]) -> usize {
+        let before = system.history.len();
+        for path in paths {
+            events.send(DebouncedEvent::Write(path.clone())).await.unwrap();
+            let event = system.watcher.next_event().await.unwrap();
+            system.handle_watch_event(event).await;
+        }
+        system.history.len() - before
+    }
+
+    #[async_std::test]
+    async fn ignored_dirs_are_not_registered_with_the_watcher() {
+        let root = test_project("registration", &["dist", "node_modules/pkg", "assets/img"]);
+        let opts = ConfigOptsWatch {
+            ignore: Some(vec![root.join("node_modules")]),
+            ..Default::default()
+        };
+        let (mut system, watched, _) = test_watch_system(&root, opts).await;
+        let mut registered = watched.lock().unwrap().clone();
+        registered.sort();
+        assert_eq!(registered, vec![
+            (root.to_path_buf(), RecursiveMode::NonRecursive),
+            (root.join("assets"), RecursiveMode::Recursive),
+            (root.join("src"), RecursiveMode::Recursive),
+        ]);
+
+        // New dirs within a partially watched dir are registered as they are created.
+        std::fs::create_dir_all(root.join("styles")).unwrap();
+        system.handle_watch_event(DebouncedEvent::Create(root.join("styles"))).await;
+        assert!(watched.lock().unwrap().contains(&(root.join("styles"), RecursiveMode::Recursive)));
+    }
+
+    #[async_std::test]
+    async fn poll_ignored_paths_are_not_polled_in_poll_mode() {
+        let root = test_project("poll-ignore", &["target/debug", "vendor/pkg", "assets"]);
+        let poll_ignored = vec![root.join("target/debug/app.wasm"), root.join("vendor/pkg/lib.rs")];
+        let opts = ConfigOptsWatch {
+            poll: Some(100),
+            poll_ignore_extra: Some(vec!["target/".into(), "vendor".into()]),
+            ..Default::default()
+        };
+        let (mut system, watched, mut events) = test_watch_system(&root, opts).await;
+        let mut registered = watched.lock().unwrap().clone();
+        registered.sort();
+        assert_eq!(registered, vec![
+            (root.to_path_buf(), RecursiveMode::NonRecursive),
+            (root.join("assets"), RecursiveMode::Recursive),
+            (root.join("src"), RecursiveMode::Recursive),
+        ]);
+        assert_eq!(builds_triggered(&mut system, &mut events, &poll_ignored).await, 0);
+        assert_eq!(builds_triggered(&mut system, &mut events, &[root.join("src/main.rs")]).await, 1);
+
+        // The poll-specific paths are only ignored in poll mode.
+        let opts = ConfigOptsWatch {
+            poll_ignore_extra: Some(vec!["target/".into(), "vendor".into()]),
+            ..Default::default()
+        };
+        let (mut system, _, mut events) = test_watch_system(&root, opts).await;
+        assert_eq!(builds_triggered(&mut system, &mut events, &poll_ignored).await, 2);
+    }
+
+    #[test]
+    fn editor_temp_files_are_recognized() {
+        for name in &["main.rs.swp", ".main.rs.swo", "main.rs~", ".#main.rs", "#main.rs#", "4913", "main.rs___jb_tmp___"] {
+            assert!(is_editor_temp_file(Path::new(name)), "{}", name);
+        }
+        for name in &["main.rs", "#", "swp", "index.html"] {
+            assert!(!is_editor_temp_file(Path::new(name)), "{}", name);
+        }
+    }
+
+    #[async_std::test]
+    async fn editor_temp_files_do_not_trigger_builds() {
+        let root = test_project("editor-temp", &[]);
+        let temp_files = vec![root.join("src/main.rs.swp"), root.join("src/main.rs~"), root.join("4913")];
+        let (mut system, _, mut events) = test_watch_system(&root, Default::default()).await;
+        assert_eq!(builds_triggered(&mut system, &mut events, &temp_files).await, 0);
+        assert_eq!(builds_triggered(&mut system, &mut events, &[root.join("src/main.rs")]).await, 1);
+
+        let opts = ConfigOptsWatch {
+            ignore_editor_temp: Some(false),
+            ..Default::default()
+        };
+        let (mut system, _, mut events) = test_watch_system(&root, opts).await;
+        assert_eq!(builds_triggered(&mut system, &mut events, &temp_files).await, 3);
+    }
+
+    #[async_std::test]
+    async fn hidden_files_do_not_trigger_builds() {
+        let root = test_project("hidden", &[".git"]);
+        let hidden = vec![root.join(".git/index"), root.join("src/.env")];
+        let (mut system, _, mut events) = test_watch_system(&root, Default::default()).await;
+        assert_eq!(builds_triggered(&mut system, &mut events, &hidden).await, 0);
+
+        let opts = ConfigOptsWatch {
+            ignore_hidden: Some(false),
+            ..Default::default()
+        };
+        let (mut system, _, mut events) = test_watch_system(&root, opts).await;
+        assert_eq!(builds_triggered(&mut system, &mut events, &hidden).await, 2);
+    }
+
+    #[async_std::test]
+    async fn watch_root_is_registered_and_scopes_hidden_paths() {
+        let root = test_project("watch-root", &[".config/app"]);
+        let watch_root = root.join(".config");
+        let opts = ConfigOptsWatch {
+            watch_root: Some(watch_root.clone()),
+            ..Default::default()
+        };
+        let (mut system, watched, mut events) = test_watch_system(&root, opts).await;
+        assert_eq!(*watched.lock().unwrap(), vec![(watch_root.clone(), RecursiveMode::Recursive)]);
+        // Only components below the watch root are checked for being hidden.
+        assert_eq!(builds_triggered(&mut system, &mut events, &[watch_root.join("app/main.rs")]).await, 1);
+        assert_eq!(builds_triggered(&mut system, &mut events, &[root.join("dist/index.html")]).await, 0);
+    }
 }